@@ -46,6 +46,25 @@ pub fn set_drive_folder(folder: String) -> Result<(), String> {
     Ok(())
 }
 
+/// Forgets the pinned Drive folder of a destination, so the next sync finds
+/// or creates it by path again. Needed after the folder was trashed.
+#[tauri::command]
+pub fn reset_drive_folder(destination: Option<String>) -> Result<(), String> {
+    crate::drive_sync::DriveSync::forget_backup_folder(&destination_name(destination))
+        .map_err(|e| e.to_string())
+}
+
+/// Sets the Shared Drive (ID or name) of the default destination; empty
+/// goes back to My Drive.
+#[tauri::command]
+pub fn set_shared_drive(drive: String) -> Result<(), String> {
+    crate::config::update_config(|config| {
+        config.shared_drive = Some(drive).filter(|drive| !drive.is_empty());
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
 #[tauri::command]
 pub fn set_sync_interval(interval: u64) -> Result<(), String> {
     crate::config::update_config(|config| {
@@ -65,23 +84,274 @@ pub fn set_auto_sync(enabled: bool) -> Result<(), String> {
 }
 
 #[tauri::command]
-pub fn get_tracked_paths() -> Result<Vec<String>, String> {
+pub fn set_sync_mode(mode: crate::config::SyncMode) -> Result<(), String> {
+    crate::config::update_config(|config| {
+        config.sync_mode = Some(mode);
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_deletion_policy(policy: crate::config::DeletionPolicy) -> Result<(), String> {
+    crate::config::update_config(|config| {
+        config.deletion_policy = Some(policy);
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_upload_versions(enabled: bool) -> Result<(), String> {
+    crate::config::update_config(|config| {
+        config.upload_versions = Some(enabled);
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_encryption(
+    mode: crate::config::EncryptionMode,
+    key_file: Option<String>,
+) -> Result<(), String> {
+    let mut previous_mode = crate::config::EncryptionMode::Off;
+    let mut changed = false;
+    crate::config::update_config(|config| {
+        previous_mode = config.encryption.unwrap_or_default();
+        changed = previous_mode != mode
+            || (mode == crate::config::EncryptionMode::KeyFile && key_file.is_some() && config.encryption_key_file != key_file);
+        config.encryption = Some(mode);
+        if key_file.is_some() {
+            config.encryption_key_file = key_file;
+        }
+    })
+    .map_err(|e| e.to_string())?;
+
+    if changed {
+        encryption_key_changed(previous_mode == crate::config::EncryptionMode::Off)?;
+    }
+    Ok(())
+}
+
+/// Uploads everything again on the next sync, so nothing stays behind under
+/// the old key or unencrypted. When encryption was off until now, the
+/// plaintext copies are also queued for deletion.
+fn encryption_key_changed(was_off: bool) -> Result<(), String> {
+    let config = crate::config::load_config()
+        .map_err(|e| e.to_string())?;
+    for destination in config.destinations() {
+        if was_off {
+            crate::sync_engine::remember_plaintext_uploads(&destination.name)
+                .map_err(|e| e.to_string())?;
+        }
+        crate::sync_engine::forget_uploads(&destination.name)
+            .map_err(|e| e.to_string())?;
+    }
+    crate::logger::log_info("Encryption settings changed; everything will be uploaded again on the next sync");
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_encryption_passphrase(passphrase: String) -> Result<(), String> {
+    crate::crypto::set_passphrase(&passphrase)
+        .map_err(|e| e.to_string())?;
+    crate::logger::log_info("Encryption passphrase updated");
+
+    let mode = crate::config::load_config()
+        .map_err(|e| e.to_string())?
+        .encryption
+        .unwrap_or_default();
+    if mode == crate::config::EncryptionMode::Passphrase {
+        encryption_key_changed(false)?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn generate_encryption_key_file(path: String) -> Result<(), String> {
+    crate::crypto::generate_key_file(&PathBuf::from(&path))
+        .map_err(|e| e.to_string())?;
+    crate::logger::log_info(&format!("Generated encryption key file {}", path));
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_retention_policy(policy: crate::config::RetentionPolicy) -> Result<(), String> {
+    crate::config::update_config(|config| {
+        config.retention = Some(policy);
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// The destination a command acts on: the one named, or the default one.
+fn destination_name(destination: Option<String>) -> String {
+    destination
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| crate::config::DEFAULT_DESTINATION.to_string())
+}
+
+#[derive(Serialize)]
+pub struct DestinationInfo {
+    name: String,
+    drive_folder: Option<String>,
+    client_id: Option<String>,
+    shared_drive: Option<String>,
+    storage: crate::config::StorageConfig,
+    authenticated: bool,
+    tracked_paths: usize,
+}
+
+#[tauri::command]
+pub fn get_destinations() -> Result<Vec<DestinationInfo>, String> {
+    let config = crate::config::load_config()
+        .map_err(|e| e.to_string())?;
+    let tracked = crate::file_tracker::get_tracked_paths()
+        .map_err(|e| e.to_string())?;
+
+    Ok(config.destinations()
+        .into_iter()
+        .map(|destination| DestinationInfo {
+            authenticated: crate::drive_sync::DriveSync::is_authenticated(&destination.name),
+            tracked_paths: tracked.iter().filter(|path| path.destination == destination.name).count(),
+            name: destination.name,
+            drive_folder: destination.drive_folder,
+            client_id: destination.client_id,
+            shared_drive: destination.shared_drive,
+            storage: destination.storage.unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Adds a destination, or updates the folder, client ID and Shared Drive of
+/// an existing one.
+#[tauri::command]
+pub fn save_destination(
+    name: String,
+    drive_folder: Option<String>,
+    client_id: Option<String>,
+    shared_drive: Option<String>,
+) -> Result<(), String> {
+    crate::config::Destination::validate_name(&name)
+        .map_err(|e| e.to_string())?;
+    if name == crate::config::DEFAULT_DESTINATION {
+        return Err("The default destination is configured in the main settings".to_string());
+    }
+
+    let destination = crate::config::Destination {
+        name,
+        drive_folder: drive_folder.filter(|folder| !folder.is_empty()),
+        client_id: client_id.filter(|id| !id.is_empty()),
+        shared_drive: shared_drive.filter(|drive| !drive.is_empty()),
+        storage: None,
+    };
+    crate::config::update_config(|config| {
+        let destinations = config.destinations.get_or_insert_with(Vec::new);
+        match destinations.iter_mut().find(|existing| existing.name == destination.name) {
+            Some(existing) => *existing = crate::config::Destination {
+                storage: existing.storage.take(),
+                ..destination
+            },
+            None => destinations.push(destination),
+        }
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Removes a destination and its stored credentials. Its tracked paths have
+/// to be moved or removed first.
+#[tauri::command]
+pub fn remove_destination(name: String) -> Result<(), String> {
+    if name == crate::config::DEFAULT_DESTINATION {
+        return Err("The default destination cannot be removed".to_string());
+    }
+    let tracked = crate::file_tracker::get_tracked_paths_for(&name)
+        .map_err(|e| e.to_string())?;
+    if !tracked.is_empty() {
+        return Err(format!("Destination '{}' still has {} tracked path(s). Move or remove them first.", name, tracked.len()));
+    }
+
+    for key in [crate::credentials::OAUTH_TOKENS, crate::credentials::CLIENT_SECRET, crate::credentials::STORAGE_SECRET] {
+        crate::credentials::delete(&crate::config::destination_key(key, &name))
+            .map_err(|e| e.to_string())?;
+    }
+    crate::config::update_config(|config| {
+        if let Some(destinations) = config.destinations.as_mut() {
+            destinations.retain(|destination| destination.name != name);
+        }
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Switches where a destination's backups are stored. The new storage starts
+/// out empty, so everything is uploaded again on the next sync.
+#[tauri::command]
+pub fn set_destination_storage(destination: Option<String>, storage: crate::config::StorageConfig) -> Result<(), String> {
+    storage.validate()
+        .map_err(|e| e.to_string())?;
+    let name = existing_destination(destination)?;
+
+    let mut changed = false;
+    crate::config::update_config(|config| {
+        let current = if name == crate::config::DEFAULT_DESTINATION {
+            &mut config.storage
+        } else {
+            match config.destinations.iter_mut().flatten().find(|destination| destination.name == name) {
+                Some(destination) => &mut destination.storage,
+                None => return,
+            }
+        };
+        if current.clone().unwrap_or_default() != storage {
+            *current = Some(storage);
+            changed = true;
+        }
+    })
+    .map_err(|e| e.to_string())?;
+
+    if changed {
+        crate::sync_engine::forget_uploads(&name)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_tracked_paths() -> Result<Vec<crate::file_tracker::TrackedPath>, String> {
     crate::file_tracker::get_tracked_paths()
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub fn add_tracked_path(path: String) -> Result<(), String> {
+pub fn add_tracked_path(path: String, destination: Option<String>) -> Result<(), String> {
     let path_buf = PathBuf::from(&path);
     if !path_buf.exists() {
         return Err(format!("Path does not exist: {}", path));
     }
+    let destination = existing_destination(destination)?;
     
-    crate::file_tracker::add_tracked_path(&path_buf)
+    crate::file_tracker::add_tracked_path(&path_buf, &destination)
         .map_err(|e| e.to_string())?;
     Ok(())
 }
 
+#[tauri::command]
+pub fn set_tracked_path_destination(path: String, destination: String) -> Result<(), String> {
+    let destination = existing_destination(Some(destination))?;
+    crate::file_tracker::set_tracked_path_destination(&PathBuf::from(&path), &destination)
+        .map_err(|e| e.to_string())
+}
+
+fn existing_destination(destination: Option<String>) -> Result<String, String> {
+    let name = destination_name(destination);
+    crate::config::load_config()
+        .and_then(|config| config.destination(&name))
+        .map(|destination| destination.name)
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub fn remove_tracked_path(path: String) -> Result<(), String> {
     let path_buf = PathBuf::from(&path);
@@ -90,252 +360,57 @@ pub fn remove_tracked_path(path: String) -> Result<(), String> {
     Ok(())
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct SyncResult {
     files_synced: usize,
     files_skipped: usize,
     errors: Vec<String>,
+    warnings: Vec<String>,
 }
 
 #[tauri::command]
-pub async fn sync_now() -> Result<SyncResult, String> {
+pub async fn sync_now(app_handle: tauri::AppHandle) -> Result<SyncResult, String> {
     crate::logger::log_info("=== Sync Now Command Started ===");
-    
-    // Get configuration
-    let config = crate::config::load_config()
-        .map_err(|e| format!("Failed to load config: {}", e))?;
-    
-    let drive_folder = config.drive_folder
-        .unwrap_or_else(|| "sync-bot-backups".to_string());
-    
-    // Get staging directory
-    let staging_dir = crate::config::get_staging_dir()
-        .map_err(|e| format!("Failed to get staging directory: {}", e))?;
-    
-    crate::logger::log_info(&format!("Staging directory: {:?}", staging_dir));
-    
-    // First, ensure all tracked files are copied to staging
-    let tracked_paths = crate::file_tracker::get_tracked_paths()
-        .map_err(|e| format!("Failed to get tracked paths: {}", e))?;
-    
-    if tracked_paths.is_empty() {
-        crate::logger::log_warn("No files or folders are being tracked");
-        return Ok(SyncResult {
-            files_synced: 0,
-            files_skipped: 0,
-            errors: vec!["No files or folders tracked. Please add files/folders first.".to_string()],
-        });
-    }
-    
-    crate::logger::log_info(&format!("Found {} tracked path(s)", tracked_paths.len()));
-    
-    // Copy all tracked files to staging if needed
-    let files = crate::file_tracker::get_all_files_to_sync()
-        .map_err(|e| format!("Failed to get files to sync: {}", e))?;
-    
-    crate::logger::log_info(&format!("Total files to process: {}", files.len()));
-    
-    for file_path in &files {
-        if !file_path.starts_with(&staging_dir) {
-            // File is outside staging, need to copy it
-            let tracked_paths = crate::file_tracker::get_tracked_paths()
-                .map_err(|e| format!("Failed to get tracked paths: {}", e))?;
-            
-            let mut found_base = None;
-            for tracked in &tracked_paths {
-                let tracked_buf = PathBuf::from(tracked);
-                if file_path.starts_with(&tracked_buf) {
-                    found_base = Some(tracked_buf);
-                    break;
-                }
-            }
-            
-            let relative_path = if let Some(ref base) = found_base {
-                if let Ok(rel) = file_path.strip_prefix(base) {
-                    if rel.as_os_str().is_empty() {
-                        // Base is the file itself (single file tracked)
-                        let file_name = file_path.file_name()
-                            .and_then(|n| n.to_str())
-                            .unwrap_or("unknown");
-                        staging_dir.join("tracked").join(file_name)
-                    } else {
-                        // Base is a directory, preserve its structure
-                        // Get the base folder name to maintain structure in ZIP
-                        if let Some(base_name) = base.file_name() {
-                            // Include base folder name: tracked/base_name/relative_path
-                            staging_dir.join("tracked").join(base_name).join(rel)
-                        } else {
-                            // Fallback: just use relative path
-                            staging_dir.join("tracked").join(rel)
-                        }
-                    }
-                } else {
-                    // Fallback: use filename
-                    let file_name = file_path.file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("unknown");
-                    staging_dir.join("tracked").join(file_name)
-                }
-            } else {
-                // No base found, use filename
-                let file_name = file_path.file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("unknown");
-                staging_dir.join("tracked").join(file_name)
-            };
-            
-            if let Some(parent) = relative_path.parent() {
-                std::fs::create_dir_all(parent)
-                    .map_err(|e| format!("Failed to create staging directory: {}", e))?;
-            }
-            
-            // Copy file to staging
-            if relative_path.exists() {
-                // If file exists, try to make it writable first to avoid permission denied errors
-                // when overwriting read-only files (like SSH keys)
-                if let Ok(metadata) = std::fs::metadata(&relative_path) {
-                    let mut permissions = metadata.permissions();
-                    if permissions.readonly() {
-                        #[allow(clippy::permissions_set_readonly_false)]
-                        permissions.set_readonly(false);
-                        let _ = std::fs::set_permissions(&relative_path, permissions);
-                    }
-                }
-                // Alternatively, remove the file first to ensure we can copy fresh
-                let _ = std::fs::remove_file(&relative_path);
-            }
+    perform_sync(&app_handle, crate::sync_coordinator::SyncTrigger::Manual).await
+}
 
-            if let Err(e) = std::fs::copy(file_path, &relative_path) {
-                let msg = format!("Failed to copy {} to {}: {}", file_path.display(), relative_path.display(), e);
-                crate::logger::log_error(&msg);
-                return Err(msg);
-            }
-            
-            crate::logger::log_info(&format!("Copied {} to staging", file_path.display()));
-        }
-    }
-    
-    // Create archive before sync (for version history)
-    let archives_dir = crate::config::get_archives_dir()
-        .map_err(|e| format!("Failed to get archives directory: {}", e))?;
-    
-    if let Err(e) = crate::version_manager::create_archive(&staging_dir, &archives_dir) {
-        crate::logger::log_warn(&format!("Warning: Failed to create archive: {}", e));
-    }
-    
-    // Create ZIP of staging directory for sync
-    crate::logger::log_info("Creating ZIP file of staging directory...");
-    let zip_path = staging_dir.join("backup.zip");
-    
-    // Check if ZIP exists and has changed
-    let zip_needs_sync = if zip_path.exists() {
-        // Check if staging directory has changed since last ZIP creation
-        let zip_modified = std::fs::metadata(&zip_path)
-            .and_then(|m| m.modified())
-            .ok();
-        
-        let staging_modified = std::fs::read_dir(&staging_dir)
-            .ok()
-            .and_then(|entries| {
-                entries
-                    .filter_map(|e| e.ok())
-                    .filter_map(|e| e.metadata().ok())
-                    .filter_map(|m| m.modified().ok())
-                    .max()
-            });
-        
-        match (zip_modified, staging_modified) {
-            (Some(zip_time), Some(staging_time)) => staging_time > zip_time,
-            _ => true, // If we can't determine, create new ZIP
-        }
-    } else {
-        true // ZIP doesn't exist, need to create it
-    };
-    
-    if zip_needs_sync {
-        crate::logger::log_info("Staging directory has changed, creating new ZIP...");
-        
-        // Create ZIP file
-        let file = std::fs::File::create(&zip_path)
-            .map_err(|e| format!("Failed to create ZIP file: {}", e))?;
-        let mut zip = zip::ZipWriter::new(file);
-        
-        // Add files from the "tracked" subdirectory, but without the "tracked" folder wrapper
-        let tracked_dir = staging_dir.join("tracked");
-        if tracked_dir.exists() {
-            crate::version_manager::add_directory_to_zip(&mut zip, &tracked_dir, &tracked_dir, "")
-                .map_err(|e| format!("Failed to add files to ZIP: {}", e))?;
-        }
-        
-        zip.finish()
-            .map_err(|e| format!("Failed to finalize ZIP: {}", e))?;
-        
-        crate::logger::log_info(&format!("ZIP file created: {:?}", zip_path));
-    } else {
-        crate::logger::log_info("Staging directory unchanged, using existing ZIP");
-    }
-    
-    // Check if ZIP has changed (for smart sync)
-    let zip_changed = crate::file_tracker::has_file_changed(&zip_path)
-        .map_err(|e| format!("Failed to check if ZIP changed: {}", e))?;
-    
-    if !zip_changed {
-        crate::logger::log_info("ZIP file has not changed, skipping upload");
-        
-        // Still update last sync time to show we checked
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::SystemTime::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        let _ = crate::file_tracker::set_metadata("last_sync_time", &now.to_string());
-
-        return Ok(SyncResult {
-            files_synced: 0,
-            files_skipped: 1,
-            errors: vec![],
-        });
-    }
-    
-    // Initialize Drive sync
-    let mut drive_sync = crate::drive_sync::DriveSync::new();
-    
-    // Find or create folder in Google Drive
-    let folder_id = drive_sync.find_or_create_folder(&drive_folder)
-        .await
-        .map_err(|e| format!("Failed to find/create Drive folder: {}", e))?;
-    
-    crate::logger::log_info(&format!("Drive folder ID: {}", folder_id));
-    
-    // Upload ZIP file to Drive
-    crate::logger::log_info("Uploading ZIP file to Google Drive...");
-    match drive_sync.upload_file(&zip_path, &folder_id).await {
-        Ok(_) => {
-            // Mark ZIP as synced
-            if let Err(e) = crate::file_tracker::mark_file_synced(&zip_path) {
-                crate::logger::log_error(&format!("Failed to mark ZIP as synced: {}", e));
-            }
-            
-            // Save last sync time
-            let now = std::time::SystemTime::now()
-                .duration_since(std::time::SystemTime::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs();
-            let _ = crate::file_tracker::set_metadata("last_sync_time", &now.to_string());
-
-            crate::logger::log_info("ZIP file uploaded successfully!");
-            Ok(SyncResult {
-                files_synced: 1,
-                files_skipped: 0,
-                errors: vec![],
-            })
-        }
-        Err(e) => {
-            crate::logger::log_error(&format!("Failed to upload ZIP: {}", e));
-            Ok(SyncResult {
+/// Runs the full sync pipeline through the sync coordinator, joining any run
+/// already in progress. Shared by the `sync_now` command, the tray and the
+/// background scheduler, so it must not depend on the webview being alive.
+pub async fn perform_sync(
+    app_handle: &tauri::AppHandle,
+    trigger: crate::sync_coordinator::SyncTrigger,
+) -> Result<SyncResult, String> {
+    use crate::sync_engine::SyncError;
+
+    match crate::sync_coordinator::request_sync(app_handle, trigger).await {
+        Ok(summary) => Ok(SyncResult::from(summary)),
+        Err(e) => match e.as_ref() {
+            // These are reported to the UI as part of the result rather than as a failed command
+            SyncError::NothingTracked | SyncError::Upload(_) => Ok(SyncResult {
                 files_synced: 0,
                 files_skipped: 0,
-                errors: vec![format!("Failed to upload ZIP file: {}", e)],
-            })
+                errors: vec![e.to_string()],
+                warnings: Vec::new(),
+            }),
+            _ => Err(e.to_string()),
+        },
+    }
+}
+
+impl From<crate::sync_coordinator::SyncSummary> for SyncResult {
+    fn from(summary: crate::sync_coordinator::SyncSummary) -> Self {
+        Self {
+            files_synced: summary.reports.iter().map(|report| report.files_uploaded).sum(),
+            files_skipped: summary.reports.iter().map(|report| report.files_unchanged).sum(),
+            warnings: summary.reports.iter()
+                .flat_map(|report| report.warnings.iter().cloned())
+                .collect(),
+            errors: summary.reports.into_iter()
+                .flat_map(|report| report.errors)
+                .chain(summary.failures.into_iter()
+                    .map(|failure| format!("{}: {}", failure.destination, failure.error)))
+                .collect(),
         }
     }
 }
@@ -367,13 +442,98 @@ pub fn get_sync_status() -> Result<SyncStatus, String> {
     Ok(SyncStatus {
         last_sync,
         next_sync,
-        is_syncing: false,
+        is_syncing: crate::sync_coordinator::is_syncing(),
     })
 }
 
 #[tauri::command]
-pub fn get_auth_url() -> Result<String, String> {
-    crate::drive_sync::DriveSync::get_auth_url()
+pub fn cancel_sync() -> bool {
+    crate::sync_coordinator::cancel_sync()
+}
+
+#[tauri::command]
+pub fn get_sync_history(limit: Option<u64>) -> Result<Vec<crate::file_tracker::SyncRunRecord>, String> {
+    crate::file_tracker::get_sync_history(limit.unwrap_or(50))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_sync_progress() -> Option<crate::sync_engine::SyncProgress> {
+    crate::sync_coordinator::current_progress()
+}
+
+#[tauri::command]
+pub async fn list_backups(destination: Option<String>) -> Result<Vec<crate::storage::StoredObject>, String> {
+    crate::restore::list_backups(&destination_name(destination))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn restore(
+    destination: Option<String>,
+    backup_id: Option<String>,
+    revision_id: Option<String>,
+    target_dir: Option<String>,
+    conflict: Option<crate::restore::ConflictPolicy>,
+    passphrase: Option<String>,
+) -> Result<crate::restore::RestoreReport, String> {
+    crate::logger::log_info("=== Restore Command Started ===");
+    let options = crate::restore::RestoreOptions {
+        destination: Some(destination_name(destination)),
+        backup_id,
+        revision_id,
+        target_dir: target_dir.filter(|dir| !dir.is_empty()).map(PathBuf::from),
+        conflict: conflict.unwrap_or_default(),
+        passphrase: passphrase.filter(|passphrase| !passphrase.is_empty()),
+    };
+    // A sync reads the same files a restore writes, so the restore holds the
+    // sync lock for its whole run
+    crate::sync_coordinator::while_locked(crate::restore::restore_backup(&options))
+        .await
+        .map_err(|e| match e {
+            crate::sync_engine::SyncError::Locked => {
+                "A sync is in progress. Wait for it to finish before restoring.".to_string()
+            }
+            e => e.to_string(),
+        })?
+        .map_err(|e| {
+            let msg = format!("Restore failed: {}", e);
+            crate::logger::log_error(&msg);
+            msg
+        })
+}
+
+#[tauri::command]
+pub async fn list_backup_revisions(destination: Option<String>) -> Result<Vec<crate::storage::Revision>, String> {
+    crate::restore::list_backup_revisions(&destination_name(destination))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn keep_backup_revision(
+    destination: Option<String>,
+    revision_id: String,
+    keep_forever: bool,
+) -> Result<(), String> {
+    crate::restore::keep_backup_revision(&destination_name(destination), &revision_id, keep_forever)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn delete_backup_revision(destination: Option<String>, revision_id: String) -> Result<(), String> {
+    crate::restore::delete_backup_revision(&destination_name(destination), &revision_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Starts the loopback OAuth flow for a destination's account and returns
+/// the URL to open in the browser.
+#[tauri::command]
+pub fn get_auth_url(destination: Option<String>) -> Result<String, String> {
+    crate::oauth::start(&existing_destination(destination)?)
         .map_err(|e| e.to_string())
 }
 
@@ -409,51 +569,39 @@ pub async fn open_url(url: String) -> Result<(), String> {
 
 #[tauri::command]
 pub async fn handle_oauth_code(code: String) -> Result<(), String> {
-    let mut drive_sync = crate::drive_sync::DriveSync::new();
-    drive_sync.handle_oauth_callback(&code)
+    let exchange = crate::oauth::finish()
+        .map_err(|e| e.to_string())?;
+    let mut drive_sync = crate::drive_sync::DriveSync::for_destination(&exchange.destination);
+    drive_sync.exchange_code_for_token(&code, &exchange)
         .await
         .map_err(|e| e.to_string())?;
     Ok(())
 }
 
 #[tauri::command]
-pub fn check_auth_status() -> bool {
-    crate::drive_sync::DriveSync::is_authenticated()
+pub fn check_auth_status(destination: Option<String>) -> bool {
+    crate::drive_sync::DriveSync::is_authenticated(&destination_name(destination))
 }
 
+/// When the current access token expires, as RFC 3339, if known.
 #[tauri::command]
-pub async fn listen_for_oauth_code() -> Result<String, String> {
-    use tiny_http::{Server, Response};
-    use url::Url;
+pub fn get_session_expiry(destination: Option<String>) -> Option<String> {
+    crate::drive_sync::DriveSync::session_expires_at(&destination_name(destination))
+        .map(|expires_at| expires_at.to_rfc3339())
+}
 
-    crate::logger::log_info("Starting local server to listen for OAuth code...");
-    
-    let server = Server::http(format!("127.0.0.1:{}", crate::drive_sync::REDIRECT_PORT))
-        .map_err(|e| format!("Failed to start local server: {}", e))?;
-
-    // Set a timeout for the server so it doesn't run forever if user cancels
-    // We'll wait for one request
-    if let Ok(request) = server.recv() {
-        let url = format!("http://localhost{}", request.url());
-        let parsed_url = Url::parse(&url).map_err(|e| format!("Failed to parse callback URL: {}", e))?;
-        
-        let code = parsed_url.query_pairs()
-            .find(|(key, _)| key == "code")
-            .map(|(_, value)| value.into_owned());
-
-        if let Some(code) = code {
-            let response = Response::from_string("Authentication successful! You can close this window and return to the app.");
-            let _ = request.respond(response);
-            crate::logger::log_info("OAuth code received successfully");
-            return Ok(code);
-        } else {
-            let response = Response::from_string("Authentication failed! No code found in request.");
-            let _ = request.respond(response);
-            return Err("No code found in the callback URL".to_string());
-        }
-    }
+#[tauri::command]
+pub async fn listen_for_oauth_code() -> Result<String, String> {
+    crate::logger::log_info("Waiting for the OAuth redirect...");
+    tokio::task::spawn_blocking(crate::oauth::wait_for_code)
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
 
-    Err("Server closed without receiving a request".to_string())
+#[tauri::command]
+pub fn cancel_oauth() -> bool {
+    crate::oauth::cancel()
 }
 
 #[tauri::command]
@@ -466,10 +614,62 @@ pub fn set_google_client_id(id: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-pub fn set_google_client_secret(secret: String) -> Result<(), String> {
-    crate::config::update_config(|config| {
-        config.client_secret = Some(secret);
+pub fn set_google_client_secret(secret: String, destination: Option<String>) -> Result<(), String> {
+    let key = client_secret_key(destination)?;
+    if secret.is_empty() {
+        crate::credentials::delete(&key)
+    } else {
+        crate::credentials::set(&key, &secret)
+    }
+    .map_err(|e| e.to_string())
+}
+
+/// A destination with its own client ID keeps its own secret; the others
+/// share the global one.
+fn client_secret_key(destination: Option<String>) -> Result<String, String> {
+    let destination = crate::config::load_config()
+        .and_then(|config| config.destination(&destination_name(destination)))
+        .map_err(|e| e.to_string())?;
+    Ok(match destination.client_id {
+        Some(_) => crate::config::destination_key(crate::credentials::CLIENT_SECRET, &destination.name),
+        None => crate::credentials::CLIENT_SECRET.to_string(),
+    })
+}
+
+/// Saves the secret the destination's storage signs in with, such as an S3
+/// secret access key; empty removes it.
+#[tauri::command]
+pub fn set_storage_secret(secret: String, destination: Option<String>) -> Result<(), String> {
+    let key = crate::config::destination_key(crate::credentials::STORAGE_SECRET, &existing_destination(destination)?);
+    if secret.is_empty() {
+        crate::credentials::delete(&key)
+    } else {
+        crate::credentials::set(&key, &secret)
+    }
+    .map_err(|e| e.to_string())
+}
+
+#[derive(Serialize)]
+pub struct CredentialStatus {
+    /// Where secrets are kept, e.g. "system keyring"
+    pub store: String,
+    pub has_client_secret: bool,
+    pub has_storage_secret: bool,
+}
+
+#[tauri::command]
+pub fn get_credential_status(destination: Option<String>) -> Result<CredentialStatus, String> {
+    let key = client_secret_key(destination.clone())?;
+    let storage_key = crate::config::destination_key(crate::credentials::STORAGE_SECRET, &existing_destination(destination)?);
+    let has_client_secret = crate::credentials::get(&key)
+        .map_err(|e| e.to_string())?
+        .is_some();
+    let has_storage_secret = crate::credentials::get(&storage_key)
+        .map_err(|e| e.to_string())?
+        .is_some();
+    Ok(CredentialStatus {
+        store: crate::credentials::store_name().to_string(),
+        has_client_secret,
+        has_storage_secret,
     })
-    .map_err(|e| e.to_string())?;
-    Ok(())
 }
@@ -1,9 +1,238 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
 use dirs;
 use anyhow::{Result, Context};
 
+/// How tracked files are stored in the Drive folder.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    /// Everything zipped into a single `backup.zip`
+    #[default]
+    Archive,
+    /// The tracked directory structure recreated file by file
+    Mirror,
+}
+
+/// What mirror mode does with the Drive copy of a file deleted locally.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DeletionPolicy {
+    /// Move it to the Drive trash
+    #[default]
+    Trash,
+    /// Move it into a timestamped folder under `deleted/`
+    Tombstone,
+    /// Leave it in place
+    Keep,
+}
+
+/// Where the key that encrypts archives before they leave the staging
+/// directory comes from. The key itself is never stored in `config.toml`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionMode {
+    #[default]
+    Off,
+    /// Derived from a passphrase; the derived key is kept in the credential store
+    Passphrase,
+    /// Derived from the contents of `encryption_key_file`
+    KeyFile,
+}
+
+/// Which archives survive pruning, locally and in the Drive `versions/` folder.
+/// An archive is kept when `keep_last` or any of the hourly/daily/weekly/monthly
+/// rules selects it; `max_age_days` and `max_total_size_mb` then trim what is
+/// left. The newest archive is never pruned. With no keep rules set, only the
+/// age and size limits apply.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Keep this many of the most recent archives
+    pub keep_last: Option<u32>,
+    /// Keep the newest archive of each of the last N hours that have one
+    pub keep_hourly: Option<u32>,
+    pub keep_daily: Option<u32>,
+    pub keep_weekly: Option<u32>,
+    pub keep_monthly: Option<u32>,
+    /// Prune the oldest archives once all kept archives exceed this size
+    pub max_total_size_mb: Option<u64>,
+    /// Prune archives older than this, whatever the keep rules say
+    pub max_age_days: Option<u64>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_last: Some(4),
+            keep_hourly: None,
+            keep_daily: None,
+            keep_weekly: None,
+            keep_monthly: None,
+            max_total_size_mb: None,
+            max_age_days: None,
+        }
+    }
+}
+
+/// The kind of storage a destination's backups go to, with the settings
+/// only that kind needs. Drive's folder, account and Shared Drive stay
+/// fields of the destination itself, as they predate the other kinds.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageConfig {
+    #[default]
+    Drive,
+    /// A local directory, such as a mounted NAS share
+    Filesystem {
+        path: String,
+    },
+    S3(S3Settings),
+    WebDav(WebDavSettings),
+    Sftp(SftpSettings),
+}
+
+/// An S3-compatible bucket, such as MinIO. The secret access key is kept in
+/// the credential store, not here.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct S3Settings {
+    /// e.g. `https://minio.example.com:9000`
+    pub endpoint: String,
+    pub bucket: String,
+    /// `us-east-1` when not set, which is also what MinIO uses by default
+    pub region: Option<String>,
+    /// Key prefix the backups go under, e.g. `sync-bot/laptop`
+    pub prefix: Option<String>,
+    /// Address the bucket as `endpoint/bucket` rather than `bucket.endpoint`,
+    /// as MinIO expects unless it is set up for virtual hosts
+    #[serde(default)]
+    pub path_style: bool,
+    pub access_key_id: String,
+}
+
+/// A WebDAV server such as Nextcloud or ownCloud. The app password is kept in
+/// the credential store, not here.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct WebDavSettings {
+    /// URL of the folder backups go into, e.g.
+    /// `https://cloud.example.com/remote.php/dav/files/alice/Backups`
+    pub url: String,
+    pub username: String,
+}
+
+/// A directory on a server reached over SFTP, signed in to with a key file.
+/// The key's passphrase, if it has one, is kept in the credential store.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SftpSettings {
+    pub host: String,
+    /// 22 when not set
+    pub port: Option<u16>,
+    pub username: String,
+    /// Private key to sign in with, e.g. `/home/alice/.ssh/id_ed25519`
+    pub key_file: String,
+    /// OpenSSH known_hosts file the server's key must be listed in;
+    /// `~/.ssh/known_hosts` when not set
+    pub known_hosts: Option<String>,
+    /// Directory backups go into; a relative path starts at the user's home
+    pub directory: String,
+}
+
+impl StorageConfig {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            StorageConfig::Drive => Ok(()),
+            StorageConfig::Filesystem { path } => {
+                if !Path::new(path).is_absolute() {
+                    anyhow::bail!("The backup directory must be an absolute path");
+                }
+                Ok(())
+            }
+            StorageConfig::S3(settings) => {
+                let endpoint = url::Url::parse(&settings.endpoint)
+                    .context("The S3 endpoint is not a valid URL")?;
+                if !matches!(endpoint.scheme(), "http" | "https") || endpoint.host_str().is_none() {
+                    anyhow::bail!("The S3 endpoint must be an http:// or https:// URL");
+                }
+                if settings.bucket.is_empty() {
+                    anyhow::bail!("Enter the name of the S3 bucket");
+                }
+                if settings.access_key_id.is_empty() {
+                    anyhow::bail!("Enter the S3 access key ID");
+                }
+                Ok(())
+            }
+            StorageConfig::WebDav(settings) => {
+                let url = url::Url::parse(&settings.url)
+                    .context("The WebDAV URL is not valid")?;
+                if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+                    anyhow::bail!("The WebDAV URL must be an http:// or https:// URL");
+                }
+                if settings.username.is_empty() {
+                    anyhow::bail!("Enter the WebDAV user name");
+                }
+                Ok(())
+            }
+            StorageConfig::Sftp(settings) => {
+                if settings.host.is_empty() {
+                    anyhow::bail!("Enter the SFTP server's host name");
+                }
+                if settings.port == Some(0) {
+                    anyhow::bail!("The SFTP port must be between 1 and 65535");
+                }
+                if settings.username.is_empty() {
+                    anyhow::bail!("Enter the SFTP user name");
+                }
+                if !Path::new(&settings.key_file).is_absolute() {
+                    anyhow::bail!("The SSH key file must be an absolute path");
+                }
+                if settings.known_hosts.as_deref().is_some_and(|path| !Path::new(path).is_absolute()) {
+                    anyhow::bail!("The known_hosts file must be an absolute path");
+                }
+                if settings.directory.is_empty() {
+                    anyhow::bail!("Enter the directory on the SFTP server to back up to");
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Name of the destination made up of the top-level `drive_folder` and the
+/// Google account authenticated before destinations existed. It always exists.
+pub const DEFAULT_DESTINATION: &str = "default";
+
+// Directory below the staging and archive directories holding one
+// subdirectory per destination
+const DESTINATIONS_DIR: &str = "destinations";
+
+// What earlier versions created for the default destination directly in the
+// staging directory. Anything else there belongs to the user.
+const LEGACY_STAGING_ENTRIES: &[&str] = &[
+    "tracked",
+    "tracked.partial",
+    "tracked.old",
+    "encrypted",
+    "backup.zip",
+    "backup.zip.partial",
+    "backup.zip.enc",
+    "backup.zip.enc.partial",
+];
+
+/// A named place backups go: a Google account, signed in separately, and a
+/// folder in it. Each tracked path belongs to one destination.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Destination {
+    pub name: String,
+    pub drive_folder: Option<String>,
+    /// OAuth client to sign in with instead of the top-level `client_id`, for
+    /// accounts whose organisation only allows its own apps
+    pub client_id: Option<String>,
+    /// Shared Drive to back up to, by ID or name, instead of My Drive
+    pub shared_drive: Option<String>,
+    /// Google Drive when not set
+    pub storage: Option<StorageConfig>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub staging_dir: Option<String>,
@@ -11,7 +240,72 @@ pub struct Config {
     pub sync_interval: Option<u64>, // minutes
     pub auto_sync: Option<bool>,
     pub client_id: Option<String>,
+    /// Only read to migrate older configs; the secret now lives in the
+    /// credential store
     pub client_secret: Option<String>,
+    pub sync_mode: Option<SyncMode>,
+    pub deletion_policy: Option<DeletionPolicy>,
+    /// Also upload each timestamped archive into `versions/` on Drive
+    pub upload_versions: Option<bool>,
+    pub encryption: Option<EncryptionMode>,
+    pub encryption_key_file: Option<String>,
+    pub retention: Option<RetentionPolicy>,
+    /// Shared Drive of the default destination, by ID or name
+    pub shared_drive: Option<String>,
+    /// Storage of the default destination; Google Drive when not set
+    pub storage: Option<StorageConfig>,
+    /// Destinations besides the default one
+    pub destinations: Option<Vec<Destination>>,
+}
+
+impl Destination {
+    /// Destination names end up in directory names and credential keys, so
+    /// they are limited to letters, digits, `-` and `_`.
+    pub fn validate_name(name: &str) -> Result<()> {
+        if name.is_empty() || name.len() > 64 {
+            anyhow::bail!("Destination names must be 1 to 64 characters long");
+        }
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            anyhow::bail!("Destination names may only contain letters, digits, '-' and '_'");
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Every destination, the default one first.
+    pub fn destinations(&self) -> Vec<Destination> {
+        let default = Destination {
+            name: DEFAULT_DESTINATION.to_string(),
+            drive_folder: self.drive_folder.clone(),
+            client_id: None,
+            shared_drive: self.shared_drive.clone(),
+            storage: self.storage.clone(),
+        };
+        std::iter::once(default)
+            .chain(self.destinations.iter().flatten()
+                .filter(|destination| destination.name != DEFAULT_DESTINATION)
+                .cloned())
+            .collect()
+    }
+
+    pub fn destination(&self, name: &str) -> Result<Destination> {
+        self.destinations()
+            .into_iter()
+            .find(|destination| destination.name == name)
+            .with_context(|| format!("No destination named '{}'", name))
+    }
+}
+
+/// Key for per-destination state stored under a shared name, such as the
+/// OAuth tokens. The default destination uses the bare name so state saved
+/// before destinations existed still belongs to it.
+pub fn destination_key(base: &str, destination: &str) -> String {
+    if destination == DEFAULT_DESTINATION {
+        base.to_string()
+    } else {
+        format!("{}:{}", base, destination)
+    }
 }
 
 impl Default for Config {
@@ -23,6 +317,15 @@ impl Default for Config {
             auto_sync: Some(false),
             client_id: None,
             client_secret: None,
+            sync_mode: Some(SyncMode::Archive),
+            deletion_policy: Some(DeletionPolicy::Trash),
+            upload_versions: Some(false),
+            encryption: Some(EncryptionMode::Off),
+            encryption_key_file: None,
+            retention: Some(RetentionPolicy::default()),
+            shared_drive: None,
+            storage: None,
+            destinations: None,
         }
     }
 }
@@ -88,6 +391,53 @@ pub fn get_staging_dir() -> Result<PathBuf> {
     Ok(default_dir)
 }
 
+/// Staging directory of a destination, in `destinations/<name>` below the
+/// staging directory.
+pub fn get_destination_staging_dir(destination: &str) -> Result<PathBuf> {
+    destination_dir(&get_staging_dir()?, destination, |name| LEGACY_STAGING_ENTRIES.contains(&name))
+        .context("Failed to create destination staging directory")
+}
+
+/// Local archive directory of a destination, laid out like its staging directory.
+pub fn get_destination_archives_dir(destination: &str) -> Result<PathBuf> {
+    destination_dir(&get_archives_dir()?, destination, |name| {
+        let name = name.strip_suffix(".partial").unwrap_or(name);
+        crate::version_manager::ArchiveCandidate::from_name(name, 0).is_some()
+    })
+        .context("Failed to create destination archives directory")
+}
+
+fn destination_dir(root: &Path, destination: &str, is_legacy: impl Fn(&str) -> bool) -> Result<PathBuf> {
+    let path = root.join(DESTINATIONS_DIR).join(destination);
+    if destination == DEFAULT_DESTINATION && !path.exists() {
+        move_default_destination_dir(root, &path, is_legacy)?;
+    }
+    fs::create_dir_all(&path)?;
+    Ok(path)
+}
+
+/// Moves the entries earlier versions kept for the default destination
+/// directly in `root`, as picked by `is_legacy`, into its own directory.
+/// Everything else in `root` is left alone: the staging directory can be any
+/// folder the user chose, even one with tracked files in it. Entries are
+/// gathered in a `.partial` directory first, so an interrupted move picks up
+/// where it stopped.
+fn move_default_destination_dir(root: &Path, path: &Path, is_legacy: impl Fn(&str) -> bool) -> Result<()> {
+    let partial = path.with_extension("partial");
+    fs::create_dir_all(&partial)?;
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if !entry.file_name().to_str().is_some_and(&is_legacy) {
+            continue;
+        }
+        fs::rename(entry.path(), partial.join(entry.file_name()))
+            .with_context(|| format!("Failed to move {}", entry.path().display()))?;
+    }
+    fs::rename(&partial, path)?;
+    crate::logger::log_info(&format!("Moved the default destination's files into {}", path.display()));
+    Ok(())
+}
+
 pub fn get_archives_dir() -> Result<PathBuf> {
     let archives_dir = get_data_dir()?.join("archives");
     if !archives_dir.exists() {
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use anyhow::{Result, Context};
+use chacha20poly1305::aead::{Aead, AeadCore, OsRng};
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305};
+use zeroize::Zeroizing;
+
+/// Service name every keyring entry is filed under.
+const KEYRING_SERVICE: &str = "sync-bot";
+
+/// OAuth access and refresh tokens, as JSON.
+pub const OAUTH_TOKENS: &str = "oauth-tokens";
+/// The Google OAuth client secret.
+pub const CLIENT_SECRET: &str = "google-client-secret";
+/// The passphrase-derived archive key, as JSON.
+pub const ENCRYPTION_KEY: &str = "encryption-key";
+/// The secret of a destination's storage backend, such as an S3 secret
+/// access key, a WebDAV app password or the passphrase of an SFTP key.
+pub const STORAGE_SECRET: &str = "storage-secret";
+
+// Fallback store: the secrets sit in the data directory, encrypted with a key
+// kept in the config directory. A copy of either directory alone reveals
+// nothing, but unlike the keyring it does not protect against other
+// processes running as the same user.
+const FALLBACK_STORE_NAME: &str = "encrypted file";
+const FALLBACK_STORE_FILE: &str = "credentials.enc";
+const FALLBACK_KEY_FILE: &str = "credentials.key";
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+
+// Plaintext files written by earlier versions
+const LEGACY_TOKENS_FILE: &str = "tokens.json";
+const LEGACY_ENCRYPTION_KEY_FILE: &str = "encryption.key";
+
+/// Somewhere to keep secrets outside `config.toml` and the data directory.
+pub trait CredentialStore: Send + Sync {
+    /// Short name shown in the UI, e.g. "system keyring".
+    fn name(&self) -> &'static str;
+    fn get(&self, key: &str) -> Result<Option<String>>;
+    fn set(&self, key: &str, value: &str) -> Result<()>;
+    /// Removes a secret. Removing one that does not exist is not an error.
+    fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// The OS keyring: Secret Service on Linux, Keychain on macOS and the
+/// Credential Manager on Windows.
+pub struct KeyringStore;
+
+impl KeyringStore {
+    fn entry(key: &str) -> Result<keyring::Entry> {
+        keyring::Entry::new(KEYRING_SERVICE, key)
+            .context("Failed to open keyring entry")
+    }
+
+    /// Whether a keyring service is reachable. Headless sessions and minimal
+    /// desktops often have no Secret Service running.
+    fn is_available() -> bool {
+        Self::probe().is_ok()
+    }
+
+    fn probe() -> Result<()> {
+        if !cfg!(any(target_os = "linux", target_os = "macos", target_os = "windows")) {
+            // keyring falls back to an in-memory mock elsewhere
+            anyhow::bail!("no keyring support on this platform");
+        }
+        match Self::entry(OAUTH_TOKENS)?.get_password() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl CredentialStore for KeyringStore {
+    fn name(&self) -> &'static str {
+        "system keyring"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        match Self::entry(key)?.get_password() {
+            Ok(value) => Ok(Some(value)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {} from the keyring", key)),
+        }
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        Self::entry(key)?
+            .set_password(value)
+            .with_context(|| format!("Failed to write {} to the keyring", key))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        match Self::entry(key)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e).with_context(|| format!("Failed to remove {} from the keyring", key)),
+        }
+    }
+}
+
+/// All secrets in one XChaCha20-Poly1305 sealed file, for systems without a
+/// usable keyring.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    key_path: PathBuf,
+}
+
+impl EncryptedFileStore {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            path: crate::config::get_data_dir()?.join(FALLBACK_STORE_FILE),
+            key_path: crate::config::get_config_dir()?.join(FALLBACK_KEY_FILE),
+        })
+    }
+
+    fn cipher(&self) -> Result<XChaCha20Poly1305> {
+        if !self.key_path.exists() {
+            let mut key = Zeroizing::new([0u8; KEY_LEN]);
+            rand::RngCore::fill_bytes(&mut OsRng, key.as_mut());
+            crate::crypto::write_private_file(&self.key_path, key.as_ref())?;
+        }
+
+        let key = Zeroizing::new(fs::read(&self.key_path)
+            .context("Failed to read credential store key")?);
+        if key.len() != KEY_LEN {
+            anyhow::bail!("Credential store key has the wrong length");
+        }
+        Ok(XChaCha20Poly1305::new(key.as_slice().into()))
+    }
+
+    fn load(&self) -> Result<BTreeMap<String, String>> {
+        if !self.path.exists() {
+            return Ok(BTreeMap::new());
+        }
+
+        let sealed = fs::read(&self.path)
+            .context("Failed to read credential store")?;
+        if sealed.len() < NONCE_LEN {
+            anyhow::bail!("Credential store is truncated");
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plain = Zeroizing::new(self.cipher()?
+            .decrypt(nonce.into(), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt credential store: wrong key or corrupted file"))?);
+        serde_json::from_slice(&plain)
+            .context("Failed to parse credential store")
+    }
+
+    fn save(&self, secrets: &BTreeMap<String, String>) -> Result<()> {
+        let plain = Zeroizing::new(serde_json::to_vec(secrets)
+            .context("Failed to serialize credential store")?);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher()?
+            .encrypt(&nonce, plain.as_slice())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt credential store"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        crate::crypto::write_private_file(&self.path, &sealed)
+    }
+}
+
+impl CredentialStore for EncryptedFileStore {
+    fn name(&self) -> &'static str {
+        FALLBACK_STORE_NAME
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.load()?.remove(key))
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        let mut secrets = self.load()?;
+        secrets.insert(key.to_string(), value.to_string());
+        self.save(&secrets)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let mut secrets = self.load()?;
+        if secrets.remove(key).is_some() {
+            self.save(&secrets)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a secret from the keyring, falling back to the encrypted file when
+/// the keyring does not answer or does not hold it.
+pub fn get(key: &str) -> Result<Option<String>> {
+    if KeyringStore::is_available() {
+        reclaim_fallback_secrets();
+        match KeyringStore.get(key) {
+            Ok(Some(value)) => return Ok(Some(value)),
+            Ok(None) => {}
+            Err(e) => crate::logger::log_warn(&format!("{:#}; trying the encrypted file", e)),
+        }
+    }
+    EncryptedFileStore::new()?.get(key)
+}
+
+/// Writes a secret to the keyring, or to the encrypted file while the
+/// keyring is unavailable or refuses the write.
+pub fn set(key: &str, value: &str) -> Result<()> {
+    let fallback = EncryptedFileStore::new()?;
+    match KeyringStore::probe().and_then(|()| KeyringStore.set(key, value)) {
+        // Drop any older copy so it cannot outlive this one
+        Ok(()) => return fallback.delete(key),
+        Err(e) => crate::logger::log_warn(&format!("System keyring unavailable ({:#}); keeping {} in the encrypted file", e, key)),
+    }
+    fallback.set(key, value)
+}
+
+/// Removes a secret from both stores.
+pub fn delete(key: &str) -> Result<()> {
+    EncryptedFileStore::new()?.delete(key)?;
+    if KeyringStore::is_available() {
+        KeyringStore.delete(key)?;
+    }
+    Ok(())
+}
+
+/// Name of the store new secrets currently go to, for the UI.
+pub fn store_name() -> &'static str {
+    if KeyringStore::is_available() {
+        KeyringStore.name()
+    } else {
+        FALLBACK_STORE_NAME
+    }
+}
+
+/// Moves secrets written to the encrypted file while the keyring was down
+/// back into the keyring. Each one leaves the file only once the keyring
+/// returns it, and a failure just leaves it for the next attempt.
+fn reclaim_fallback_secrets() {
+    let fallback = match EncryptedFileStore::new() {
+        Ok(fallback) if fallback.path.exists() => fallback,
+        _ => return,
+    };
+    let result = (|| -> Result<usize> {
+        let mut secrets = fallback.load()?;
+        let mut moved = 0;
+        secrets.retain(|key, value| {
+            let kept = KeyringStore.set(key, value).is_ok()
+                && KeyringStore.get(key).ok().flatten().as_deref() == Some(value.as_str());
+            if kept {
+                moved += 1;
+            }
+            !kept
+        });
+        if moved > 0 {
+            fallback.save(&secrets)?;
+        }
+        if secrets.is_empty() {
+            fs::remove_file(&fallback.path)
+                .context("Failed to remove the emptied credential store")?;
+        }
+        Ok(moved)
+    })();
+
+    match result {
+        Ok(0) => {}
+        Ok(moved) => crate::logger::log_info(&format!("Moved {} credential(s) from the encrypted file back to the system keyring", moved)),
+        Err(e) => crate::logger::log_warn(&format!("Failed to move credentials back to the system keyring: {:#}", e)),
+    }
+}
+
+/// Moves secrets that earlier versions kept in plaintext into the credential
+/// store: `tokens.json`, the passphrase key file and `client_secret` from
+/// `config.toml`. Each plaintext copy is removed only once the store holds it.
+pub fn migrate_plaintext_secrets() -> Result<()> {
+    let data_dir = crate::config::get_data_dir()?;
+    migrate_file(&data_dir.join(LEGACY_TOKENS_FILE), OAUTH_TOKENS)?;
+    migrate_file(&data_dir.join(LEGACY_ENCRYPTION_KEY_FILE), ENCRYPTION_KEY)?;
+
+    let config = crate::config::load_config()?;
+    if let Some(secret) = config.client_secret.filter(|secret| !secret.is_empty()) {
+        store_verified(CLIENT_SECRET, &secret)?;
+        crate::config::update_config(|config| config.client_secret = None)?;
+        crate::logger::log_info(&format!("Moved client secret from config.toml to the {}", store_name()));
+    }
+
+    Ok(())
+}
+
+fn migrate_file(path: &std::path::Path, key: &str) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let value = Zeroizing::new(fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?);
+    store_verified(key, &value)?;
+    fs::remove_file(path)
+        .with_context(|| format!("Failed to remove {}", path.display()))?;
+    crate::logger::log_info(&format!("Moved {} to the {}", path.display(), store_name()));
+    Ok(())
+}
+
+/// Writes a secret and reads it back, so a plaintext copy is never deleted
+/// on the strength of a write the store silently dropped.
+fn store_verified(key: &str, value: &str) -> Result<()> {
+    set(key, value)?;
+    if get(key)?.as_deref() != Some(value) {
+        anyhow::bail!("Credential store did not keep {}", key);
+    }
+    Ok(())
+}
@@ -0,0 +1,442 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use base64::Engine;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::Payload;
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+use crate::config::EncryptionMode;
+
+/// Suffix added to the name of every encrypted file.
+pub const ENCRYPTED_SUFFIX: &str = ".enc";
+
+// Container layout:
+//   MAGIC | key source (1 byte) | salt (16 bytes) | nonce prefix (19 bytes)
+//   followed by XChaCha20-Poly1305 STREAM chunks of CHUNK_SIZE plaintext bytes
+//   plus a 16-byte tag each. The header is the associated data of every chunk,
+//   so tampering with it fails decryption.
+const MAGIC: &[u8; 8] = b"SBENC\x00\x01\x00";
+const SALT_LEN: usize = 16;
+const NONCE_PREFIX_LEN: usize = 19;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN + NONCE_PREFIX_LEN;
+const KEY_LEN: usize = 32;
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+// Key files shorter than this are rejected as too guessable
+const MIN_KEY_FILE_LEN: u64 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum KeySource {
+    Passphrase = 1,
+    KeyFile = 2,
+}
+
+impl KeySource {
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            1 => Ok(KeySource::Passphrase),
+            2 => Ok(KeySource::KeyFile),
+            other => anyhow::bail!("Unknown key source {} in encrypted file", other),
+        }
+    }
+}
+
+/// A 256-bit archive key and how it was derived. The salt is written into
+/// every file it encrypts so a passphrase can re-derive it on another machine.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    key: Zeroizing<[u8; KEY_LEN]>,
+    source: KeySource,
+    salt: [u8; SALT_LEN],
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The parts of an encrypted file's header needed to pick its key.
+#[derive(Debug, Clone)]
+pub struct EncryptedHeader {
+    pub source: KeySource,
+    salt: [u8; SALT_LEN],
+}
+
+impl EncryptionKey {
+    /// Derives a key from a passphrase with Argon2id.
+    pub fn from_passphrase(passphrase: &str, salt: [u8; SALT_LEN]) -> Result<Self> {
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, key.as_mut())
+            .map_err(|e| anyhow::anyhow!("Failed to derive key from passphrase: {}", e))?;
+        Ok(Self { key, source: KeySource::Passphrase, salt })
+    }
+
+    /// Derives a key from the contents of a key file.
+    pub fn from_key_file(path: &Path) -> Result<Self> {
+        let contents = Zeroizing::new(fs::read(path)
+            .with_context(|| format!("Failed to read key file {}", path.display()))?);
+        if (contents.len() as u64) < MIN_KEY_FILE_LEN {
+            anyhow::bail!("Key file {} is too short (at least {} bytes needed)", path.display(), MIN_KEY_FILE_LEN);
+        }
+
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        key.copy_from_slice(&Sha256::digest(contents.as_slice()));
+        Ok(Self { key, source: KeySource::KeyFile, salt: [0u8; SALT_LEN] })
+    }
+
+    /// Identifies the key without revealing it, to tell whether a file was
+    /// encrypted with the key configured now.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(b"sync-bot key fingerprint\0");
+        hasher.update(self.key.as_ref());
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(self.key.as_ref().into())
+    }
+}
+
+/// The key configured for syncs, or `None` when encryption is off.
+pub fn load_configured_key() -> Result<Option<EncryptionKey>> {
+    let config = crate::config::load_config()?;
+    match config.encryption.unwrap_or_default() {
+        EncryptionMode::Off => Ok(None),
+        EncryptionMode::Passphrase => load_stored_key()?
+            .map(Some)
+            .context("Encryption is enabled but no passphrase has been set"),
+        EncryptionMode::KeyFile => {
+            let key_file = config.encryption_key_file
+                .context("Encryption is enabled but no key file has been selected")?;
+            Ok(Some(EncryptionKey::from_key_file(Path::new(&key_file))?))
+        }
+    }
+}
+
+/// Derives a key from a new passphrase with a fresh salt and stores it as
+/// the sync key. Files encrypted under an earlier passphrase keep their own salt.
+pub fn set_passphrase(passphrase: &str) -> Result<()> {
+    if passphrase.is_empty() {
+        anyhow::bail!("Passphrase must not be empty");
+    }
+
+    let mut salt = [0u8; SALT_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    let key = EncryptionKey::from_passphrase(passphrase, salt)?;
+    store_key(&key)
+}
+
+/// Writes a new random key file that can be used with `EncryptionMode::KeyFile`.
+pub fn generate_key_file(path: &Path) -> Result<()> {
+    if path.exists() {
+        anyhow::bail!("{} already exists", path.display());
+    }
+
+    let mut contents = Zeroizing::new([0u8; KEY_LEN]);
+    rand::rngs::OsRng.fill_bytes(contents.as_mut());
+    write_private_file(path, contents.as_ref())
+}
+
+/// Resolves the key for an encrypted file: a passphrase when one is given,
+/// otherwise the configured key if it matches how the file was encrypted.
+pub fn key_for_header(header: &EncryptedHeader, passphrase: Option<&str>) -> Result<EncryptionKey> {
+    match (header.source, passphrase) {
+        (KeySource::Passphrase, Some(passphrase)) => EncryptionKey::from_passphrase(passphrase, header.salt),
+        (KeySource::Passphrase, None) => match load_stored_key()? {
+            Some(key) if key.salt == header.salt => Ok(key),
+            _ => anyhow::bail!("This backup was encrypted with a different passphrase. Enter it to restore."),
+        },
+        (KeySource::KeyFile, _) => match load_configured_key()? {
+            Some(key) if key.source == KeySource::KeyFile => Ok(key),
+            _ => anyhow::bail!("This backup was encrypted with a key file. Select it in the encryption settings to restore."),
+        },
+    }
+}
+
+/// Reads the header of `path`, or `None` if it is not one of our encrypted files.
+pub fn read_header(path: &Path) -> Result<Option<EncryptedHeader>> {
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut header = [0u8; HEADER_LEN];
+    match file.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e).context("Failed to read file header"),
+    }
+    if &header[..MAGIC.len()] != MAGIC {
+        return Ok(None);
+    }
+
+    let (source, salt, _) = split_header(&header)?;
+    Ok(Some(EncryptedHeader { source, salt }))
+}
+
+/// Name of the encrypted counterpart of `path`, e.g. `backup.zip.enc`.
+pub fn encrypted_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    name.push(ENCRYPTED_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Encrypts `source` into `dest`, writing through a temporary file so `dest`
+/// only ever holds a complete container.
+pub fn encrypt_file(source: &Path, dest: &Path, key: &EncryptionKey) -> Result<()> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.push(key.source as u8);
+    header.extend_from_slice(&key.salt);
+    let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut nonce_prefix);
+    header.extend_from_slice(&nonce_prefix);
+
+    let mut input = fs::File::open(source)
+        .with_context(|| format!("Failed to open {}", source.display()))?;
+    write_atomically(dest, |output| {
+        output.write_all(&header)?;
+
+        let mut encryptor = EncryptorBE32::from_aead(key.cipher(), nonce_prefix.as_ref().into());
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        let mut len = read_full(&mut input, &mut chunk)?;
+        loop {
+            let mut next = vec![0u8; CHUNK_SIZE];
+            let next_len = if len == CHUNK_SIZE { read_full(&mut input, &mut next)? } else { 0 };
+            let payload = Payload { msg: &chunk[..len], aad: &header };
+            if next_len == 0 {
+                let sealed = encryptor.encrypt_last(payload)
+                    .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+                output.write_all(&sealed)?;
+                return Ok(());
+            }
+            let sealed = encryptor.encrypt_next(payload)
+                .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+            output.write_all(&sealed)?;
+            chunk = next;
+            len = next_len;
+        }
+    })
+}
+
+/// Decrypts a container written by `encrypt_file` into `dest`. Fails without
+/// leaving `dest` behind if the key is wrong or the file was modified or truncated.
+pub fn decrypt_file(source: &Path, dest: &Path, key: &EncryptionKey) -> Result<()> {
+    let mut input = fs::File::open(source)
+        .with_context(|| format!("Failed to open {}", source.display()))?;
+    let mut header = [0u8; HEADER_LEN];
+    input.read_exact(&mut header)
+        .context("Encrypted file is truncated")?;
+    if &header[..MAGIC.len()] != MAGIC {
+        anyhow::bail!("{} is not an encrypted backup", source.display());
+    }
+    let (_, _, nonce_prefix) = split_header(&header)?;
+
+    write_atomically(dest, |output| {
+        let mut decryptor = DecryptorBE32::from_aead(key.cipher(), nonce_prefix.into());
+        let sealed_size = CHUNK_SIZE + TAG_LEN;
+        let mut chunk = vec![0u8; sealed_size];
+        let mut len = read_full(&mut input, &mut chunk)?;
+        loop {
+            let mut next = vec![0u8; sealed_size];
+            let next_len = if len == sealed_size { read_full(&mut input, &mut next)? } else { 0 };
+            let payload = Payload { msg: &chunk[..len], aad: &header };
+            if next_len == 0 {
+                let plain = Zeroizing::new(decryptor.decrypt_last(payload)
+                    .map_err(|_| anyhow::anyhow!("Decryption failed: wrong key or corrupted file"))?);
+                output.write_all(&plain)?;
+                return Ok(());
+            }
+            let plain = Zeroizing::new(decryptor.decrypt_next(payload)
+                .map_err(|_| anyhow::anyhow!("Decryption failed: wrong key or corrupted file"))?);
+            output.write_all(&plain)?;
+            chunk = next;
+            len = next_len;
+        }
+    })
+}
+
+fn split_header(header: &[u8; HEADER_LEN]) -> Result<(KeySource, [u8; SALT_LEN], &[u8])> {
+    let source = KeySource::from_byte(header[MAGIC.len()])?;
+    let salt_start = MAGIC.len() + 1;
+    let mut salt = [0u8; SALT_LEN];
+    salt.copy_from_slice(&header[salt_start..salt_start + SALT_LEN]);
+    Ok((source, salt, &header[salt_start + SALT_LEN..]))
+}
+
+/// Fills `buf` as far as the reader allows, returning the number of bytes read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).context("Failed to read file"),
+        }
+    }
+    Ok(filled)
+}
+
+fn write_atomically<F>(dest: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut fs::File) -> Result<()>,
+{
+    let mut partial_name = dest.file_name()
+        .context("Destination has no file name")?
+        .to_os_string();
+    partial_name.push(".partial");
+    let partial_path = dest.with_file_name(partial_name);
+
+    let result = fs::File::create(&partial_path)
+        .with_context(|| format!("Failed to create {}", partial_path.display()))
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all().context("Failed to flush file")
+        });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial_path);
+        return Err(e);
+    }
+
+    fs::rename(&partial_path, dest)
+        .with_context(|| format!("Failed to move {} into place", dest.display()))
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    salt: String,
+    key: String,
+}
+
+fn load_stored_key() -> Result<Option<EncryptionKey>> {
+    let content = match crate::credentials::get(crate::credentials::ENCRYPTION_KEY)? {
+        Some(content) => Zeroizing::new(content),
+        None => return Ok(None),
+    };
+    let stored: StoredKey = serde_json::from_str(&content)
+        .context("Failed to parse stored encryption key")?;
+    let engine = base64::engine::general_purpose::STANDARD;
+    let salt_bytes = engine.decode(&stored.salt).context("Invalid stored salt")?;
+    let key_bytes = Zeroizing::new(engine.decode(&stored.key).context("Invalid stored key")?);
+    if salt_bytes.len() != SALT_LEN || key_bytes.len() != KEY_LEN {
+        anyhow::bail!("Stored encryption key has the wrong length");
+    }
+
+    let mut salt = [0u8; SALT_LEN];
+    salt.copy_from_slice(&salt_bytes);
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    key.copy_from_slice(&key_bytes);
+    Ok(Some(EncryptionKey { key, source: KeySource::Passphrase, salt }))
+}
+
+fn store_key(key: &EncryptionKey) -> Result<()> {
+    let engine = base64::engine::general_purpose::STANDARD;
+    let stored = StoredKey {
+        salt: engine.encode(key.salt),
+        key: engine.encode(key.key.as_ref()),
+    };
+    let content = Zeroizing::new(serde_json::to_string(&stored)
+        .context("Failed to serialize encryption key")?);
+    crate::credentials::set(crate::credentials::ENCRYPTION_KEY, &content)
+}
+
+/// Writes a file only the current user can read.
+pub fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(contents)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    file.sync_all()
+        .with_context(|| format!("Failed to flush {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key(dir: &Path, seed: u8) -> EncryptionKey {
+        let key_file = dir.join(format!("key-{}", seed));
+        fs::write(&key_file, [seed; MIN_KEY_FILE_LEN as usize]).unwrap();
+        EncryptionKey::from_key_file(&key_file).unwrap()
+    }
+
+    /// Encrypts `len` bytes and returns the plaintext and the encrypted file.
+    fn encrypt_sample(dir: &Path, key: &EncryptionKey, len: usize) -> (Vec<u8>, PathBuf) {
+        let plain: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let source = dir.join(format!("plain-{}", len));
+        fs::write(&source, &plain).unwrap();
+        let encrypted = encrypted_path(&source);
+        encrypt_file(&source, &encrypted, key).unwrap();
+        (plain, encrypted)
+    }
+
+    #[test]
+    fn round_trips_across_chunk_boundaries() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let key = test_key(dir, 1);
+        for len in [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE + 1, 3 * CHUNK_SIZE + 7] {
+            let (plain, encrypted) = encrypt_sample(dir, &key, len);
+            assert_eq!(read_header(&encrypted).unwrap().unwrap().source, KeySource::KeyFile);
+
+            let decrypted = dir.join("decrypted");
+            decrypt_file(&encrypted, &decrypted, &key).unwrap();
+            assert_eq!(fs::read(&decrypted).unwrap(), plain, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_key_and_tampering() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let key = test_key(dir, 1);
+        let (_, encrypted) = encrypt_sample(dir, &key, 2 * CHUNK_SIZE + 100);
+        let decrypted = dir.join("decrypted");
+
+        assert!(decrypt_file(&encrypted, &decrypted, &test_key(dir, 2)).is_err());
+        assert!(!decrypted.exists());
+
+        let mut sealed = fs::read(&encrypted).unwrap();
+        sealed[HEADER_LEN + 10] ^= 1;
+        fs::write(&encrypted, &sealed).unwrap();
+        assert!(decrypt_file(&encrypted, &decrypted, &key).is_err());
+        assert!(!decrypted.exists());
+    }
+
+    #[test]
+    fn detects_truncation() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let key = test_key(dir, 1);
+        let (_, encrypted) = encrypt_sample(dir, &key, 2 * CHUNK_SIZE + 100);
+        let sealed = fs::read(&encrypted).unwrap();
+        let decrypted = dir.join("decrypted");
+
+        // Cut inside the last chunk, exactly at a chunk boundary, and inside the header
+        for len in [sealed.len() - 1, HEADER_LEN + 2 * (CHUNK_SIZE + TAG_LEN), HEADER_LEN - 1] {
+            fs::write(&encrypted, &sealed[..len]).unwrap();
+            assert!(decrypt_file(&encrypted, &decrypted, &key).is_err(), "truncated to {}", len);
+            assert!(!decrypted.exists());
+        }
+    }
+}
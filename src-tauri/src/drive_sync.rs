@@ -1,16 +1,21 @@
+use std::collections::HashMap;
 use std::path::Path;
 use std::fs;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use anyhow::{Result, Context};
+use crate::storage::{Revision, StorageBackend, StoredObject, TransferHooks};
 
 const GOOGLE_OAUTH_AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
 const GOOGLE_OAUTH_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
 const GOOGLE_DRIVE_API_BASE: &str = "https://www.googleapis.com/drive/v3";
+const GOOGLE_DRIVE_UPLOAD_BASE: &str = "https://www.googleapis.com/upload/drive/v3";
 
-// Standard loopback URI for desktop apps
+// Preferred loopback port for the OAuth redirect; `oauth` falls back to an
+// ephemeral port when it is taken
 pub const REDIRECT_PORT: u16 = 14242;
-pub const REDIRECT_URI: &str = "http://localhost:14242";
 
 // Default placeholders
 const DEFAULT_CLIENT_ID: &str = "YOUR_CLIENT_ID";
@@ -24,73 +29,164 @@ struct TokenResponse {
     token_type: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct DriveFile {
-    id: String,
-    name: String,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriveFile {
+    pub id: String,
+    pub name: String,
     #[serde(rename = "mimeType")]
-    mime_type: String,
-    parents: Option<Vec<String>>,
+    pub mime_type: String,
+    pub parents: Option<Vec<String>>,
+    /// Size in bytes as reported by Drive (a decimal string); absent for folders
+    pub size: Option<String>,
+    #[serde(alias = "modifiedTime")]
+    pub modified_time: Option<String>,
+}
+
+impl DriveFile {
+    pub fn is_folder(&self) -> bool {
+        self.mime_type == FOLDER_MIME_TYPE
+    }
+}
+
+/// The parts of a folder's metadata that show whether it was trashed or moved.
+#[derive(Debug, Deserialize)]
+struct FolderStatus {
+    name: String,
+    #[serde(default)]
+    trashed: bool,
+    #[serde(default)]
+    parents: Vec<String>,
 }
 
 pub struct DriveSync {
     client: Client,
+    /// Destination whose account the tokens belong to
+    destination: String,
     access_token: Option<String>,
     refresh_token: Option<String>,
+    /// When `access_token` stops being accepted, if Google told us
+    token_expires_at: Option<DateTime<Utc>>,
+    /// Shared Drive every call is scoped to, once resolved; `Some(None)` for My Drive
+    shared_drive_id: Option<Option<String>>,
+    /// Path of the backup folder the `StorageBackend` paths are relative to
+    backup_folder: String,
+    /// Folder IDs by path below the backup folder, `""` being the folder itself
+    folder_ids: HashMap<String, String>,
 }
 
 const MAX_RETRIES: u32 = 3;
 
+// Refresh this long before the access token expires, so a request started
+// just before expiry doesn't reach Drive with a stale token
+const TOKEN_REFRESH_MARGIN_SECS: i64 = 60;
+
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+
+// Lets calls other than files.list act on items in Shared Drives
+const SUPPORTS_ALL_DRIVES: (&str, &str) = ("supportsAllDrives", "true");
+
+// Listing Shared Drives to find one by name is outside the drive.file scope
+const SHARED_DRIVE_LOOKUP_SCOPE: &str = "https://www.googleapis.com/auth/drive.readonly";
+
+// Fields requested for every file returned by list_folder
+const DRIVE_FILE_FIELDS: &str = "id,name,mimeType,parents,size,modifiedTime";
+
+// Fields requested for every revision returned by list_revisions
+const DRIVE_REVISION_FIELDS: &str = "id,modifiedTime,size,keepForever,md5Checksum";
+
+// Size of each resumable upload request; Drive requires a multiple of 256 KiB
+const RESUMABLE_CHUNK_SIZE: usize = 32 * 256 * 1024;
+
+// Drive keeps resumable sessions for a week; don't try to resume anything close to that
+const UPLOAD_SESSION_MAX_AGE_SECS: u64 = 6 * 24 * 60 * 60;
+
 impl DriveSync {
+    /// Client for the default destination.
     pub fn new() -> Self {
+        Self::for_destination(crate::config::DEFAULT_DESTINATION)
+    }
+
+    pub fn for_destination(destination: &str) -> Self {
         Self {
             client: Client::new(),
+            destination: destination.to_string(),
             access_token: None,
             refresh_token: None,
+            token_expires_at: None,
+            shared_drive_id: None,
+            backup_folder: crate::sync_engine::DEFAULT_DRIVE_FOLDER.to_string(),
+            folder_ids: HashMap::new(),
         }
     }
 
-    pub fn get_auth_url() -> Result<String> {
-        let config = crate::config::load_config()?;
-        let client_id = config.client_id
-            .or_else(|| std::env::var("GOOGLE_CLIENT_ID").ok())
-            .unwrap_or_else(|| DEFAULT_CLIENT_ID.to_string());
+    /// Sets the backup folder path, e.g. `backups/laptop`, that the
+    /// `StorageBackend` paths are relative to.
+    pub fn with_backup_folder(mut self, path: &str) -> Self {
+        self.backup_folder = path.to_string();
+        self
+    }
+
+    /// Consent URL for the loopback flow, with a PKCE S256 challenge and the
+    /// `state` the redirect must echo back.
+    pub fn get_auth_url(&self, redirect_uri: &str, code_challenge: &str, state: &str) -> Result<String> {
+        let (client_id, _) = self.client_credentials()?;
         
         if client_id == DEFAULT_CLIENT_ID {
             anyhow::bail!("Please set Google Client ID in settings");
         }
         
-        let scopes = "https://www.googleapis.com/auth/drive.file";
-        let redirect_uri = urlencoding::encode(REDIRECT_URI);
-        let scope = urlencoding::encode(scopes);
+        // A Shared Drive given by ID is used as is, so only a name needs
+        // the broader scope
+        let mut scopes = "https://www.googleapis.com/auth/drive.file".to_string();
+        let shared_drive = crate::config::load_config()?
+            .destination(&self.destination)?
+            .shared_drive;
+        if shared_drive.is_some_and(|drive| !drive.is_empty() && !looks_like_drive_id(&drive)) {
+            scopes = format!("{} {}", scopes, SHARED_DRIVE_LOOKUP_SCOPE);
+        }
+        let redirect_uri = urlencoding::encode(redirect_uri);
+        let scope = urlencoding::encode(&scopes);
         
         let url = format!(
-            "{}?client_id={}&redirect_uri={}&response_type=code&scope={}&access_type=offline&prompt=consent",
-            GOOGLE_OAUTH_AUTH_URL, client_id, redirect_uri, scope
+            "{}?client_id={}&redirect_uri={}&response_type=code&scope={}&access_type=offline&prompt=consent\
+             &code_challenge={}&code_challenge_method=S256&state={}",
+            GOOGLE_OAUTH_AUTH_URL, client_id, redirect_uri, scope,
+            urlencoding::encode(code_challenge), urlencoding::encode(state)
         );
         
         Ok(url)
     }
     
-    pub async fn handle_oauth_callback(&mut self, code: &str) -> Result<()> {
-        self.exchange_code_for_token(code).await
-    }
-
-    pub async fn exchange_code_for_token(&mut self, code: &str) -> Result<()> {
+    /// The OAuth client this destination signs in with: its own client ID and
+    /// secret when it has a client ID set, the top-level ones otherwise.
+    fn client_credentials(&self) -> Result<(String, String)> {
         let config = crate::config::load_config()?;
-        let client_id = config.client_id
+        let destination = config.destination(&self.destination)?;
+        let (client_id, secret_key) = match destination.client_id {
+            Some(client_id) => (Some(client_id), crate::config::destination_key(crate::credentials::CLIENT_SECRET, &self.destination)),
+            None => (config.client_id, crate::credentials::CLIENT_SECRET.to_string()),
+        };
+        
+        let client_id = client_id
             .or_else(|| std::env::var("GOOGLE_CLIENT_ID").ok())
             .unwrap_or_else(|| DEFAULT_CLIENT_ID.to_string());
-        let client_secret = config.client_secret
+        let client_secret = crate::credentials::get(&secret_key)?
             .or_else(|| std::env::var("GOOGLE_CLIENT_SECRET").ok())
             .unwrap_or_else(|| DEFAULT_CLIENT_SECRET.to_string());
+        Ok((client_id, client_secret))
+    }
+
+    /// Exchanges a code from the pending `oauth` authorization for tokens.
+    pub async fn exchange_code_for_token(&mut self, code: &str, exchange: &crate::oauth::CodeExchange) -> Result<()> {
+        let (client_id, client_secret) = self.client_credentials()?;
         
         let params = [
             ("code", code),
             ("client_id", &client_id),
             ("client_secret", &client_secret),
-            ("redirect_uri", REDIRECT_URI),
+            ("redirect_uri", &exchange.redirect_uri),
             ("grant_type", "authorization_code"),
+            ("code_verifier", &exchange.code_verifier),
         ];
         
         let response = self.client
@@ -105,8 +201,8 @@ impl DriveSync {
         
         self.access_token = Some(token_data.access_token);
         self.refresh_token = token_data.refresh_token;
+        self.token_expires_at = expiry_from(token_data.expires_in);
         
-        // Store tokens securely (in production, use Tauri's secure storage)
         self.save_tokens()?;
         
         Ok(())
@@ -116,13 +212,7 @@ impl DriveSync {
         let refresh_token = self.refresh_token.as_ref()
             .context("No refresh token available")?;
         
-        let config = crate::config::load_config()?;
-        let client_id = config.client_id
-            .or_else(|| std::env::var("GOOGLE_CLIENT_ID").ok())
-            .unwrap_or_else(|| DEFAULT_CLIENT_ID.to_string());
-        let client_secret = config.client_secret
-            .or_else(|| std::env::var("GOOGLE_CLIENT_SECRET").ok())
-            .unwrap_or_else(|| DEFAULT_CLIENT_SECRET.to_string());
+        let (client_id, client_secret) = self.client_credentials()?;
         
         let params = [
             ("refresh_token", refresh_token),
@@ -142,6 +232,11 @@ impl DriveSync {
             .context("Failed to parse token response")?;
         
         self.access_token = Some(token_data.access_token);
+        self.token_expires_at = expiry_from(token_data.expires_in);
+        // Google may rotate the refresh token; keep the old one otherwise
+        if let Some(refresh_token) = token_data.refresh_token {
+            self.refresh_token = Some(refresh_token);
+        }
         self.save_tokens()?;
         
         Ok(())
@@ -156,9 +251,9 @@ impl DriveSync {
             anyhow::bail!("Not authenticated. Please authenticate first.");
         }
         
-        // Try a simple API call to check if token is valid
-        if let Err(_) = self.test_connection().await {
-            // Token might be expired, try to refresh
+        // Refresh ahead of expiry. Tokens saved before expiry was tracked
+        // have none and rely on the 401 handling in `handle_auth_error`.
+        if self.token_expires_soon() {
             if self.refresh_token.is_some() {
                 self.refresh_access_token().await?;
             } else {
@@ -169,23 +264,10 @@ impl DriveSync {
         Ok(())
     }
 
-    async fn test_connection(&self) -> Result<()> {
-        let token = self.access_token.as_ref()
-            .context("No access token")?;
-        
-        let response = self.client
-            .get(&format!("{}/about", GOOGLE_DRIVE_API_BASE))
-            .bearer_auth(token)
-            .query(&[("fields", "user")])
-            .send()
-            .await
-            .context("Failed to test connection")?;
-        
-        if !response.status().is_success() {
-            anyhow::bail!("Connection test failed");
-        }
-        
-        Ok(())
+    fn token_expires_soon(&self) -> bool {
+        self.token_expires_at.is_some_and(|expires_at| {
+            expires_at - chrono::Duration::seconds(TOKEN_REFRESH_MARGIN_SECS) <= Utc::now()
+        })
     }
 
     async fn handle_auth_error(&mut self, response: &reqwest::Response) -> Result<bool> {
@@ -201,17 +283,162 @@ impl DriveSync {
         Ok(false) // No auth error, don't retry
     }
 
+    /// ID of the Shared Drive this destination backs up to, or `None` for My
+    /// Drive. Resolved from the configured ID or name on first use.
+    pub async fn shared_drive_id(&mut self) -> Result<Option<String>> {
+        if let Some(resolved) = &self.shared_drive_id {
+            return Ok(resolved.clone());
+        }
+        
+        let configured = crate::config::load_config()?
+            .destination(&self.destination)?
+            .shared_drive
+            .filter(|drive| !drive.is_empty());
+        let resolved = match configured {
+            Some(drive) => {
+                let drive_id = match self.get_shared_drive(&drive).await? {
+                    Some(drive_id) => drive_id,
+                    None => self.find_shared_drive_by_name(&drive).await?,
+                };
+                crate::logger::log_info(&format!("Using Shared Drive {} for destination {}", drive_id, self.destination));
+                Some(drive_id)
+            }
+            None => None,
+        };
+        self.shared_drive_id = Some(resolved.clone());
+        Ok(resolved)
+    }
+
+    /// Looks a Shared Drive up by ID. Returns `None` if there is no drive
+    /// with that ID, so the value can be tried as a name instead.
+    async fn get_shared_drive(&mut self, drive: &str) -> Result<Option<String>> {
+        if !looks_like_drive_id(drive) {
+            return Ok(None);
+        }
+        
+        for retry_count in 0..MAX_RETRIES {
+            self.ensure_authenticated().await?;
+            let token = self.access_token.as_ref().unwrap();
+            
+            let response = self.client
+                .get(&format!("{}/drives/{}", GOOGLE_DRIVE_API_BASE, drive))
+                .bearer_auth(token)
+                .query(&[("fields", "id")])
+                .send()
+                .await
+                .context("Failed to look up Shared Drive")?;
+            
+            // Check for auth error
+            if self.handle_auth_error(&response).await? {
+                crate::logger::log_info(&format!("Retrying get_shared_drive (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                continue;
+            }
+            
+            match response.status() {
+                reqwest::StatusCode::NOT_FOUND => return Ok(None),
+                // drive.file can't read drive metadata; files calls will
+                // fail clearly enough if the ID is wrong
+                reqwest::StatusCode::FORBIDDEN => return Ok(Some(drive.to_string())),
+                status if !status.is_success() => {
+                    if retry_count < MAX_RETRIES - 1 && status.is_server_error() {
+                        crate::logger::log_warn(&format!("Shared Drive lookup failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                        continue;
+                    }
+                    anyhow::bail!("Failed to look up Shared Drive: {}", status);
+                }
+                _ => {}
+            }
+            
+            let data: serde_json::Value = response.json().await
+                .context("Failed to parse Shared Drive response")?;
+            return Ok(data.get("id")
+                .and_then(|i| i.as_str())
+                .map(|s| s.to_string()));
+        }
+        
+        anyhow::bail!("Max retries ({}) exceeded for get_shared_drive", MAX_RETRIES)
+    }
+
+    /// Finds the one Shared Drive the account can see with this name.
+    async fn find_shared_drive_by_name(&mut self, name: &str) -> Result<String> {
+        for retry_count in 0..MAX_RETRIES {
+            self.ensure_authenticated().await?;
+            let token = self.access_token.as_ref().unwrap();
+            
+            let query = format!("name = '{}'", name.replace("'", "\\'"));
+            let response = self.client
+                .get(&format!("{}/drives", GOOGLE_DRIVE_API_BASE))
+                .bearer_auth(token)
+                .query(&[("q", query.as_str()), ("fields", "drives(id,name)"), ("pageSize", "10")])
+                .send()
+                .await
+                .context("Failed to search for Shared Drive")?;
+            
+            // Check for auth error
+            if self.handle_auth_error(&response).await? {
+                crate::logger::log_info(&format!("Retrying find_shared_drive_by_name (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                continue;
+            }
+            
+            if response.status() == reqwest::StatusCode::FORBIDDEN {
+                anyhow::bail!("Not allowed to list Shared Drives. Authenticate again so Sync Bot can look up '{}', or enter the drive's ID instead.", name);
+            }
+            
+            if !response.status().is_success() {
+                if retry_count < MAX_RETRIES - 1 {
+                    crate::logger::log_warn(&format!("Shared Drive search failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                    continue;
+                }
+                anyhow::bail!("Failed to search for Shared Drive: {}", response.status());
+            }
+            
+            let data: serde_json::Value = response.json().await
+                .context("Failed to parse Shared Drive search response")?;
+            let ids: Vec<&str> = data.get("drives")
+                .and_then(|d| d.as_array())
+                .map(|drives| drives.iter().filter_map(|d| d.get("id").and_then(|i| i.as_str())).collect())
+                .unwrap_or_default();
+            
+            return match ids.as_slice() {
+                [id] => Ok(id.to_string()),
+                [] => anyhow::bail!("No Shared Drive named '{}' found", name),
+                _ => anyhow::bail!("Several Shared Drives are named '{}'; enter the drive's ID instead", name),
+            };
+        }
+        
+        anyhow::bail!("Max retries ({}) exceeded for find_shared_drive_by_name", MAX_RETRIES)
+    }
+
+    /// Query parameters scoping a files.list call to the destination's
+    /// drive. Without them Drive only searches My Drive.
+    async fn list_scope(&mut self) -> Result<Vec<(&'static str, String)>> {
+        Ok(match self.shared_drive_id().await? {
+            Some(drive_id) => vec![
+                ("corpora", "drive".to_string()),
+                ("driveId", drive_id),
+                ("includeItemsFromAllDrives", "true".to_string()),
+                ("supportsAllDrives", "true".to_string()),
+            ],
+            None => Vec::new(),
+        })
+    }
+
     pub async fn find_or_create_folder(&mut self, folder_name: &str) -> Result<String> {
+        let drive_id = self.shared_drive_id().await?;
+        let scope = self.list_scope().await?;
+        
         for retry_count in 0..MAX_RETRIES {
             self.ensure_authenticated().await?;
             let token = self.access_token.as_ref().unwrap();
             
-            // Search for existing folder
-            let query = format!("name='{}' and mimeType='application/vnd.google-apps.folder' and trashed=false", folder_name);
+            // Search for existing folder at the top of the drive
+            let query = format!("name='{}' and mimeType='application/vnd.google-apps.folder' and trashed=false and '{}' in parents",
+                folder_name.replace("'", "\\'"), drive_id.as_deref().unwrap_or("root"));
             let response = self.client
                 .get(&format!("{}/files", GOOGLE_DRIVE_API_BASE))
                 .bearer_auth(token)
-                .query(&[("q", &query), ("fields", &"files(id,name)".to_string())])
+                .query(&[("q", &query), ("fields", &"files(id,name)".to_string()), ("orderBy", &"createdTime".to_string())])
+                .query(&scope)
                 .send()
                 .await
                 .context("Failed to search for folder")?;
@@ -234,6 +461,9 @@ impl DriveSync {
                 .context("Failed to parse folder search response")?;
             
             if let Some(files) = data.get("files").and_then(|f| f.as_array()) {
+                if files.len() > 1 {
+                    crate::logger::log_warn(&format!("{} folders named '{}', using the oldest", files.len(), folder_name));
+                }
                 if let Some(first) = files.first() {
                     if let Some(id) = first.get("id").and_then(|i| i.as_str()) {
                         return Ok(id.to_string());
@@ -241,16 +471,20 @@ impl DriveSync {
                 }
             }
             
-            // Folder doesn't exist, create it
-            let folder_data = serde_json::json!({
+            // Folder doesn't exist, create it; at the root of the Shared Drive if there is one
+            let mut folder_data = serde_json::json!({
                 "name": folder_name,
                 "mimeType": "application/vnd.google-apps.folder"
             });
+            if let Some(drive_id) = &drive_id {
+                folder_data["parents"] = serde_json::json!([drive_id]);
+            }
             
             let token = self.access_token.as_ref().unwrap();
             let response = self.client
                 .post(&format!("{}/files", GOOGLE_DRIVE_API_BASE))
                 .bearer_auth(token)
+                .query(&[SUPPORTS_ALL_DRIVES])
                 .json(&folder_data)
                 .send()
                 .await
@@ -286,6 +520,8 @@ impl DriveSync {
     }
 
     pub async fn find_or_create_subfolder(&mut self, parent_id: &str, folder_name: &str) -> Result<String> {
+        let scope = self.list_scope().await?;
+        
         for retry_count in 0..MAX_RETRIES {
             self.ensure_authenticated().await?;
             let token = self.access_token.as_ref().unwrap();
@@ -297,7 +533,8 @@ impl DriveSync {
             let response = self.client
                 .get(&format!("{}/files", GOOGLE_DRIVE_API_BASE))
                 .bearer_auth(token)
-                .query(&[("q", &query), ("fields", &"files(id,name)".to_string())])
+                .query(&[("q", &query), ("fields", &"files(id,name)".to_string()), ("orderBy", &"createdTime".to_string())])
+                .query(&scope)
                 .send()
                 .await
                 .context("Failed to search for subfolder")?;
@@ -320,6 +557,9 @@ impl DriveSync {
                 .context("Failed to parse subfolder search response")?;
             
             if let Some(files) = data.get("files").and_then(|f| f.as_array()) {
+                if files.len() > 1 {
+                    crate::logger::log_warn(&format!("{} folders named '{}', using the oldest", files.len(), folder_name));
+                }
                 if let Some(first) = files.first() {
                     if let Some(id) = first.get("id").and_then(|i| i.as_str()) {
                         return Ok(id.to_string());
@@ -338,6 +578,7 @@ impl DriveSync {
             let response = self.client
                 .post(&format!("{}/files", GOOGLE_DRIVE_API_BASE))
                 .bearer_auth(token)
+                .query(&[SUPPORTS_ALL_DRIVES])
                 .json(&folder_data)
                 .send()
                 .await
@@ -372,67 +613,262 @@ impl DriveSync {
         anyhow::bail!("Max retries ({}) exceeded for find_or_create_subfolder", MAX_RETRIES)
     }
 
-    pub async fn get_folder_id_for_path(&mut self, root_id: &str, relative_path: &Path) -> Result<String> {
-        let mut current_id = root_id.to_string();
+    /// ID of the backup folder at `path`, e.g. `backups/laptop`, creating any
+    /// missing level. The folder is pinned by ID after the first lookup, so
+    /// renaming or moving it in Drive doesn't split the backups.
+    pub async fn backup_folder(&mut self, path: &str) -> Result<String> {
+        if let Some(folder_id) = self.pinned_folder(path).await? {
+            return Ok(folder_id);
+        }
+        
+        let components = folder_path(path)?;
+        let mut folder_id = self.find_or_create_folder(components[0]).await?;
+        for name in &components[1..] {
+            folder_id = self.find_or_create_subfolder(&folder_id, name).await?;
+        }
+        self.pin_folder(path, &folder_id).await?;
+        Ok(folder_id)
+    }
+
+    /// Like `backup_folder`, but returns `None` instead of creating anything.
+    pub async fn find_backup_folder(&mut self, path: &str) -> Result<Option<String>> {
+        if let Some(folder_id) = self.pinned_folder(path).await? {
+            return Ok(Some(folder_id));
+        }
+        
+        let components = folder_path(path)?;
+        let Some(mut folder_id) = self.find_folder(components[0]).await? else {
+            return Ok(None);
+        };
+        for name in &components[1..] {
+            match self.find_file_in_folder(name, &folder_id).await? {
+                Some(id) => folder_id = id,
+                None => return Ok(None),
+            }
+        }
+        self.pin_folder(path, &folder_id).await?;
+        Ok(Some(folder_id))
+    }
+
+    /// Forgets the pinned backup folder so the next sync looks it up by path again.
+    pub fn forget_backup_folder(destination: &str) -> Result<()> {
+        crate::file_tracker::remove_metadata(&PinnedFolder::metadata_key(destination))
+    }
+
+    /// The pinned folder, if it was pinned for this path and drive and still
+    /// exists. Fails if it is in the trash rather than quietly starting a
+    /// new folder next to it.
+    async fn pinned_folder(&mut self, path: &str) -> Result<Option<String>> {
+        let Some(mut pinned) = PinnedFolder::load(&self.destination) else {
+            return Ok(None);
+        };
+        let drive_id = self.shared_drive_id().await?;
+        if pinned.path != path || pinned.drive_id != drive_id {
+            return Ok(None);
+        }
         
-        for component in relative_path.components() {
-            if let Some(name) = component.as_os_str().to_str() {
-                if name == ".." || name == "/" || name == "." {
+        let Some(status) = self.folder_status(&pinned.folder_id).await? else {
+            crate::logger::log_warn(&format!("Pinned Drive folder '{}' no longer exists, looking it up again", path));
+            Self::forget_backup_folder(&self.destination)?;
+            return Ok(None);
+        };
+        if status.trashed {
+            anyhow::bail!("Drive folder '{}' is in the Drive trash. Restore it from the trash, or reset the Drive folder to start a new one.", path);
+        }
+        if status.parents != pinned.parents || status.name != pinned.name {
+            crate::logger::log_warn(&format!(
+                "Drive folder '{}' was moved or renamed (now '{}'); still backing up to it. Reset the Drive folder to use '{}' instead.",
+                path, status.name, path
+            ));
+            pinned.parents = status.parents;
+            pinned.name = status.name;
+            pinned.save(&self.destination)?;
+        }
+        Ok(Some(pinned.folder_id))
+    }
+
+    async fn pin_folder(&mut self, path: &str, folder_id: &str) -> Result<()> {
+        let status = self.folder_status(folder_id)
+            .await?
+            .with_context(|| format!("Drive folder '{}' disappeared while it was being set up", path))?;
+        let pinned = PinnedFolder {
+            path: path.to_string(),
+            drive_id: self.shared_drive_id().await?,
+            folder_id: folder_id.to_string(),
+            name: status.name,
+            parents: status.parents,
+        };
+        pinned.save(&self.destination)?;
+        crate::logger::log_info(&format!("Pinned Drive folder '{}' as {}", path, folder_id));
+        Ok(())
+    }
+
+    /// Name, parents and trash state of a folder, or `None` if it is gone or
+    /// no longer visible to the app.
+    async fn folder_status(&mut self, folder_id: &str) -> Result<Option<FolderStatus>> {
+        for retry_count in 0..MAX_RETRIES {
+            self.ensure_authenticated().await?;
+            let token = self.access_token.as_ref().unwrap();
+            
+            let response = self.client
+                .get(&format!("{}/files/{}", GOOGLE_DRIVE_API_BASE, folder_id))
+                .bearer_auth(token)
+                .query(&[("fields", "name,trashed,parents"), SUPPORTS_ALL_DRIVES])
+                .send()
+                .await
+                .context("Failed to check Drive folder")?;
+            
+            // Check for auth error
+            if self.handle_auth_error(&response).await? {
+                crate::logger::log_info(&format!("Retrying folder_status (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                continue;
+            }
+            
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            
+            if !response.status().is_success() {
+                if retry_count < MAX_RETRIES - 1 {
+                    crate::logger::log_warn(&format!("Folder check failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
                     continue;
                 }
-                current_id = self.find_or_create_subfolder(&current_id, name).await?;
+                anyhow::bail!("Failed to check Drive folder: {}", response.status());
             }
+            
+            return response.json().await
+                .map(Some)
+                .context("Failed to parse folder metadata");
         }
         
-        Ok(current_id)
+        anyhow::bail!("Max retries ({}) exceeded for folder_status", MAX_RETRIES)
     }
 
-    pub async fn upload_file(&mut self, file_path: &Path, parent_folder_id: &str) -> Result<String> {
-        let file_name = file_path.file_name()
-            .and_then(|n| n.to_str())
-            .context("Invalid file name")?;
-        
-        // Determine MIME type based on file extension
-        let mime_type = file_path.extension()
-            .and_then(|ext| ext.to_str())
-            .map(|ext| match ext.to_lowercase().as_str() {
-                "zip" => "application/zip",
-                "json" => "application/json",
-                "txt" => "text/plain",
-                "html" => "text/html",
-                "css" => "text/css",
-                "js" => "application/javascript",
-                "png" => "image/png",
-                "jpg" | "jpeg" => "image/jpeg",
-                "pdf" => "application/pdf",
-                _ => "application/octet-stream",
-            })
-            .unwrap_or("application/octet-stream");
+    /// Moves a file to the Drive trash. A file that no longer exists counts as trashed.
+    pub async fn trash_file(&mut self, file_id: &str) -> Result<()> {
+        self.update_file_metadata(file_id, &[], serde_json::json!({ "trashed": true }), "trash_file").await
+    }
+
+    /// Permanently deletes a file, bypassing the trash. A file that no longer
+    /// exists counts as deleted.
+    pub async fn delete_file(&mut self, file_id: &str) -> Result<()> {
+        for retry_count in 0..MAX_RETRIES {
+            self.ensure_authenticated().await?;
+            let token = self.access_token.as_ref().unwrap();
+            
+            let response = self.client
+                .delete(&format!("{}/files/{}", GOOGLE_DRIVE_API_BASE, file_id))
+                .bearer_auth(token)
+                .query(&[SUPPORTS_ALL_DRIVES])
+                .send()
+                .await
+                .context("Failed to delete file")?;
+            
+            // Check for auth error
+            if self.handle_auth_error(&response).await? {
+                crate::logger::log_info(&format!("Retrying delete_file (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                continue;
+            }
+            
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                crate::logger::log_warn(&format!("delete_file: file {} no longer exists on Drive", file_id));
+                return Ok(());
+            }
+            
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                if retry_count < MAX_RETRIES - 1 {
+                    crate::logger::log_warn(&format!("Delete failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                    continue;
+                }
+                anyhow::bail!("Failed to delete file: {} - {}", status, error_text);
+            }
+            
+            return Ok(());
+        }
         
-        crate::logger::log_info(&format!("Uploading {} with MIME type: {}", file_name, mime_type));
+        anyhow::bail!("Max retries ({}) exceeded for delete_file", MAX_RETRIES)
+    }
+
+    /// PATCHes a file's metadata. Used for operations where a missing file
+    /// means there is nothing left to do.
+    async fn update_file_metadata(
+        &mut self,
+        file_id: &str,
+        params: &[(&str, &str)],
+        body: serde_json::Value,
+        operation: &str,
+    ) -> Result<()> {
+        for retry_count in 0..MAX_RETRIES {
+            self.ensure_authenticated().await?;
+            let token = self.access_token.as_ref().unwrap();
+            
+            let response = self.client
+                .patch(&format!("{}/files/{}", GOOGLE_DRIVE_API_BASE, file_id))
+                .bearer_auth(token)
+                .query(params)
+                .query(&[SUPPORTS_ALL_DRIVES])
+                .json(&body)
+                .send()
+                .await
+                .with_context(|| format!("Failed to {}", operation))?;
+            
+            // Check for auth error
+            if self.handle_auth_error(&response).await? {
+                crate::logger::log_info(&format!("Retrying {} (attempt {}/{})", operation, retry_count + 1, MAX_RETRIES));
+                continue;
+            }
+            
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                crate::logger::log_warn(&format!("{}: file {} no longer exists on Drive", operation, file_id));
+                return Ok(());
+            }
+            
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                if retry_count < MAX_RETRIES - 1 {
+                    crate::logger::log_warn(&format!("{} failed, retrying (attempt {}/{})", operation, retry_count + 1, MAX_RETRIES));
+                    continue;
+                }
+                anyhow::bail!("Failed to {}: {} - {}", operation, status, error_text);
+            }
+            
+            return Ok(());
+        }
         
-        // Read file once
-        let file_data = fs::read(file_path)
-            .context("Failed to read file")?;
+        anyhow::bail!("Max retries ({}) exceeded for {}", MAX_RETRIES, operation)
+    }
+
+    /// Looks up a non-trashed file by name directly inside `parent_folder_id`.
+    pub async fn find_file_in_folder(&mut self, file_name: &str, parent_folder_id: &str) -> Result<Option<String>> {
+        Ok(self.find_in_folder(file_name, parent_folder_id).await?.map(|file| file.id))
+    }
+
+    /// Like `find_file_in_folder`, returning the file's metadata.
+    async fn find_in_folder(&mut self, file_name: &str, parent_folder_id: &str) -> Result<Option<DriveFile>> {
+        let scope = self.list_scope().await?;
+        let fields = format!("files({})", DRIVE_FILE_FIELDS);
         
         for retry_count in 0..MAX_RETRIES {
             self.ensure_authenticated().await?;
             let token = self.access_token.as_ref().unwrap();
             
-            // Check if file already exists
             let query = format!("name='{}' and '{}' in parents and trashed=false", 
                 file_name.replace("'", "\\'"), parent_folder_id);
             let response = self.client
                 .get(&format!("{}/files", GOOGLE_DRIVE_API_BASE))
                 .bearer_auth(token)
-                .query(&[("q", &query), ("fields", &"files(id)".to_string())])
+                .query(&[("q", &query), ("fields", &fields)])
+                .query(&scope)
                 .send()
                 .await
                 .context("Failed to check for existing file")?;
             
             // Check for auth error
             if self.handle_auth_error(&response).await? {
-                crate::logger::log_info(&format!("Retrying upload_file (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                crate::logger::log_info(&format!("Retrying find_file_in_folder (attempt {}/{})", retry_count + 1, MAX_RETRIES));
                 continue;
             }
             
@@ -447,136 +883,624 @@ impl DriveSync {
             let data: serde_json::Value = response.json().await
                 .context("Failed to parse file check response")?;
             
-            let file_id = if let Some(files) = data.get("files").and_then(|f| f.as_array()) {
-                files.first()
-                    .and_then(|f| f.get("id"))
-                    .and_then(|i| i.as_str())
-                    .map(|s| s.to_string())
-            } else {
-                None
+            return match data.get("files").and_then(|f| f.as_array()).and_then(|files| files.first()) {
+                Some(entry) => serde_json::from_value(entry.clone())
+                    .map(Some)
+                    .context("Unexpected file entry in file check response"),
+                None => Ok(None),
             };
+        }
+        
+        anyhow::bail!("Max retries ({}) exceeded for find_file_in_folder", MAX_RETRIES)
+    }
+
+    /// Looks up a top-level folder by name without creating it, the oldest
+    /// if there are several.
+    pub async fn find_folder(&mut self, folder_name: &str) -> Result<Option<String>> {
+        let drive_id = self.shared_drive_id().await?;
+        let scope = self.list_scope().await?;
+        
+        for retry_count in 0..MAX_RETRIES {
+            self.ensure_authenticated().await?;
+            let token = self.access_token.as_ref().unwrap();
             
-            // Upload or update file
-            if let Some(existing_id) = file_id {
-                // Update existing file
-                let url = format!("https://www.googleapis.com/upload/drive/v3/files/{}?uploadType=media", existing_id);
-                let token = self.access_token.as_ref().unwrap();
-                let response = self.client
-                    .patch(&url)
-                    .bearer_auth(token)
-                    .header("Content-Type", mime_type)
-                    .body(file_data.clone())
-                    .send()
-                    .await
-                    .context("Failed to update file")?;
-                
-                // Check for auth error
-                if self.handle_auth_error(&response).await? {
-                    crate::logger::log_info(&format!("Retrying file update (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+            let query = format!("name='{}' and mimeType='{}' and trashed=false and '{}' in parents",
+                folder_name.replace("'", "\\'"), FOLDER_MIME_TYPE, drive_id.as_deref().unwrap_or("root"));
+            let response = self.client
+                .get(&format!("{}/files", GOOGLE_DRIVE_API_BASE))
+                .bearer_auth(token)
+                .query(&[("q", &query), ("fields", &"files(id)".to_string()), ("orderBy", &"createdTime".to_string())])
+                .query(&scope)
+                .send()
+                .await
+                .context("Failed to search for folder")?;
+            
+            // Check for auth error
+            if self.handle_auth_error(&response).await? {
+                crate::logger::log_info(&format!("Retrying find_folder (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                continue;
+            }
+            
+            if !response.status().is_success() {
+                if retry_count < MAX_RETRIES - 1 {
+                    crate::logger::log_warn(&format!("Search failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
                     continue;
                 }
-                
-                if !response.status().is_success() {
-                    let status = response.status();
-                    let error_text = response.text().await.unwrap_or_default();
-                    if retry_count < MAX_RETRIES - 1 {
-                        crate::logger::log_warn(&format!("File update failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
-                        continue;
-                    }
-                    anyhow::bail!("Failed to update file: {} - {}", status, error_text);
+                anyhow::bail!("Failed to search for folder: {}", response.status());
+            }
+            
+            let data: serde_json::Value = response.json().await
+                .context("Failed to parse folder search response")?;
+            
+            return Ok(data.get("files")
+                .and_then(|f| f.as_array())
+                .and_then(|files| files.first())
+                .and_then(|f| f.get("id"))
+                .and_then(|i| i.as_str())
+                .map(|s| s.to_string()));
+        }
+        
+        anyhow::bail!("Max retries ({}) exceeded for find_folder", MAX_RETRIES)
+    }
+
+    /// Lists the non-trashed files and folders directly inside a folder,
+    /// newest first, following pagination.
+    pub async fn list_folder(&mut self, folder_id: &str) -> Result<Vec<DriveFile>> {
+        let query = format!("'{}' in parents and trashed=false", folder_id);
+        let fields = format!("nextPageToken,files({})", DRIVE_FILE_FIELDS);
+        let scope = self.list_scope().await?;
+        let mut files = Vec::new();
+        let mut page_token: Option<String> = None;
+        
+        loop {
+            let mut page = None;
+            for retry_count in 0..MAX_RETRIES {
+                self.ensure_authenticated().await?;
+                let token = self.access_token.as_ref().unwrap();
+                
+                let mut params = vec![
+                    ("q", query.clone()),
+                    ("fields", fields.clone()),
+                    ("orderBy", "modifiedTime desc".to_string()),
+                    ("pageSize", "1000".to_string()),
+                ];
+                if let Some(page_token) = &page_token {
+                    params.push(("pageToken", page_token.clone()));
                 }
                 
-                return Ok(existing_id);
-            } else {
-                // Create new file using multipart upload
-                let metadata = serde_json::json!({
-                    "name": file_name,
-                    "parents": [parent_folder_id]
-                });
+                let response = self.client
+                    .get(&format!("{}/files", GOOGLE_DRIVE_API_BASE))
+                    .bearer_auth(token)
+                    .query(&params)
+                    .query(&scope)
+                    .send()
+                    .await
+                    .context("Failed to list folder")?;
                 
-                let metadata_part = reqwest::multipart::Part::text(serde_json::to_string(&metadata)?)
-                    .mime_str("application/json; charset=UTF-8")?;
+                // Check for auth error
+                if self.handle_auth_error(&response).await? {
+                    crate::logger::log_info(&format!("Retrying list_folder (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                    continue;
+                }
                 
-                let file_part = reqwest::multipart::Part::bytes(file_data.clone())
-                    .file_name(file_name.to_string())
-                    .mime_str(mime_type)?;
-
-                let form = reqwest::multipart::Form::new()
-                    .part("metadata", metadata_part)
-                    .part("file", file_part);
+                if !response.status().is_success() {
+                    if retry_count < MAX_RETRIES - 1 {
+                        crate::logger::log_warn(&format!("Folder listing failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                        continue;
+                    }
+                    anyhow::bail!("Failed to list folder: {}", response.status());
+                }
                 
-                let url = "https://www.googleapis.com/upload/drive/v3/files?uploadType=multipart";
+                let data: serde_json::Value = response.json().await
+                    .context("Failed to parse folder listing")?;
+                page = Some(data);
+                break;
+            }
+            
+            let data = page.ok_or_else(|| anyhow::anyhow!("Max retries ({}) exceeded for list_folder", MAX_RETRIES))?;
+            if let Some(entries) = data.get("files").and_then(|f| f.as_array()) {
+                for entry in entries {
+                    let file: DriveFile = serde_json::from_value(entry.clone())
+                        .context("Unexpected file entry in folder listing")?;
+                    files.push(file);
+                }
+            }
+            
+            page_token = data.get("nextPageToken")
+                .and_then(|t| t.as_str())
+                .map(|t| t.to_string());
+            if page_token.is_none() {
+                return Ok(files);
+            }
+        }
+    }
+
+    /// Downloads a file's content to `dest_path`, returning the number of bytes written.
+    ///
+    /// The body is streamed to `<dest_path>.partial` and renamed into place once
+    /// complete, so a failed or cancelled download never leaves a truncated file.
+    pub async fn download_file(&mut self, file_id: &str, dest_path: &Path, hooks: TransferHooks) -> Result<u64> {
+        let url = format!("{}/files/{}?supportsAllDrives=true", GOOGLE_DRIVE_API_BASE, file_id);
+        self.download_media(&url, dest_path, hooks).await
+    }
+
+    /// Downloads the content of one revision of a file, like `download_file`.
+    pub async fn download_revision(&mut self, file_id: &str, revision_id: &str, dest_path: &Path, hooks: TransferHooks) -> Result<u64> {
+        let url = format!("{}/files/{}/revisions/{}", GOOGLE_DRIVE_API_BASE, file_id, revision_id);
+        self.download_media(&url, dest_path, hooks).await
+    }
+
+    /// Lists the stored revisions of a file, oldest first as Drive returns them.
+    pub async fn list_revisions(&mut self, file_id: &str) -> Result<Vec<Revision>> {
+        let fields = format!("nextPageToken,revisions({})", DRIVE_REVISION_FIELDS);
+        let mut revisions = Vec::new();
+        let mut page_token: Option<String> = None;
+        
+        loop {
+            let mut page = None;
+            for retry_count in 0..MAX_RETRIES {
+                self.ensure_authenticated().await?;
                 let token = self.access_token.as_ref().unwrap();
+                
+                let mut params = vec![
+                    ("fields", fields.clone()),
+                    ("pageSize", "1000".to_string()),
+                ];
+                if let Some(page_token) = &page_token {
+                    params.push(("pageToken", page_token.clone()));
+                }
+                
                 let response = self.client
-                    .post(url)
+                    .get(&format!("{}/files/{}/revisions", GOOGLE_DRIVE_API_BASE, file_id))
                     .bearer_auth(token)
-                    .multipart(form)
+                    .query(&params)
                     .send()
                     .await
-                    .context("Failed to upload file")?;
+                    .context("Failed to list revisions")?;
                 
                 // Check for auth error
                 if self.handle_auth_error(&response).await? {
-                    crate::logger::log_info(&format!("Retrying file upload (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                    crate::logger::log_info(&format!("Retrying list_revisions (attempt {}/{})", retry_count + 1, MAX_RETRIES));
                     continue;
                 }
                 
-                // Check status and handle error or success
-                let status = response.status();
-                if status.is_success() {
-                    let data: serde_json::Value = response.json().await
-                        .context("Failed to parse upload response")?;
-                    
-                    let id = data.get("id")
-                        .and_then(|v| v.as_str())
-                        .ok_or_else(|| anyhow::anyhow!("No 'id' field in upload response: {:?}", data))?;
-                    
-                    return Ok(id.to_string());
-                } else {
-                    let status_code = status.as_u16();
-                    let error_text = response.text().await.unwrap_or_default();
+                if !response.status().is_success() {
                     if retry_count < MAX_RETRIES - 1 {
-                        crate::logger::log_warn(&format!("File upload failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                        crate::logger::log_warn(&format!("Revision listing failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
                         continue;
                     }
-                    anyhow::bail!("Failed to upload file: {} - {}", status_code, error_text);
+                    anyhow::bail!("Failed to list revisions: {}", response.status());
+                }
+                
+                let data: serde_json::Value = response.json().await
+                    .context("Failed to parse revision listing")?;
+                page = Some(data);
+                break;
+            }
+            
+            let data = page.ok_or_else(|| anyhow::anyhow!("Max retries ({}) exceeded for list_revisions", MAX_RETRIES))?;
+            if let Some(entries) = data.get("revisions").and_then(|r| r.as_array()) {
+                for entry in entries {
+                    let revision: Revision = serde_json::from_value(entry.clone())
+                        .context("Unexpected entry in revision listing")?;
+                    revisions.push(revision);
+                }
+            }
+            
+            page_token = data.get("nextPageToken")
+                .and_then(|t| t.as_str())
+                .map(|t| t.to_string());
+            if page_token.is_none() {
+                return Ok(revisions);
+            }
+        }
+    }
+
+    /// Marks a revision to be kept forever, or lets Drive expire it again.
+    /// Drive otherwise purges old revisions of a file after 30 days or 100 revisions.
+    pub async fn set_revision_keep_forever(&mut self, file_id: &str, revision_id: &str, keep_forever: bool) -> Result<()> {
+        for retry_count in 0..MAX_RETRIES {
+            self.ensure_authenticated().await?;
+            let token = self.access_token.as_ref().unwrap();
+            
+            let response = self.client
+                .patch(&format!("{}/files/{}/revisions/{}", GOOGLE_DRIVE_API_BASE, file_id, revision_id))
+                .bearer_auth(token)
+                .json(&serde_json::json!({ "keepForever": keep_forever }))
+                .send()
+                .await
+                .context("Failed to update revision")?;
+            
+            // Check for auth error
+            if self.handle_auth_error(&response).await? {
+                crate::logger::log_info(&format!("Retrying set_revision_keep_forever (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                continue;
+            }
+            
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                if retry_count < MAX_RETRIES - 1 && status.is_server_error() {
+                    crate::logger::log_warn(&format!("Revision update failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                    continue;
                 }
+                anyhow::bail!("Failed to update revision: {} - {}", status, error_text);
             }
+            
+            return Ok(());
         }
         
-        anyhow::bail!("Max retries ({}) exceeded for upload_file", MAX_RETRIES)
+        anyhow::bail!("Max retries ({}) exceeded for set_revision_keep_forever", MAX_RETRIES)
     }
 
-    fn save_tokens(&self) -> Result<()> {
-        // In production, use Tauri's secure storage
-        // For now, save to a file (not secure, but works)
-        let data_dir = crate::config::get_data_dir()?;
-        let token_file = data_dir.join("tokens.json");
+    /// Permanently deletes a revision. Drive refuses to delete the head revision.
+    pub async fn delete_revision(&mut self, file_id: &str, revision_id: &str) -> Result<()> {
+        for retry_count in 0..MAX_RETRIES {
+            self.ensure_authenticated().await?;
+            let token = self.access_token.as_ref().unwrap();
+            
+            let response = self.client
+                .delete(&format!("{}/files/{}/revisions/{}", GOOGLE_DRIVE_API_BASE, file_id, revision_id))
+                .bearer_auth(token)
+                .send()
+                .await
+                .context("Failed to delete revision")?;
+            
+            // Check for auth error
+            if self.handle_auth_error(&response).await? {
+                crate::logger::log_info(&format!("Retrying delete_revision (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                continue;
+            }
+            
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                crate::logger::log_warn(&format!("delete_revision: revision {} no longer exists", revision_id));
+                return Ok(());
+            }
+            
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                if retry_count < MAX_RETRIES - 1 && status.is_server_error() {
+                    crate::logger::log_warn(&format!("Revision delete failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                    continue;
+                }
+                anyhow::bail!("Failed to delete revision: {} - {}", status, error_text);
+            }
+            
+            return Ok(());
+        }
+        
+        anyhow::bail!("Max retries ({}) exceeded for delete_revision", MAX_RETRIES)
+    }
+
+    /// Streams `?alt=media` content from `url` to `<dest_path>.partial` and
+    /// renames it into place once complete.
+    async fn download_media(&mut self, url: &str, dest_path: &Path, hooks: TransferHooks) -> Result<u64> {
+        for retry_count in 0..MAX_RETRIES {
+            if hooks.is_cancelled() {
+                anyhow::bail!("Download cancelled");
+            }
+            
+            self.ensure_authenticated().await?;
+            let token = self.access_token.as_ref().unwrap();
+            
+            let response = self.client
+                .get(url)
+                .bearer_auth(token)
+                .query(&[("alt", "media")])
+                .send()
+                .await
+                .context("Failed to download file")?;
+            
+            // Check for auth error
+            if self.handle_auth_error(&response).await? {
+                crate::logger::log_info(&format!("Retrying download (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                continue;
+            }
+            
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                if retry_count < MAX_RETRIES - 1 && status.is_server_error() {
+                    crate::logger::log_warn(&format!("Download failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                    continue;
+                }
+                anyhow::bail!("Failed to download file: {} - {}", status, error_text);
+            }
+            
+            match crate::storage::save_download(response, dest_path, &hooks).await? {
+                crate::storage::Download::Complete(received) => return Ok(received),
+                crate::storage::Download::Interrupted(e) if retry_count < MAX_RETRIES - 1 => {
+                    crate::logger::log_warn(&format!("Download interrupted ({}), retrying (attempt {}/{})", e, retry_count + 1, MAX_RETRIES));
+                }
+                crate::storage::Download::Interrupted(e) => {
+                    return Err(anyhow::Error::new(e).context("Download interrupted"));
+                }
+            }
+        }
         
+        anyhow::bail!("Max retries ({}) exceeded for download", MAX_RETRIES)
+    }
+
+    /// Uploads a file as `file_name` with the Drive resumable upload protocol,
+    /// replacing the content of a same-named file in the folder if there is one.
+    ///
+    /// The file is streamed from disk in `RESUMABLE_CHUNK_SIZE` pieces. The
+    /// session URI is persisted so an upload interrupted by a network error or
+    /// an app restart continues from the last byte Drive acknowledged.
+    pub async fn upload_file(
+        &mut self,
+        file_path: &Path,
+        file_name: &str,
+        parent_folder_id: &str,
+        hooks: TransferHooks,
+    ) -> Result<String> {
+        let mime_type = mime_type_for(file_path);
+        let file_size = fs::metadata(file_path)
+            .context("Failed to read file metadata")?
+            .len();
+        let content_hash = crate::file_tracker::calculate_file_hash(file_path)?;
+        
+        crate::logger::log_info(&format!("Uploading {} ({} bytes) with MIME type: {}", file_name, file_size, mime_type));
+        
+        // Only resume a session that was started for this exact file content
+        let mut session = UploadSession::load(file_path)
+            .filter(|s| s.parent_id == parent_folder_id && s.size == file_size && s.sha256 == content_hash);
+        if session.is_some() {
+            crate::logger::log_info("Resuming previous upload session");
+        }
+        
+        let mut failures = 0;
+        let mut committed = 0;
+        loop {
+            if hooks.is_cancelled() {
+                anyhow::bail!("Upload cancelled");
+            }
+            
+            let current = match session.take() {
+                Some(existing) => existing,
+                None => {
+                    let created = self.start_upload_session(file_name, mime_type, parent_folder_id, file_size, &content_hash).await?;
+                    created.save(file_path);
+                    created
+                }
+            };
+            
+            let before = committed;
+            match self.send_upload_chunks(&current, file_path, &hooks, &mut committed).await {
+                Ok(Some(file_id)) => {
+                    UploadSession::clear(file_path);
+                    return Ok(file_id);
+                }
+                Ok(None) => {
+                    crate::logger::log_warn("Upload session expired, starting a new one");
+                    UploadSession::clear(file_path);
+                    committed = 0;
+                }
+                Err(e) => {
+                    if hooks.is_cancelled() {
+                        return Err(e);
+                    }
+                    // Only give up after repeated failures that made no progress
+                    failures = if committed > before { 1 } else { failures + 1 };
+                    if failures >= MAX_RETRIES {
+                        return Err(e);
+                    }
+                    crate::logger::log_warn(&format!("Upload interrupted at {} of {} bytes, resuming (attempt {}/{}): {}", committed, file_size, failures, MAX_RETRIES, e));
+                    session = Some(current);
+                    tokio::time::sleep(std::time::Duration::from_secs(2u64.pow(failures))).await;
+                }
+            }
+        }
+    }
+
+    async fn start_upload_session(
+        &mut self,
+        file_name: &str,
+        mime_type: &str,
+        parent_folder_id: &str,
+        file_size: u64,
+        content_hash: &str,
+    ) -> Result<UploadSession> {
+        let existing_id = self.find_file_in_folder(file_name, parent_folder_id).await?;
+        
+        for retry_count in 0..MAX_RETRIES {
+            self.ensure_authenticated().await?;
+            let token = self.access_token.as_ref().unwrap();
+            
+            // Updating an existing file keeps its ID (and adds a Drive revision)
+            let request = match &existing_id {
+                Some(id) => self.client
+                    .patch(&format!("{}/files/{}?uploadType=resumable", GOOGLE_DRIVE_UPLOAD_BASE, id))
+                    .json(&serde_json::json!({})),
+                None => self.client
+                    .post(&format!("{}/files?uploadType=resumable", GOOGLE_DRIVE_UPLOAD_BASE))
+                    .json(&serde_json::json!({
+                        "name": file_name,
+                        "parents": [parent_folder_id]
+                    })),
+            };
+            
+            let response = request
+                .bearer_auth(token)
+                .query(&[SUPPORTS_ALL_DRIVES])
+                .header("X-Upload-Content-Type", mime_type)
+                .header("X-Upload-Content-Length", file_size)
+                .send()
+                .await
+                .context("Failed to start upload session")?;
+            
+            // Check for auth error
+            if self.handle_auth_error(&response).await? {
+                crate::logger::log_info(&format!("Retrying upload session start (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                continue;
+            }
+            
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                if retry_count < MAX_RETRIES - 1 {
+                    crate::logger::log_warn(&format!("Upload session start failed, retrying (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                    continue;
+                }
+                anyhow::bail!("Failed to start upload session: {} - {}", status, error_text);
+            }
+            
+            let uri = response.headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .context("No session URI in upload session response")?;
+            
+            return Ok(UploadSession {
+                uri: uri.to_string(),
+                parent_id: parent_folder_id.to_string(),
+                size: file_size,
+                sha256: content_hash.to_string(),
+                created_at: now_secs(),
+            });
+        }
+        
+        anyhow::bail!("Max retries ({}) exceeded for start_upload_session", MAX_RETRIES)
+    }
+
+    /// Sends the remaining chunks of `session`, starting from the offset Drive
+    /// reports. Returns the file ID once complete, or `None` if Drive no longer
+    /// knows the session.
+    async fn send_upload_chunks(
+        &mut self,
+        session: &UploadSession,
+        file_path: &Path,
+        hooks: &TransferHooks,
+        committed: &mut u64,
+    ) -> Result<Option<String>> {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+        
+        let total = session.size;
+        let mut offset = match self.query_upload_status(session).await? {
+            UploadStatus::Incomplete(offset) => offset,
+            UploadStatus::Complete(file_id) => return Ok(Some(file_id)),
+            UploadStatus::Expired => return Ok(None),
+        };
+        *committed = offset;
+        hooks.report(offset, total);
+        
+        let mut file = tokio::fs::File::open(file_path).await
+            .context("Failed to open file for upload")?;
+        let mut buffer = vec![0u8; RESUMABLE_CHUNK_SIZE];
+        let mut auth_retries = 0;
+        
+        while offset < total {
+            if hooks.is_cancelled() {
+                anyhow::bail!("Upload cancelled");
+            }
+            
+            let len = RESUMABLE_CHUNK_SIZE.min((total - offset) as usize);
+            file.seek(std::io::SeekFrom::Start(offset)).await
+                .context("Failed to seek in upload file")?;
+            file.read_exact(&mut buffer[..len]).await
+                .context("Failed to read upload chunk")?;
+            
+            self.ensure_authenticated().await?;
+            let token = self.access_token.as_ref().unwrap();
+            let response = self.client
+                .put(&session.uri)
+                .bearer_auth(token)
+                .header("Content-Length", len)
+                .header("Content-Range", format!("bytes {}-{}/{}", offset, offset + len as u64 - 1, total))
+                .body(crate::storage::progress_body(buffer[..len].to_vec(), hooks.clone(), offset, total))
+                .send()
+                .await
+                .context("Failed to send upload chunk")?;
+            
+            // Check for auth error; the next iteration re-sends from the acknowledged offset
+            if self.handle_auth_error(&response).await? {
+                auth_retries += 1;
+                if auth_retries >= MAX_RETRIES {
+                    anyhow::bail!("Max retries ({}) exceeded for upload chunk", MAX_RETRIES);
+                }
+                crate::logger::log_info(&format!("Retrying upload chunk (attempt {}/{})", auth_retries, MAX_RETRIES));
+                offset = match self.query_upload_status(session).await? {
+                    UploadStatus::Incomplete(offset) => offset,
+                    UploadStatus::Complete(file_id) => return Ok(Some(file_id)),
+                    UploadStatus::Expired => return Ok(None),
+                };
+                continue;
+            }
+            
+            match parse_upload_response(response).await? {
+                UploadStatus::Incomplete(acknowledged) => {
+                    offset = acknowledged;
+                    auth_retries = 0;
+                }
+                UploadStatus::Complete(file_id) => {
+                    *committed = total;
+                    hooks.report(total, total);
+                    return Ok(Some(file_id));
+                }
+                UploadStatus::Expired => return Ok(None),
+            }
+            *committed = offset;
+            hooks.report(offset, total);
+        }
+        
+        // Every byte is acknowledged but Drive hasn't returned the file yet
+        match self.query_upload_status(session).await? {
+            UploadStatus::Complete(file_id) => Ok(Some(file_id)),
+            UploadStatus::Expired => Ok(None),
+            UploadStatus::Incomplete(offset) => anyhow::bail!("Upload incomplete at {} of {} bytes", offset, total),
+        }
+    }
+
+    /// Asks Drive how much of the session it has received.
+    async fn query_upload_status(&mut self, session: &UploadSession) -> Result<UploadStatus> {
+        for retry_count in 0..MAX_RETRIES {
+            self.ensure_authenticated().await?;
+            let token = self.access_token.as_ref().unwrap();
+            
+            let response = self.client
+                .put(&session.uri)
+                .bearer_auth(token)
+                .header("Content-Length", 0)
+                .header("Content-Range", format!("bytes */{}", session.size))
+                .send()
+                .await
+                .context("Failed to query upload status")?;
+            
+            // Check for auth error
+            if self.handle_auth_error(&response).await? {
+                crate::logger::log_info(&format!("Retrying upload status query (attempt {}/{})", retry_count + 1, MAX_RETRIES));
+                continue;
+            }
+            
+            return parse_upload_response(response).await;
+        }
+        
+        anyhow::bail!("Max retries ({}) exceeded for query_upload_status", MAX_RETRIES)
+    }
+
+    fn save_tokens(&self) -> Result<()> {
         let tokens = serde_json::json!({
             "access_token": self.access_token,
             "refresh_token": self.refresh_token,
+            "expires_at": self.token_expires_at.map(|expires_at| expires_at.to_rfc3339()),
         });
         
-        fs::write(&token_file, serde_json::to_string_pretty(&tokens)?)
-            .context("Failed to save tokens")?;
-        
-        Ok(())
+        crate::credentials::set(&self.tokens_key(), &tokens.to_string())
+            .context("Failed to save tokens")
+    }
+
+    fn tokens_key(&self) -> String {
+        crate::config::destination_key(crate::credentials::OAUTH_TOKENS, &self.destination)
     }
 
     fn load_tokens(&mut self) -> Result<()> {
-        let data_dir = crate::config::get_data_dir()?;
-        let token_file = data_dir.join("tokens.json");
-        
-        if !token_file.exists() {
-            return Ok(());
-        }
-        
-        let content = fs::read_to_string(&token_file)
-            .context("Failed to read token file")?;
+        let content = match crate::credentials::get(&self.tokens_key())? {
+            Some(content) => content,
+            None => return Ok(()),
+        };
         
         let tokens: serde_json::Value = serde_json::from_str(&content)
-            .context("Failed to parse token file")?;
+            .context("Failed to parse stored tokens")?;
         
         self.access_token = tokens.get("access_token")
             .and_then(|v| v.as_str())
@@ -584,23 +1508,26 @@ impl DriveSync {
         self.refresh_token = tokens.get("refresh_token")
             .and_then(|v| v.as_str())
             .map(|s| s.to_string());
+        self.token_expires_at = tokens.get("expires_at")
+            .and_then(|v| v.as_str())
+            .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+            .map(|expires_at| expires_at.with_timezone(&Utc));
         
         Ok(())
     }
 
-    pub fn is_authenticated() -> bool {
-        let data_dir = match crate::config::get_data_dir() {
-            Ok(dir) => dir,
-            Err(_) => return false,
-        };
-        let token_file = data_dir.join("tokens.json");
-        
-        if !token_file.exists() {
-            return false;
-        }
+    /// When the stored access token expires. It is refreshed automatically
+    /// as long as a refresh token is stored.
+    pub fn session_expires_at(destination: &str) -> Option<DateTime<Utc>> {
+        let mut drive_sync = Self::for_destination(destination);
+        drive_sync.load_tokens().ok()?;
+        drive_sync.token_expires_at
+    }
 
+    pub fn is_authenticated(destination: &str) -> bool {
+        let tokens_key = Self::for_destination(destination).tokens_key();
         // We check if we have at least a refresh token, which means we can re-authenticate
-        if let Ok(content) = fs::read_to_string(&token_file) {
+        if let Ok(Some(content)) = crate::credentials::get(&tokens_key) {
             if let Ok(tokens) = serde_json::from_str::<serde_json::Value>(&content) {
                 return tokens.get("refresh_token").map_or(false, |v| !v.is_null());
             }
@@ -609,3 +1536,354 @@ impl DriveSync {
         false
     }
 }
+
+/// Resumable upload session persisted in `app_metadata`, keyed by local path.
+#[derive(Debug, Serialize, Deserialize)]
+struct UploadSession {
+    uri: String,
+    parent_id: String,
+    size: u64,
+    /// SHA-256 of the file the session was started for, so a session is only
+    /// resumed with the exact same bytes
+    sha256: String,
+    created_at: u64,
+}
+
+impl UploadSession {
+    fn metadata_key(file_path: &Path) -> String {
+        format!("upload_session:{}", file_path.to_string_lossy())
+    }
+
+    fn load(file_path: &Path) -> Option<Self> {
+        let value = crate::file_tracker::get_metadata(&Self::metadata_key(file_path)).ok()??;
+        let session: Self = serde_json::from_str(&value).ok()?;
+        if now_secs().saturating_sub(session.created_at) > UPLOAD_SESSION_MAX_AGE_SECS {
+            return None;
+        }
+        Some(session)
+    }
+
+    fn save(&self, file_path: &Path) {
+        let saved = serde_json::to_string(self)
+            .map_err(anyhow::Error::from)
+            .and_then(|value| crate::file_tracker::set_metadata(&Self::metadata_key(file_path), &value));
+        if let Err(e) = saved {
+            crate::logger::log_warn(&format!("Failed to persist upload session: {}", e));
+        }
+    }
+
+    fn clear(file_path: &Path) {
+        let _ = crate::file_tracker::remove_metadata(&Self::metadata_key(file_path));
+    }
+}
+
+impl DriveSync {
+    /// ID of the backup folder, found without creating it.
+    async fn root_id(&mut self) -> Result<Option<String>> {
+        if let Some(id) = self.folder_ids.get("") {
+            return Ok(Some(id.clone()));
+        }
+        let folder = self.backup_folder.clone();
+        let root_id = self.find_backup_folder(&folder).await?;
+        if let Some(id) = &root_id {
+            self.folder_ids.insert(String::new(), id.clone());
+        }
+        Ok(root_id)
+    }
+
+    /// ID of a directory below the backup folder, creating missing levels
+    /// when `create` is set. Every level resolved is cached.
+    async fn dir_id(&mut self, dir: &str, create: bool) -> Result<Option<String>> {
+        if let Some(id) = self.folder_ids.get(dir) {
+            return Ok(Some(id.clone()));
+        }
+        
+        let root_id = if create {
+            self.ensure_container().await?;
+            self.folder_ids[""].clone()
+        } else {
+            match self.root_id().await? {
+                Some(id) => id,
+                None => return Ok(None),
+            }
+        };
+        
+        let mut current_id = root_id;
+        let mut current_path = String::new();
+        for name in dir.split('/').filter(|name| !name.is_empty()) {
+            current_path = crate::storage::join(&current_path, name);
+            if let Some(id) = self.folder_ids.get(&current_path) {
+                current_id = id.clone();
+                continue;
+            }
+            current_id = if create {
+                self.find_or_create_subfolder(&current_id, name).await?
+            } else {
+                match self.find_in_folder(name, &current_id).await? {
+                    Some(folder) if folder.is_folder() => folder.id,
+                    _ => return Ok(None),
+                }
+            };
+            self.folder_ids.insert(current_path.clone(), current_id.clone());
+        }
+        Ok(Some(current_id))
+    }
+
+    async fn find_object(&mut self, path: &str) -> Result<Option<DriveFile>> {
+        let (dir, name) = crate::storage::split(path);
+        match self.dir_id(dir, false).await? {
+            Some(parent_id) => self.find_in_folder(name, &parent_id).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn require_object(&mut self, path: &str) -> Result<DriveFile> {
+        self.find_object(path)
+            .await?
+            .with_context(|| format!("{} not found in Drive folder '{}'", path, self.backup_folder))
+    }
+}
+
+impl DriveFile {
+    fn to_stored(&self, dir: &str) -> StoredObject {
+        StoredObject {
+            id: self.id.clone(),
+            path: crate::storage::join(dir, &self.name),
+            name: self.name.clone(),
+            is_dir: self.is_folder(),
+            size: self.size.as_deref().and_then(|size| size.parse().ok()),
+            modified_time: self.modified_time.clone(),
+            etag: None,
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for DriveSync {
+    fn describe(&self) -> String {
+        format!("Google Drive folder '{}'", self.backup_folder)
+    }
+
+    async fn ensure_container(&mut self) -> Result<()> {
+        if !self.folder_ids.contains_key("") {
+            let folder = self.backup_folder.clone();
+            let root_id = self.backup_folder(&folder).await?;
+            crate::logger::log_info(&format!("Drive folder ID: {}", root_id));
+            self.folder_ids.insert(String::new(), root_id);
+        }
+        Ok(())
+    }
+
+    async fn put(&mut self, local: &Path, path: &str, hooks: TransferHooks) -> Result<StoredObject> {
+        let (dir, name) = crate::storage::split(path);
+        let parent_id = self.dir_id(dir, true)
+            .await?
+            .context("Failed to create Drive folder")?;
+        let id = self.upload_file(local, name, &parent_id, hooks).await?;
+        Ok(StoredObject {
+            id,
+            path: path.to_string(),
+            name: name.to_string(),
+            is_dir: false,
+            size: fs::metadata(local).ok().map(|metadata| metadata.len()),
+            modified_time: Some(Utc::now().to_rfc3339()),
+            etag: None,
+        })
+    }
+
+    async fn get(&mut self, path: &str, local: &Path, hooks: TransferHooks) -> Result<u64> {
+        let file = self.require_object(path).await?;
+        self.download_file(&file.id, local, hooks).await
+    }
+
+    async fn list(&mut self, dir: &str) -> Result<Vec<StoredObject>> {
+        let Some(folder_id) = self.dir_id(dir, false).await? else {
+            return Ok(Vec::new());
+        };
+        Ok(self.list_folder(&folder_id)
+            .await?
+            .iter()
+            .map(|file| file.to_stored(dir))
+            .collect())
+    }
+
+    async fn stat(&mut self, path: &str) -> Result<Option<StoredObject>> {
+        let (dir, _) = crate::storage::split(path);
+        Ok(self.find_object(path).await?.map(|file| file.to_stored(dir)))
+    }
+
+    async fn delete(&mut self, path: &str) -> Result<()> {
+        match self.find_object(path).await? {
+            Some(file) => self.delete_file(&file.id).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let file = self.require_object(from).await?;
+        let (from_dir, _) = crate::storage::split(from);
+        let (to_dir, to_name) = crate::storage::split(to);
+        let from_parent = self.dir_id(from_dir, false)
+            .await?
+            .context("Source folder disappeared")?;
+        let to_parent = self.dir_id(to_dir, true)
+            .await?
+            .context("Failed to create Drive folder")?;
+        
+        let params: Vec<(&str, &str)> = if from_parent == to_parent {
+            Vec::new()
+        } else {
+            vec![("addParents", to_parent.as_str()), ("removeParents", from_parent.as_str())]
+        };
+        let body = if file.name == to_name {
+            serde_json::json!({})
+        } else {
+            serde_json::json!({ "name": to_name })
+        };
+        self.update_file_metadata(&file.id, &params, body, "rename").await
+    }
+
+    async fn trash(&mut self, path: &str) -> Result<()> {
+        match self.find_object(path).await? {
+            Some(file) => self.trash_file(&file.id).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn list_revisions(&mut self, path: &str) -> Result<Vec<Revision>> {
+        let file = self.require_object(path).await?;
+        DriveSync::list_revisions(self, &file.id).await
+    }
+
+    async fn get_revision(&mut self, path: &str, revision_id: &str, local: &Path, hooks: TransferHooks) -> Result<u64> {
+        let file = self.require_object(path).await?;
+        self.download_revision(&file.id, revision_id, local, hooks).await
+    }
+
+    async fn keep_revision(&mut self, path: &str, revision_id: &str, keep_forever: bool) -> Result<()> {
+        let file = self.require_object(path).await?;
+        self.set_revision_keep_forever(&file.id, revision_id, keep_forever).await
+    }
+
+    async fn delete_revision(&mut self, path: &str, revision_id: &str) -> Result<()> {
+        let file = self.require_object(path).await?;
+        DriveSync::delete_revision(self, &file.id, revision_id).await
+    }
+}
+
+/// Backup folder resolved for a destination, persisted in `app_metadata`.
+/// Only used while the configured path and Shared Drive still match.
+#[derive(Debug, Serialize, Deserialize)]
+struct PinnedFolder {
+    path: String,
+    drive_id: Option<String>,
+    folder_id: String,
+    /// Name and parents when pinned or last checked, to notice a move
+    name: String,
+    parents: Vec<String>,
+}
+
+impl PinnedFolder {
+    fn metadata_key(destination: &str) -> String {
+        crate::config::destination_key("drive_folder", destination)
+    }
+
+    fn load(destination: &str) -> Option<Self> {
+        let value = crate::file_tracker::get_metadata(&Self::metadata_key(destination)).ok()??;
+        serde_json::from_str(&value).ok()
+    }
+
+    fn save(&self, destination: &str) -> Result<()> {
+        let value = serde_json::to_string(self)?;
+        crate::file_tracker::set_metadata(&Self::metadata_key(destination), &value)
+    }
+}
+
+enum UploadStatus {
+    /// Drive has everything before this byte offset
+    Incomplete(u64),
+    Complete(String),
+    Expired,
+}
+
+async fn parse_upload_response(response: reqwest::Response) -> Result<UploadStatus> {
+    let status = response.status();
+    
+    // 308 "Resume Incomplete"; the Range header is absent when nothing was stored yet
+    if status.as_u16() == 308 {
+        let offset = response.headers()
+            .get(reqwest::header::RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|range| range.rsplit('-').next())
+            .and_then(|last| last.parse::<u64>().ok())
+            .map(|last| last + 1)
+            .unwrap_or(0);
+        return Ok(UploadStatus::Incomplete(offset));
+    }
+    
+    if status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::GONE {
+        return Ok(UploadStatus::Expired);
+    }
+    
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        anyhow::bail!("Upload request failed: {} - {}", status, error_text);
+    }
+    
+    let data: serde_json::Value = response.json().await
+        .context("Failed to parse upload response")?;
+    let id = data.get("id")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("No 'id' field in upload response: {:?}", data))?;
+    
+    Ok(UploadStatus::Complete(id.to_string()))
+}
+
+fn mime_type_for(file_path: &Path) -> &'static str {
+    file_path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| match ext.to_lowercase().as_str() {
+            "zip" => "application/zip",
+            "json" => "application/json",
+            "txt" => "text/plain",
+            "html" => "text/html",
+            "css" => "text/css",
+            "js" => "application/javascript",
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            "pdf" => "application/pdf",
+            _ => "application/octet-stream",
+        })
+        .unwrap_or("application/octet-stream")
+}
+
+/// Splits a `drive_folder` path like `backups/laptop` into folder names.
+fn folder_path(path: &str) -> Result<Vec<&str>> {
+    let components: Vec<&str> = path.split('/')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+    if components.is_empty() {
+        anyhow::bail!("The Drive folder path is empty");
+    }
+    Ok(components)
+}
+
+/// Shared Drive IDs are 19 characters starting with `0A`; anything else is
+/// taken to be a drive name.
+fn looks_like_drive_id(value: &str) -> bool {
+    value.len() == 19
+        && value.starts_with("0A")
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn expiry_from(expires_in: Option<u64>) -> Option<DateTime<Utc>> {
+    expires_in.map(|secs| Utc::now() + chrono::Duration::seconds(secs as i64))
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
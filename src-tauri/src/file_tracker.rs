@@ -7,6 +7,40 @@ use std::time::SystemTime;
 use anyhow::{Result, Context};
 use serde::{Deserialize, Serialize};
 
+/// A source file uploaded in mirror mode and where its remote copy lives.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MirrorFile {
+    pub path: String,
+    pub destination: String,
+    /// `/`-separated path relative to the backup folder
+    pub remote_path: String,
+    /// The backend's ID for the copy (a Drive file ID, otherwise its path)
+    pub remote_id: String,
+    /// Directory of `remote_path`; a Drive folder ID in rows from older versions
+    pub parent_id: String,
+}
+
+/// A tracked file or folder and the destination it is backed up to.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrackedPath {
+    pub path: String,
+    pub destination: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncRunRecord {
+    /// `None` for runs that failed before any destination was synced
+    pub destination: Option<String>,
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub trigger: String,
+    /// One of "success", "failed" or "cancelled"
+    pub status: String,
+    pub files_staged: u64,
+    pub uploaded: bool,
+    pub message: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileMetadata {
     pub path: String,
@@ -49,7 +83,8 @@ pub fn init_database() -> Result<()> {
             id INTEGER PRIMARY KEY AUTOINCREMENT,
             path TEXT NOT NULL UNIQUE,
             is_directory INTEGER NOT NULL,
-            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            destination TEXT NOT NULL DEFAULT 'default'
         )",
         [],
     )?;
@@ -62,6 +97,49 @@ pub fn init_database() -> Result<()> {
         [],
     )?;
 
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS mirror_files (
+            path TEXT PRIMARY KEY,
+            remote_path TEXT NOT NULL,
+            remote_id TEXT NOT NULL,
+            parent_id TEXT NOT NULL,
+            synced_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            destination TEXT NOT NULL DEFAULT 'default'
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            started_at INTEGER NOT NULL,
+            finished_at INTEGER NOT NULL,
+            trigger TEXT NOT NULL,
+            status TEXT NOT NULL,
+            files_staged INTEGER NOT NULL,
+            uploaded INTEGER NOT NULL,
+            message TEXT,
+            destination TEXT
+        )",
+        [],
+    )?;
+
+    // Databases created before destinations existed; their rows all belong
+    // to the default destination
+    add_column_if_missing(&conn, "tracked_paths", "destination", "TEXT NOT NULL DEFAULT 'default'")?;
+    add_column_if_missing(&conn, "mirror_files", "destination", "TEXT NOT NULL DEFAULT 'default'")?;
+    add_column_if_missing(&conn, "sync_history", "destination", "TEXT")?;
+
+    Ok(())
+}
+
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt.query_map([], |row| row.get::<_, String>(1))?
+        .collect::<SqlResult<Vec<String>>>()?;
+    if !columns.iter().any(|existing| existing == column) {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
     Ok(())
 }
 
@@ -166,13 +244,14 @@ pub fn has_file_changed(path: &Path) -> Result<bool> {
     Ok(true)
 }
 
-pub fn get_all_files_to_sync() -> Result<Vec<PathBuf>> {
+/// Every file below the paths tracked for `destination`.
+pub fn get_files_to_sync(destination: &str) -> Result<Vec<PathBuf>> {
     let conn = get_connection()?;
     let mut stmt = conn.prepare(
-        "SELECT path FROM tracked_paths"
+        "SELECT path FROM tracked_paths WHERE destination = ?"
     )?;
     
-    let rows = stmt.query_map([], |row| {
+    let rows = stmt.query_map([destination], |row| {
         Ok(PathBuf::from(row.get::<_, String>(0)?))
     })?;
     
@@ -224,19 +303,179 @@ pub fn mark_file_synced(path: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn add_tracked_path(path: &Path) -> Result<()> {
+pub fn remove_file_metadata(path: &Path) -> Result<()> {
+    let conn = get_connection()?;
+    let path_str = path.to_string_lossy().to_string();
+    
+    conn.execute(
+        "DELETE FROM file_metadata WHERE path = ?1",
+        rusqlite::params![path_str],
+    )?;
+    
+    Ok(())
+}
+
+/// Forgets the sync state of every file at or below `path`, so they are
+/// uploaded again, e.g. after the path moved to another destination.
+pub fn remove_file_metadata_under(path: &Path) -> Result<()> {
+    let conn = get_connection()?;
+    let mut stmt = conn.prepare("SELECT path FROM file_metadata")?;
+    let paths = stmt.query_map([], |row| row.get::<_, String>(0))?
+        .collect::<SqlResult<Vec<String>>>()?;
+    
+    for stored in paths.iter().filter(|stored| Path::new(stored).starts_with(path)) {
+        conn.execute(
+            "DELETE FROM file_metadata WHERE path = ?1",
+            rusqlite::params![stored],
+        )?;
+    }
+    
+    Ok(())
+}
+
+/// Deletes metadata rows for files below `roots` that no longer exist on
+/// disk, returning how many were removed. Roots that are missing themselves
+/// are skipped: an unmounted drive is not a deletion, and its files should not
+/// be hashed and uploaded again once it is back.
+pub fn prune_file_metadata(roots: &[PathBuf]) -> Result<usize> {
+    let present: Vec<&PathBuf> = roots.iter().filter(|root| root.exists()).collect();
+    let conn = get_connection()?;
+    let mut stmt = conn.prepare("SELECT path FROM file_metadata")?;
+    let paths = stmt.query_map([], |row| row.get::<_, String>(0))?
+        .collect::<SqlResult<Vec<String>>>()?;
+    
+    let mut pruned = 0;
+    let stale = paths.iter().filter(|path| {
+        let path = Path::new(path);
+        present.iter().any(|root| path.starts_with(root)) && !path.exists()
+    });
+    for path in stale {
+        pruned += conn.execute(
+            "DELETE FROM file_metadata WHERE path = ?1",
+            rusqlite::params![path],
+        )?;
+    }
+    
+    Ok(pruned)
+}
+
+pub fn record_mirror_file(mirror_file: &MirrorFile) -> Result<()> {
+    let conn = get_connection()?;
+    conn.execute(
+        "INSERT OR REPLACE INTO mirror_files (path, remote_path, remote_id, parent_id, destination)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![
+            mirror_file.path,
+            mirror_file.remote_path,
+            mirror_file.remote_id,
+            mirror_file.parent_id,
+            mirror_file.destination,
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn get_mirror_files(destination: &str) -> Result<Vec<MirrorFile>> {
+    let conn = get_connection()?;
+    let mut stmt = conn.prepare(
+        "SELECT path, remote_path, remote_id, parent_id, destination FROM mirror_files
+         WHERE destination = ? ORDER BY path"
+    )?;
+    
+    let rows = stmt.query_map([destination], |row| {
+        Ok(MirrorFile {
+            path: row.get(0)?,
+            remote_path: row.get(1)?,
+            remote_id: row.get(2)?,
+            parent_id: row.get(3)?,
+            destination: row.get(4)?,
+        })
+    })?;
+    
+    let mut files = Vec::new();
+    for row in rows {
+        files.push(row?);
+    }
+    
+    Ok(files)
+}
+
+pub fn remove_mirror_file(path: &str) -> Result<()> {
+    let conn = get_connection()?;
+    conn.execute(
+        "DELETE FROM mirror_files WHERE path = ?1",
+        rusqlite::params![path],
+    )?;
+    Ok(())
+}
+
+pub fn remove_mirror_files(destination: &str) -> Result<()> {
+    let conn = get_connection()?;
+    conn.execute(
+        "DELETE FROM mirror_files WHERE destination = ?1",
+        rusqlite::params![destination],
+    )?;
+    Ok(())
+}
+
+pub fn add_tracked_path(path: &Path, destination: &str) -> Result<()> {
+    ensure_no_overlap(path, destination)?;
     let conn = get_connection()?;
     let path_str = path.to_string_lossy().to_string();
     let is_directory = path.is_dir();
     
+    let owner = get_tracked_paths()?
+        .into_iter()
+        .find(|tracked| tracked.path == path_str && tracked.destination != destination);
+    if let Some(owner) = owner {
+        anyhow::bail!("{} is already backed up to destination '{}'", path.display(), owner.destination);
+    }
+    
     conn.execute(
-        "INSERT OR IGNORE INTO tracked_paths (path, is_directory) VALUES (?1, ?2)",
-        rusqlite::params![path_str, is_directory as i32],
+        "INSERT OR IGNORE INTO tracked_paths (path, is_directory, destination) VALUES (?1, ?2, ?3)",
+        rusqlite::params![path_str, is_directory as i32, destination],
     )?;
     
     Ok(())
 }
 
+/// Moves a tracked path to another destination. Its files are uploaded to
+/// the new destination in full on the next sync.
+pub fn set_tracked_path_destination(path: &Path, destination: &str) -> Result<()> {
+    ensure_no_overlap(path, destination)?;
+    let conn = get_connection()?;
+    let path_str = path.to_string_lossy().to_string();
+    
+    let updated = conn.execute(
+        "UPDATE tracked_paths SET destination = ?1 WHERE path = ?2 AND destination != ?1",
+        rusqlite::params![destination, path_str],
+    )?;
+    if updated > 0 {
+        remove_file_metadata_under(path)?;
+    }
+    
+    Ok(())
+}
+
+/// Fails if `path` contains, or lies inside, a path tracked for another
+/// destination. Sync state is kept per file path, so two destinations
+/// backing up the same files would overwrite each other's.
+fn ensure_no_overlap(path: &Path, destination: &str) -> Result<()> {
+    for tracked in get_tracked_paths()? {
+        let tracked_path = Path::new(&tracked.path);
+        if tracked.destination == destination || tracked_path == path {
+            continue;
+        }
+        if tracked_path.starts_with(path) || path.starts_with(tracked_path) {
+            anyhow::bail!(
+                "{} overlaps {}, which is backed up to destination '{}'",
+                path.display(), tracked.path, tracked.destination
+            );
+        }
+    }
+    Ok(())
+}
+
 pub fn remove_tracked_path(path: &Path) -> Result<()> {
     let conn = get_connection()?;
     let path_str = path.to_string_lossy().to_string();
@@ -249,12 +488,15 @@ pub fn remove_tracked_path(path: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn get_tracked_paths() -> Result<Vec<String>> {
+pub fn get_tracked_paths() -> Result<Vec<TrackedPath>> {
     let conn = get_connection()?;
-    let mut stmt = conn.prepare("SELECT path FROM tracked_paths ORDER BY path")?;
+    let mut stmt = conn.prepare("SELECT path, destination FROM tracked_paths ORDER BY path")?;
     
     let rows = stmt.query_map([], |row| {
-        Ok(row.get::<_, String>(0)?)
+        Ok(TrackedPath {
+            path: row.get(0)?,
+            destination: row.get(1)?,
+        })
     })?;
     
     let mut paths = Vec::new();
@@ -265,6 +507,20 @@ pub fn get_tracked_paths() -> Result<Vec<String>> {
     Ok(paths)
 }
 
+pub fn get_tracked_paths_for(destination: &str) -> Result<Vec<String>> {
+    let conn = get_connection()?;
+    let mut stmt = conn.prepare("SELECT path FROM tracked_paths WHERE destination = ? ORDER BY path")?;
+    
+    let rows = stmt.query_map([destination], |row| row.get::<_, String>(0))?;
+    
+    let mut paths = Vec::new();
+    for row in rows {
+        paths.push(row?);
+    }
+    
+    Ok(paths)
+}
+
 pub fn set_metadata(key: &str, value: &str) -> Result<()> {
     let conn = get_connection()?;
     conn.execute(
@@ -274,6 +530,15 @@ pub fn set_metadata(key: &str, value: &str) -> Result<()> {
     Ok(())
 }
 
+pub fn remove_metadata(key: &str) -> Result<()> {
+    let conn = get_connection()?;
+    conn.execute(
+        "DELETE FROM app_metadata WHERE key = ?1",
+        rusqlite::params![key],
+    )?;
+    Ok(())
+}
+
 pub fn get_metadata(key: &str) -> Result<Option<String>> {
     let conn = get_connection()?;
     let mut stmt = conn.prepare("SELECT value FROM app_metadata WHERE key = ?")?;
@@ -286,3 +551,49 @@ pub fn get_metadata(key: &str) -> Result<Option<String>> {
     }
 }
 
+pub fn record_sync_run(record: &SyncRunRecord) -> Result<()> {
+    let conn = get_connection()?;
+    conn.execute(
+        "INSERT INTO sync_history (started_at, finished_at, trigger, status, files_staged, uploaded, message, destination)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        rusqlite::params![
+            record.started_at,
+            record.finished_at,
+            record.trigger,
+            record.status,
+            record.files_staged,
+            record.uploaded,
+            record.message,
+            record.destination,
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn get_sync_history(limit: u64) -> Result<Vec<SyncRunRecord>> {
+    let conn = get_connection()?;
+    let mut stmt = conn.prepare(
+        "SELECT started_at, finished_at, trigger, status, files_staged, uploaded, message, destination
+         FROM sync_history ORDER BY id DESC LIMIT ?"
+    )?;
+
+    let rows = stmt.query_map([limit], |row| {
+        Ok(SyncRunRecord {
+            destination: row.get(7)?,
+            started_at: row.get(0)?,
+            finished_at: row.get(1)?,
+            trigger: row.get(2)?,
+            status: row.get(3)?,
+            files_staged: row.get(4)?,
+            uploaded: row.get(5)?,
+            message: row.get(6)?,
+        })
+    })?;
+
+    let mut history = Vec::new();
+    for row in rows {
+        history.push(row?);
+    }
+
+    Ok(history)
+}
@@ -0,0 +1,267 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use crate::storage::{StorageBackend, StoredObject, TransferHooks};
+
+// Size of the pieces files are copied in, which sets how often progress is reported
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Backups in a local directory, typically a mounted NAS share. Files are
+/// written under a `.partial` name, flushed to disk and renamed into place,
+/// so a reader never sees half a backup.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The local path of a backend path. Rejects `..` and absolute components
+    /// so nothing outside the backup directory can be touched.
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let mut resolved = self.root.clone();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let mut components = Path::new(name).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) => resolved.push(name),
+                _ => anyhow::bail!("Invalid path in backup directory: {}", path),
+            }
+        }
+        Ok(resolved)
+    }
+
+    fn to_stored(path: &str, metadata: &fs::Metadata) -> StoredObject {
+        let (_, name) = crate::storage::split(path);
+        StoredObject {
+            id: path.to_string(),
+            path: path.to_string(),
+            name: name.to_string(),
+            is_dir: metadata.is_dir(),
+            size: metadata.is_file().then_some(metadata.len()),
+            modified_time: metadata.modified()
+                .ok()
+                .map(|time| DateTime::<Utc>::from(time).to_rfc3339()),
+            etag: None,
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    fn describe(&self) -> String {
+        format!("folder '{}'", self.root.display())
+    }
+
+    /// Creates the backup directory, but not the directory above it: when that
+    /// is missing the share is most likely not mounted, and backing up to the
+    /// local disk instead would go unnoticed.
+    async fn ensure_container(&mut self) -> Result<()> {
+        if self.root.is_dir() {
+            return Ok(());
+        }
+        match self.root.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && !parent.is_dir() => {
+                anyhow::bail!("{} does not exist. Is the backup drive mounted?", parent.display())
+            }
+            _ => fs::create_dir(&self.root)
+                .with_context(|| format!("Failed to create {}", self.root.display())),
+        }
+    }
+
+    async fn put(&mut self, local: &Path, path: &str, hooks: TransferHooks) -> Result<StoredObject> {
+        let target = self.resolve(path)?;
+        let source = local.to_path_buf();
+        let copy_target = target.clone();
+        tokio::task::spawn_blocking(move || copy_atomically(&source, &copy_target, &hooks))
+            .await
+            .context("Copy task failed")??;
+
+        let metadata = fs::metadata(&target)
+            .with_context(|| format!("Failed to read {}", target.display()))?;
+        Ok(Self::to_stored(path, &metadata))
+    }
+
+    async fn get(&mut self, path: &str, local: &Path, hooks: TransferHooks) -> Result<u64> {
+        let source = self.resolve(path)?;
+        let target = local.to_path_buf();
+        tokio::task::spawn_blocking(move || copy_atomically(&source, &target, &hooks))
+            .await
+            .context("Copy task failed")?
+    }
+
+    async fn list(&mut self, dir: &str) -> Result<Vec<StoredObject>> {
+        let dir_path = self.resolve(dir)?;
+        let entries = match fs::read_dir(&dir_path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to list {}", dir_path.display())),
+        };
+
+        let mut objects = Vec::new();
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Failed to list {}", dir_path.display()))?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            // Copies still in flight, or left behind by a crash
+            if name.ends_with(".partial") {
+                continue;
+            }
+            let metadata = entry.metadata()
+                .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+            objects.push(Self::to_stored(&crate::storage::join(dir, &name), &metadata));
+        }
+        objects.sort_by(|a, b| b.modified_time.cmp(&a.modified_time));
+        Ok(objects)
+    }
+
+    async fn stat(&mut self, path: &str) -> Result<Option<StoredObject>> {
+        let local = self.resolve(path)?;
+        match fs::metadata(&local) {
+            Ok(metadata) => Ok(Some(Self::to_stored(path, &metadata))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", local.display())),
+        }
+    }
+
+    async fn delete(&mut self, path: &str) -> Result<()> {
+        let local = self.resolve(path)?;
+        match fs::remove_file(&local) {
+            Ok(()) => sync_parent(&local),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).with_context(|| format!("Failed to delete {}", local.display())),
+        }
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let from_path = self.resolve(from)?;
+        let to_path = self.resolve(to)?;
+        if let Some(parent) = to_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::rename(&from_path, &to_path)
+            .with_context(|| format!("Failed to move {} to {}", from_path.display(), to_path.display()))?;
+        sync_parent(&from_path)?;
+        sync_parent(&to_path)
+    }
+}
+
+/// Copies `source` to a `.partial` sibling of `target`, flushes it and
+/// renames it over `target`. Returns the number of bytes copied; on failure
+/// or cancellation the partial copy is removed and `target` is untouched.
+fn copy_atomically(source: &Path, target: &Path, hooks: &TransferHooks) -> Result<u64> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let partial_path = crate::storage::partial_path(target);
+
+    let result = (|| -> Result<u64> {
+        let mut input = fs::File::open(source)
+            .with_context(|| format!("Failed to open {}", source.display()))?;
+        let total = input.metadata().map(|m| m.len()).unwrap_or(0);
+        let mut output = fs::File::create(&partial_path)
+            .with_context(|| format!("Failed to create {}", partial_path.display()))?;
+
+        let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+        let mut copied = 0u64;
+        hooks.report(0, total);
+        loop {
+            if hooks.is_cancelled() {
+                anyhow::bail!("Copy cancelled");
+            }
+            let read = input.read(&mut buffer)
+                .with_context(|| format!("Failed to read {}", source.display()))?;
+            if read == 0 {
+                break;
+            }
+            output.write_all(&buffer[..read])
+                .with_context(|| format!("Failed to write {}", partial_path.display()))?;
+            copied += read as u64;
+            hooks.report(copied, total.max(copied));
+        }
+
+        output.sync_all()
+            .with_context(|| format!("Failed to flush {}", partial_path.display()))?;
+        Ok(copied)
+    })();
+
+    let copied = match result {
+        Ok(copied) => copied,
+        Err(e) => {
+            let _ = fs::remove_file(&partial_path);
+            return Err(e);
+        }
+    };
+
+    fs::rename(&partial_path, target)
+        .with_context(|| format!("Failed to move {} into place", target.display()))?;
+    sync_parent(target)?;
+    Ok(copied)
+}
+
+/// Flushes a directory entry change (create, rename, delete) to disk. Only
+/// Unix can open a directory for this; elsewhere the rename is the best we get.
+fn sync_parent(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        fs::File::open(parent)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("Failed to flush {}", parent.display()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_rejects_paths_outside_the_root() {
+        let storage = LocalStorage::new("/backups");
+        assert_eq!(storage.resolve("versions/a.zip").unwrap(), Path::new("/backups/versions/a.zip"));
+        assert_eq!(storage.resolve("/versions//a.zip").unwrap(), Path::new("/backups/versions/a.zip"));
+        assert!(storage.resolve("..").is_err());
+        assert!(storage.resolve("versions/../../etc/passwd").is_err());
+        assert!(storage.resolve("./a.zip").is_err());
+        #[cfg(windows)]
+        assert!(storage.resolve("C:\\Windows").is_err());
+    }
+
+    #[tokio::test]
+    async fn round_trips_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut storage = LocalStorage::new(temp.path().join("backups"));
+        storage.ensure_container().await.unwrap();
+
+        let source = temp.path().join("source.txt");
+        fs::write(&source, b"backup contents").unwrap();
+        let stored = storage.put(&source, "versions/a.txt", TransferHooks::default()).await.unwrap();
+        assert_eq!(stored.name, "a.txt");
+        assert_eq!(stored.size, Some(15));
+
+        let listed = storage.list("versions").await.unwrap();
+        assert_eq!(listed.iter().map(|object| object.path.as_str()).collect::<Vec<_>>(), ["versions/a.txt"]);
+        assert!(storage.list("missing").await.unwrap().is_empty());
+
+        storage.rename("versions/a.txt", "deleted/b.txt").await.unwrap();
+        assert!(storage.stat("versions/a.txt").await.unwrap().is_none());
+
+        let restored = temp.path().join("restored.txt");
+        let copied = storage.get("deleted/b.txt", &restored, TransferHooks::default()).await.unwrap();
+        assert_eq!(copied, 15);
+        assert_eq!(fs::read(&restored).unwrap(), b"backup contents");
+
+        storage.delete("deleted/b.txt").await.unwrap();
+        storage.delete("deleted/b.txt").await.unwrap();
+        assert!(storage.stat("deleted/b.txt").await.unwrap().is_none());
+    }
+}
@@ -6,10 +6,21 @@ mod file_tracker;
 mod drive_sync;
 mod version_manager;
 mod scheduler;
+mod sync_engine;
+mod sync_coordinator;
+mod manifest;
+mod restore;
+mod crypto;
+mod credentials;
+mod oauth;
+mod storage;
+mod local_storage;
+mod s3_storage;
+mod webdav_storage;
+mod sftp_storage;
 mod logger;
 
 use tauri::{Manager, menu::{Menu, MenuItem}, tray::{TrayIconBuilder, TrayIconEvent}};
-use tauri::Emitter;
 
 fn main() {
     // Initialize logger first
@@ -37,6 +48,11 @@ fn main() {
                 logger::log_info("Configuration initialized successfully");
             }
 
+            // Move secrets older versions kept in plaintext into the credential store
+            if let Err(e) = credentials::migrate_plaintext_secrets() {
+                logger::log_error(&format!("Failed to migrate credentials: {}", e));
+            }
+
             // Initialize file tracker database
             logger::log_info("Initializing file tracker database...");
             if let Err(e) = file_tracker::init_database() {
@@ -78,20 +94,46 @@ fn main() {
             commands::get_config,
             commands::set_staging_dir,
             commands::set_drive_folder,
+            commands::set_shared_drive,
+            commands::reset_drive_folder,
             commands::set_sync_interval,
             commands::set_auto_sync,
+            commands::set_sync_mode,
+            commands::set_deletion_policy,
+            commands::set_retention_policy,
+            commands::set_upload_versions,
+            commands::set_encryption,
+            commands::set_encryption_passphrase,
+            commands::generate_encryption_key_file,
             commands::get_tracked_paths,
             commands::add_tracked_path,
             commands::remove_tracked_path,
+            commands::set_tracked_path_destination,
+            commands::get_destinations,
+            commands::save_destination,
+            commands::set_destination_storage,
+            commands::remove_destination,
             commands::sync_now,
             commands::get_sync_status,
+            commands::get_sync_progress,
+            commands::cancel_sync,
+            commands::get_sync_history,
+            commands::list_backups,
+            commands::restore,
+            commands::list_backup_revisions,
+            commands::keep_backup_revision,
+            commands::delete_backup_revision,
                     commands::get_auth_url,
                     commands::open_url,
                     commands::handle_oauth_code,
                     commands::check_auth_status,
+            commands::get_session_expiry,
                     commands::listen_for_oauth_code,
+            commands::cancel_oauth,
                     commands::set_google_client_id,
             commands::set_google_client_secret,
+            commands::set_storage_secret,
+            commands::get_credential_status,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -103,9 +145,10 @@ fn setup_tray(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
     // Create tray menu
     let show_item = MenuItem::with_id(app, "show", "Show Window", true, None::<&str>)?;
     let sync_item = MenuItem::with_id(app, "sync", "Sync Now", true, None::<&str>)?;
+    let cancel_item = MenuItem::with_id(app, "cancel", "Cancel Sync", true, None::<&str>)?;
     let quit_item = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
     
-    let menu = Menu::with_items(app, &[&show_item, &sync_item, &quit_item])?;
+    let menu = Menu::with_items(app, &[&show_item, &sync_item, &cancel_item, &quit_item])?;
     
     // Build tray icon
     let _tray = TrayIconBuilder::new()
@@ -122,7 +165,15 @@ fn setup_tray(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
                 }
                 "sync" => {
                     logger::log_info("Sync triggered from tray menu");
-                    let _ = app.emit("tray-sync-requested", ());
+                    let app_handle = app.clone();
+                    tauri::async_runtime::spawn(async move {
+                        let _ = sync_coordinator::request_sync(&app_handle, sync_coordinator::SyncTrigger::Tray).await;
+                    });
+                }
+                "cancel" => {
+                    if !sync_coordinator::cancel_sync() {
+                        logger::log_info("Cancel requested from tray, but no sync is running");
+                    }
                 }
                 "quit" => {
                     logger::log_info("Quit requested from tray");
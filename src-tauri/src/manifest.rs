@@ -0,0 +1,194 @@
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::write::{FileOptions, ZipWriter};
+use zip::{CompressionMethod, ZipArchive};
+use anyhow::{Result, Context};
+
+/// Name of the manifest entry at the root of every backup archive.
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+// Version 1 only recorded entry origins; version 2 added roots, hashes and
+// file metadata. Older manifests still parse, with the new fields empty.
+const MANIFEST_VERSION: u32 = 2;
+
+/// Describes where each file in a backup archive came from, so restore,
+/// verify and diff don't have to guess from the flattened `tracked/` layout.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub created_at: String,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub app_version: Option<String>,
+    /// The tracked paths this archive was made from
+    #[serde(default)]
+    pub roots: Vec<ManifestRoot>,
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestRoot {
+    /// Path inside the archive the tracked path was flattened to
+    pub path: String,
+    /// Absolute tracked path
+    pub origin: String,
+    pub is_dir: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path of the entry inside the archive
+    pub path: String,
+    /// Absolute path of the tracked file it was copied from
+    pub origin: String,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub size: u64,
+    /// Unix permission bits, `None` on platforms without them
+    #[serde(default)]
+    pub mode: Option<u32>,
+    /// Modification time of the original file, seconds since the Unix epoch
+    #[serde(default)]
+    pub modified: Option<u64>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            created_at: chrono::Local::now().to_rfc3339(),
+            hostname: Some(gethostname::gethostname().to_string_lossy().to_string()),
+            app_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            roots: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add_root(&mut self, archive_path: &Path, origin: &Path) {
+        self.roots.push(ManifestRoot {
+            path: entry_name(archive_path),
+            origin: origin.to_string_lossy().to_string(),
+            is_dir: origin.is_dir(),
+        });
+    }
+
+    /// Records a file copied from `origin` to `copy`, hashing the copy so the
+    /// checksum matches what actually went into the archive.
+    pub fn add_file(&mut self, archive_path: &Path, origin: &Path, copy: &Path) -> Result<()> {
+        let metadata = std::fs::metadata(origin)
+            .with_context(|| format!("Failed to read metadata of {}", origin.display()))?;
+        let sha256 = crate::file_tracker::calculate_file_hash(copy)?;
+
+        let entry = ManifestEntry {
+            path: entry_name(archive_path),
+            origin: origin.to_string_lossy().to_string(),
+            sha256: Some(sha256),
+            size: metadata.len(),
+            mode: file_mode(&metadata),
+            modified: metadata.modified()
+                .ok()
+                .and_then(|time| time.duration_since(std::time::SystemTime::UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs()),
+        };
+
+        // When two files map to the same entry the last one staged wins, just
+        // like the copy itself
+        self.entries.retain(|existing| existing.path != entry.path);
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// The same manifest for an archive that stores the files below `prefix`.
+    pub fn with_prefix(&self, prefix: &str) -> Self {
+        let prefixed = |path: &str| format!("{}/{}", prefix, path);
+        Self {
+            roots: self.roots.iter()
+                .map(|root| ManifestRoot { path: prefixed(&root.path), ..root.clone() })
+                .collect(),
+            entries: self.entries.iter()
+                .map(|entry| ManifestEntry { path: prefixed(&entry.path), ..entry.clone() })
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// The recorded entry for an archive path, if the manifest knows it.
+    pub fn entry(&self, entry_path: &str) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|entry| entry.path == entry_path)
+    }
+
+    /// Original location of an archive entry, if the manifest knows it.
+    pub fn origin_of(&self, entry_path: &str) -> Option<&str> {
+        self.entry(entry_path).map(|entry| entry.origin.as_str())
+    }
+
+    /// SHA-256 over every entry's path and checksum. Two manifests with the
+    /// same digest describe archives with the same file contents.
+    pub fn content_digest(&self) -> String {
+        let mut entries: Vec<&ManifestEntry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut hasher = Sha256::new();
+        for entry in entries {
+            hasher.update(entry.path.as_bytes());
+            hasher.update([0]);
+            hasher.update(entry.sha256.as_deref().unwrap_or_default().as_bytes());
+            hasher.update([b'\n']);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub fn write_to_zip<W: Write + Seek>(&self, zip: &mut ZipWriter<W>) -> Result<()> {
+        let json = serde_json::to_vec_pretty(self)
+            .context("Failed to serialize manifest")?;
+        zip.start_file(MANIFEST_NAME, FileOptions::default()
+            .compression_method(CompressionMethod::Deflated))?;
+        zip.write_all(&json)
+            .context("Failed to write manifest to archive")?;
+        Ok(())
+    }
+
+    /// Reads the manifest of an archive. Archives made before manifests were
+    /// added have none and yield `None`.
+    pub fn read_from_zip<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<Option<Self>> {
+        let mut file = match archive.by_name(MANIFEST_NAME) {
+            Ok(file) => file,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(e).context("Failed to read manifest from archive"),
+        };
+
+        let mut json = Vec::new();
+        file.read_to_end(&mut json)
+            .context("Failed to read manifest from archive")?;
+        let manifest = serde_json::from_slice(&json)
+            .context("Failed to parse archive manifest")?;
+        Ok(Some(manifest))
+    }
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Archive entry name for a relative path, spelled the way
+/// `version_manager::add_directory_to_zip` names entries.
+fn entry_name(archive_path: &Path) -> String {
+    archive_path.to_string_lossy().to_string()
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &std::fs::Metadata) -> Option<u32> {
+    None
+}
@@ -0,0 +1,232 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use anyhow::{Result, Context};
+use base64::Engine;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use tiny_http::{Header, Response, Server};
+
+// Give up on the browser after this long; the manual code entry still works
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+// How often the listener checks for cancellation between requests
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// An authorization started by `start` and not yet exchanged for tokens.
+struct PendingAuthorization {
+    /// Destination whose account is being signed in
+    destination: String,
+    /// Loopback listener for the redirect; `None` once it has stopped
+    server: Option<Arc<Server>>,
+    redirect_uri: String,
+    state: String,
+    code_verifier: String,
+    cancelled: Arc<AtomicBool>,
+}
+
+static PENDING: Mutex<Option<PendingAuthorization>> = Mutex::new(None);
+
+/// Redirect target and PKCE verifier needed to exchange an authorization code.
+pub struct CodeExchange {
+    pub destination: String,
+    pub redirect_uri: String,
+    pub code_verifier: String,
+}
+
+/// Starts a loopback authorization: binds the redirect listener, preferring
+/// `REDIRECT_PORT` and falling back to an ephemeral port when it is taken,
+/// and returns the consent URL to open in the browser. Replaces any
+/// authorization still pending.
+pub fn start(destination: &str) -> Result<String> {
+    cancel();
+    let server = bind_listener()?;
+    let port = server.server_addr()
+        .to_ip()
+        .context("Loopback listener has no IP address")?
+        .port();
+    let redirect_uri = format!("http://127.0.0.1:{}", port);
+
+    let state = random_token();
+    let code_verifier = random_token();
+    let code_challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .encode(Sha256::digest(code_verifier.as_bytes()));
+    let url = crate::drive_sync::DriveSync::for_destination(destination)
+        .get_auth_url(&redirect_uri, &code_challenge, &state)?;
+
+    let pending = PendingAuthorization {
+        destination: destination.to_string(),
+        server: Some(Arc::new(server)),
+        redirect_uri,
+        state,
+        code_verifier,
+        cancelled: Arc::new(AtomicBool::new(false)),
+    };
+    *PENDING.lock().unwrap() = Some(pending);
+
+    Ok(url)
+}
+
+/// Waits for the browser to be redirected back with an authorization code.
+/// Requests without the expected `state` are answered with an error page and
+/// otherwise ignored. Blocks, so call it off the async runtime.
+pub fn wait_for_code() -> Result<String> {
+    let (server, state, cancelled) = {
+        let pending = PENDING.lock().unwrap();
+        let pending = pending.as_ref()
+            .context("No authorization in progress")?;
+        let server = pending.server.clone()
+            .context("The authorization listener has already stopped")?;
+        (server, pending.state.clone(), pending.cancelled.clone())
+    };
+
+    let result = receive_code(&server, &state, &cancelled);
+    stop_listener(&server);
+    result
+}
+
+/// Abandons the pending authorization. Returns false if there was none.
+pub fn cancel() -> bool {
+    match PENDING.lock().unwrap().take() {
+        Some(pending) => {
+            pending.cancelled.store(true, Ordering::SeqCst);
+            if let Some(server) = pending.server {
+                server.unblock();
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Ends the pending authorization, handing back what the token exchange needs.
+pub fn finish() -> Result<CodeExchange> {
+    let pending = PENDING.lock().unwrap()
+        .take()
+        .context("No authorization in progress. Start authentication again.")?;
+    if let Some(server) = pending.server {
+        server.unblock();
+    }
+    Ok(CodeExchange {
+        destination: pending.destination,
+        redirect_uri: pending.redirect_uri,
+        code_verifier: pending.code_verifier,
+    })
+}
+
+fn bind_listener() -> Result<Server> {
+    match Server::http(("127.0.0.1", crate::drive_sync::REDIRECT_PORT)) {
+        Ok(server) => Ok(server),
+        Err(e) => {
+            crate::logger::log_warn(&format!(
+                "Port {} unavailable ({}), using an ephemeral port",
+                crate::drive_sync::REDIRECT_PORT, e
+            ));
+            Server::http(("127.0.0.1", 0))
+                .map_err(|e| anyhow::anyhow!("Failed to start local server: {}", e))
+        }
+    }
+}
+
+fn receive_code(server: &Server, state: &str, cancelled: &AtomicBool) -> Result<String> {
+    let deadline = Instant::now() + LISTEN_TIMEOUT;
+    loop {
+        if cancelled.load(Ordering::SeqCst) {
+            anyhow::bail!("Authentication cancelled");
+        }
+        if Instant::now() >= deadline {
+            anyhow::bail!("Timed out waiting for the browser. Paste the code manually or try again.");
+        }
+
+        let request = match server.recv_timeout(POLL_INTERVAL) {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            Err(e) => return Err(e).context("Local server failed"),
+        };
+
+        // Anything can reach the port, so a request that isn't even a valid URL
+        // is answered and ignored rather than ending the wait
+        let url = match url::Url::parse(&format!("http://127.0.0.1{}", request.url())) {
+            Ok(url) => url,
+            Err(e) => {
+                crate::logger::log_warn(&format!("Ignoring malformed request to the OAuth listener: {}", e));
+                let _ = request.respond(Response::empty(400));
+                continue;
+            }
+        };
+        if url.path() != "/" {
+            let _ = request.respond(Response::empty(404));
+            continue;
+        }
+
+        let param = |name: &str| url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned());
+
+        if param("state").as_deref() != Some(state) {
+            crate::logger::log_warn("Ignoring OAuth callback with a missing or wrong state");
+            let _ = request.respond(html_page(400, "Authentication failed",
+                "This sign-in link does not belong to the running app. Start authentication again from Sync Bot."));
+            continue;
+        }
+
+        if let Some(error) = param("error") {
+            let _ = request.respond(html_page(400, "Authentication failed",
+                &format!("Google reported: {}. You can close this window and try again.", error)));
+            anyhow::bail!("Authorization was denied: {}", error);
+        }
+
+        match param("code") {
+            Some(code) => {
+                let _ = request.respond(html_page(200, "Authentication successful",
+                    "You can close this window and return to Sync Bot."));
+                crate::logger::log_info("OAuth code received successfully");
+                return Ok(code);
+            }
+            None => {
+                let _ = request.respond(html_page(400, "Authentication failed",
+                    "No authorization code was found in the request."));
+                anyhow::bail!("No code found in the callback URL");
+            }
+        }
+    }
+}
+
+/// Drops the listener so the port is freed; the verifier stays pending so a
+/// manually pasted code can still be exchanged.
+fn stop_listener(server: &Arc<Server>) {
+    let mut pending = PENDING.lock().unwrap();
+    if let Some(pending) = pending.as_mut() {
+        if pending.server.as_ref().is_some_and(|current| Arc::ptr_eq(current, server)) {
+            pending.server = None;
+        }
+    }
+}
+
+/// 32 random bytes, base64url encoded: 43 characters, valid as both a PKCE
+/// verifier and a `state` value.
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn html_page(status: u16, title: &str, message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    let body = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title>\
+         <style>body{{font-family:sans-serif;max-width:32em;margin:4em auto;color:#333}}\
+         h1{{font-size:1.4em}}</style></head>\
+         <body><h1>{title}</h1><p>{message}</p></body></html>\n",
+        title = escape_html(title),
+        message = escape_html(message),
+    );
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
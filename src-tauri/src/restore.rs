@@ -0,0 +1,363 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+use anyhow::{Result, Context};
+use crate::storage::{Revision, StorageBackend, StoredObject};
+use crate::manifest::{Manifest, ManifestEntry, MANIFEST_NAME};
+
+// Name of the rolling archive uploaded by archive-mode syncs
+const CURRENT_BACKUP_NAME: &str = "backup.zip";
+
+// Downloads are kept here until they have been unpacked
+const RESTORE_DIR: &str = "restore";
+
+/// What to do when a restored file would replace one that already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    Overwrite,
+    Skip,
+    /// Restore next to the existing file as `name (restored).ext`
+    #[default]
+    Rename,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// Destination to restore from; the default destination when `None`
+    pub destination: Option<String>,
+    /// Backend ID of the backup to restore; the current `backup.zip` when `None`
+    pub backup_id: Option<String>,
+    /// Revision of the backup to restore instead of its latest content
+    pub revision_id: Option<String>,
+    /// Unpack below this directory instead of the original tracked locations
+    pub target_dir: Option<PathBuf>,
+    pub conflict: ConflictPolicy,
+    /// Passphrase for a backup encrypted under a passphrase other than the current one
+    pub passphrase: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreReport {
+    pub backup_name: String,
+    pub files_restored: usize,
+    pub files_skipped: usize,
+    /// Files restored under a new name because the destination existed
+    pub files_renamed: usize,
+    pub errors: Vec<String>,
+}
+
+/// ZIP backups in a destination's backup folder and its `versions/`
+/// subfolder, newest first.
+pub async fn list_backups(destination: &str) -> Result<Vec<StoredObject>> {
+    let mut storage = crate::storage::open_destination(destination)?;
+    list_all_backups(storage.as_mut()).await
+}
+
+/// Downloads a backup from storage and unpacks it according to `options`.
+pub async fn restore_backup(options: &RestoreOptions) -> Result<RestoreReport> {
+    let destination = options.destination.as_deref()
+        .unwrap_or(crate::config::DEFAULT_DESTINATION);
+    let mut storage = crate::storage::open_destination(destination)?;
+    let backups = list_all_backups(storage.as_mut()).await?;
+
+    let backup = match &options.backup_id {
+        Some(id) => backups.into_iter().find(|file| &file.id == id)
+            .with_context(|| format!("Backup {} not found in {}", id, storage.describe()))?,
+        None => backups.into_iter().find(|file| is_current_backup(&file.name))
+            .with_context(|| format!("No backup.zip found in {}", storage.describe()))?,
+    };
+
+    let restore_dir = crate::config::get_data_dir()?.join(RESTORE_DIR);
+    fs::create_dir_all(&restore_dir)
+        .context("Failed to create restore directory")?;
+    let download_path = restore_dir.join(&backup.name);
+
+    match &options.revision_id {
+        Some(revision_id) => {
+            crate::logger::log_info(&format!("Downloading revision {} of {} for restore", revision_id, backup.name));
+            storage.get_revision(&backup.path, revision_id, &download_path, Default::default()).await?;
+        }
+        None => {
+            crate::logger::log_info(&format!("Downloading {} for restore", backup.path));
+            storage.get(&backup.path, &download_path, Default::default()).await?;
+        }
+    }
+
+    let result = match decrypt_download(&download_path, options) {
+        Ok(archive_path) => {
+            let result = extract_backup(&archive_path, options);
+            if archive_path != download_path {
+                let _ = fs::remove_file(&archive_path);
+            }
+            result
+        }
+        Err(e) => Err(e),
+    };
+    let _ = fs::remove_file(&download_path);
+
+    let mut report = result?;
+    report.backup_name = backup.name;
+    crate::logger::log_info(&format!(
+        "Restore finished: {} restored, {} renamed, {} skipped, {} error(s)",
+        report.files_restored, report.files_renamed, report.files_skipped, report.errors.len()
+    ));
+    Ok(report)
+}
+
+/// Decrypts a downloaded backup next to itself if it is encrypted, returning
+/// the path of the plain ZIP.
+fn decrypt_download(download_path: &Path, options: &RestoreOptions) -> Result<PathBuf> {
+    let header = match crate::crypto::read_header(download_path)? {
+        Some(header) => header,
+        None => return Ok(download_path.to_path_buf()),
+    };
+
+    let key = crate::crypto::key_for_header(&header, options.passphrase.as_deref())?;
+    let name = download_path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let plain_name = match name.strip_suffix(crate::crypto::ENCRYPTED_SUFFIX) {
+        Some(plain) if !plain.is_empty() => plain.to_string(),
+        _ => format!("{}.decrypted", name),
+    };
+    let plain_path = download_path.with_file_name(plain_name);
+
+    crate::crypto::decrypt_file(download_path, &plain_path, &key)?;
+    crate::logger::log_info("Decrypted backup for restore");
+    Ok(plain_path)
+}
+
+/// Unpacks a downloaded backup. Entries go back to the origin recorded in the
+/// manifest, or below `target_dir` with their archive layout when one is set.
+pub fn extract_backup(archive_path: &Path, options: &RestoreOptions) -> Result<RestoreReport> {
+    let file = fs::File::open(archive_path)
+        .context("Failed to open downloaded backup")?;
+    let mut archive = ZipArchive::new(file)
+        .context("Downloaded backup is not a valid ZIP file")?;
+
+    let manifest = Manifest::read_from_zip(&mut archive)?;
+    if manifest.is_none() && options.target_dir.is_none() {
+        anyhow::bail!("This backup has no manifest, so the original locations are unknown. Choose a target directory to restore into.");
+    }
+
+    let mut report = RestoreReport {
+        backup_name: archive_path.file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        files_restored: 0,
+        files_skipped: 0,
+        files_renamed: 0,
+        errors: Vec::new(),
+    };
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)
+            .context("Failed to read backup entry")?;
+        if entry.is_dir() || entry.name() == MANIFEST_NAME {
+            continue;
+        }
+
+        let entry_name = entry.name().to_string();
+        // Rejects absolute paths and `..` so an entry can't escape the target
+        let relative_path = match entry.enclosed_name() {
+            Some(path) => path.to_path_buf(),
+            None => {
+                report.errors.push(format!("Skipped unsafe entry path: {}", entry_name));
+                continue;
+            }
+        };
+
+        let dest = match (&options.target_dir, &manifest) {
+            (Some(target_dir), _) => target_dir.join(&relative_path),
+            (None, Some(manifest)) => match manifest.origin_of(&entry_name) {
+                Some(origin) => PathBuf::from(origin),
+                None => {
+                    report.errors.push(format!("No original location recorded for {}", entry_name));
+                    continue;
+                }
+            },
+            (None, None) => unreachable!(),
+        };
+
+        let dest = if dest.exists() {
+            match options.conflict {
+                ConflictPolicy::Skip => {
+                    crate::logger::log_info(&format!("Skipped {} (already exists)", dest.display()));
+                    report.files_skipped += 1;
+                    continue;
+                }
+                ConflictPolicy::Overwrite => dest,
+                ConflictPolicy::Rename => {
+                    report.files_renamed += 1;
+                    renamed_path(&dest)
+                }
+            }
+        } else {
+            dest
+        };
+
+        let recorded = manifest.as_ref().and_then(|manifest| manifest.entry(&entry_name));
+        match write_entry(&mut entry, &dest, recorded) {
+            Ok(()) => {
+                crate::logger::log_info(&format!("Restored {} to {}", entry_name, dest.display()));
+                report.files_restored += 1;
+            }
+            Err(e) => {
+                let msg = format!("Failed to restore {} to {}: {}", entry_name, dest.display(), e);
+                crate::logger::log_error(&msg);
+                report.errors.push(msg);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Revisions of the current `backup.zip`, newest first, on backends that keep
+/// them. Each archive-mode upload replaces its content and leaves the previous
+/// content as a revision.
+pub async fn list_backup_revisions(destination: &str) -> Result<Vec<Revision>> {
+    let (mut storage, backup_path) = find_current_backup(destination).await?;
+    let mut revisions = storage.list_revisions(&backup_path).await?;
+    revisions.reverse();
+    Ok(revisions)
+}
+
+/// Pins a `backup.zip` revision so the backend doesn't purge it, or unpins it.
+pub async fn keep_backup_revision(destination: &str, revision_id: &str, keep_forever: bool) -> Result<()> {
+    let (mut storage, backup_path) = find_current_backup(destination).await?;
+    storage.keep_revision(&backup_path, revision_id, keep_forever).await?;
+    crate::logger::log_info(&format!(
+        "Revision {} of backup.zip {}",
+        revision_id,
+        if keep_forever { "will be kept forever" } else { "may expire again" }
+    ));
+    Ok(())
+}
+
+pub async fn delete_backup_revision(destination: &str, revision_id: &str) -> Result<()> {
+    let (mut storage, backup_path) = find_current_backup(destination).await?;
+    storage.delete_revision(&backup_path, revision_id).await?;
+    crate::logger::log_info(&format!("Deleted revision {} of backup.zip", revision_id));
+    Ok(())
+}
+
+async fn find_current_backup(destination: &str) -> Result<(Box<dyn StorageBackend>, String)> {
+    let mut storage = crate::storage::open_destination(destination)?;
+    // Both exist when encryption was switched on or off; the newer one is current
+    let mut backup: Option<StoredObject> = None;
+    for name in [CURRENT_BACKUP_NAME.to_string(), format!("{}{}", CURRENT_BACKUP_NAME, crate::crypto::ENCRYPTED_SUFFIX)] {
+        if let Some(found) = storage.stat(&name).await? {
+            if backup.as_ref().is_none_or(|current| found.modified_time > current.modified_time) {
+                backup = Some(found);
+            }
+        }
+    }
+    let backup = backup
+        .with_context(|| format!("No backup.zip found in {}", storage.describe()))?;
+    Ok((storage, backup.path))
+}
+
+/// The rolling backup is `backup.zip`, or `backup.zip.enc` when encrypted.
+fn is_current_backup(name: &str) -> bool {
+    name.strip_suffix(crate::crypto::ENCRYPTED_SUFFIX).unwrap_or(name) == CURRENT_BACKUP_NAME
+}
+
+async fn list_all_backups(storage: &mut dyn StorageBackend) -> Result<Vec<StoredObject>> {
+    let mut backups = list_zip_files(storage, "").await?;
+    backups.extend(list_zip_files(storage, crate::sync_engine::VERSIONS_DIR).await?);
+    backups.sort_by(|a, b| b.modified_time.cmp(&a.modified_time));
+    Ok(backups)
+}
+
+async fn list_zip_files(storage: &mut dyn StorageBackend, dir: &str) -> Result<Vec<StoredObject>> {
+    Ok(storage.list(dir)
+        .await?
+        .into_iter()
+        .filter(|file| !file.is_dir)
+        .filter(|file| file.name.strip_suffix(crate::crypto::ENCRYPTED_SUFFIX).unwrap_or(&file.name).ends_with(".zip"))
+        .collect())
+}
+
+/// Writes one archive entry to `dest` through a temporary sibling, so an
+/// existing file is only replaced by a complete copy. When the manifest has a
+/// record of the file, its checksum is verified and its mode and mtime restored.
+fn write_entry(entry: &mut zip::read::ZipFile, dest: &Path, recorded: Option<&ManifestEntry>) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let mut partial_name = dest.file_name()
+        .context("Destination has no file name")?
+        .to_os_string();
+    partial_name.push(".restore-partial");
+    let partial_path = dest.with_file_name(partial_name);
+
+    let result = (|| -> Result<()> {
+        let mut file = fs::File::create(&partial_path)
+            .context("Failed to create file")?;
+        std::io::copy(entry, &mut file)
+            .context("Failed to extract file")?;
+        file.sync_all()
+            .context("Failed to flush file")?;
+
+        if let Some(recorded) = recorded {
+            if let Some(expected) = &recorded.sha256 {
+                let actual = crate::file_tracker::calculate_file_hash(&partial_path)?;
+                if &actual != expected {
+                    anyhow::bail!("Checksum mismatch (expected {}, got {})", expected, actual);
+                }
+            }
+            if let Some(modified) = recorded.modified {
+                let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(modified);
+                let _ = file.set_modified(time);
+            }
+            set_mode(&partial_path, recorded.mode);
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial_path);
+        return Err(e);
+    }
+
+    fs::rename(&partial_path, dest)
+        .context("Failed to move file into place")
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: Option<u32>) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Some(mode) = mode {
+        let _ = fs::set_permissions(path, fs::Permissions::from_mode(mode));
+    }
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: Option<u32>) {}
+
+/// First free `name (restored).ext` / `name (restored N).ext` next to `path`.
+fn renamed_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path.extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut attempt = 1;
+    loop {
+        let name = match attempt {
+            1 => format!("{} (restored){}", stem, extension),
+            n => format!("{} (restored {}){}", stem, n, extension),
+        };
+        let candidate = path.with_file_name(name);
+        if !candidate.exists() {
+            return candidate;
+        }
+        attempt += 1;
+    }
+}
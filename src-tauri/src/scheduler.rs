@@ -1,6 +1,5 @@
 use tokio::time::{sleep, Duration};
 use anyhow::Result;
-use tauri::Emitter;
 
 pub async fn start_scheduler(app_handle: tauri::AppHandle) {
     let mut interval = Duration::from_secs(60); // Default 1 minute check interval
@@ -16,7 +15,7 @@ pub async fn start_scheduler(app_handle: tauri::AppHandle) {
                     
                     // Perform sync
                     if let Err(e) = perform_scheduled_sync(&app_handle).await {
-                        eprintln!("Scheduled sync error: {}", e);
+                        crate::logger::log_error(&format!("Scheduled sync error: {}", e));
                     }
                 }
             } else {
@@ -30,9 +29,12 @@ pub async fn start_scheduler(app_handle: tauri::AppHandle) {
 }
 
 async fn perform_scheduled_sync(app_handle: &tauri::AppHandle) -> Result<()> {
-    // This will be called by the scheduler
-    // The actual sync logic is in commands.rs
-    // We can emit an event to trigger sync from the frontend if needed
-    app_handle.emit("scheduled-sync", ())?;
+    crate::logger::log_info("=== Scheduled Sync Started ===");
+
+    // The sync runs entirely in the backend; the coordinator emits events only
+    // so an open window can display what happened.
+    crate::sync_coordinator::request_sync(app_handle, crate::sync_coordinator::SyncTrigger::Scheduled)
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?;
     Ok(())
 }
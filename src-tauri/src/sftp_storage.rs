@@ -0,0 +1,490 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use chrono::DateTime;
+use ssh2::{CheckResult, ErrorCode, FileStat, KnownHostFileKind, Session, Sftp};
+use zeroize::Zeroizing;
+use crate::config::SftpSettings;
+use crate::storage::{StorageBackend, StoredObject, TransferHooks, MAX_RETRIES};
+
+const DEFAULT_PORT: u16 = 22;
+
+// Size of the pieces files are transferred in, which sets how often progress is reported
+const COPY_CHUNK_SIZE: usize = 256 * 1024;
+
+// How long a blocking SSH call waits on the server before failing, in milliseconds
+const SESSION_TIMEOUT_MS: u32 = 60_000;
+
+// SFTP status codes for a file or directory that doesn't exist
+const FX_NO_SUCH_FILE: i32 = 2;
+const FX_NO_SUCH_PATH: i32 = 10;
+
+// SFTP status codes a rename onto an existing file fails with: the generic
+// failure of protocol version 3, and the explicit one of later versions
+const FX_FAILURE: i32 = 4;
+const FX_FILE_ALREADY_EXISTS: i32 = 11;
+
+/// Which end of a copy is the remote file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Remote {
+    Input,
+    Output,
+}
+
+/// A failed read or write of a remote file. ssh2 reports these as plain
+/// `io::Error`s, so they are wrapped to tell them apart from local ones.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+struct RemoteIoError(std::io::Error);
+
+/// Where to connect and how to sign in.
+struct Server {
+    settings: SftpSettings,
+    passphrase: Option<Zeroizing<String>>,
+}
+
+/// An open SFTP session. `sftp` comes first so it is closed before the session.
+struct Connection {
+    sftp: Sftp,
+    _session: Session,
+}
+
+/// Backups in a directory on a server reached over SFTP. The server's host
+/// key must be listed in a known_hosts file. Files are uploaded under a
+/// `.partial` name and renamed into place, so a reader never sees half a
+/// backup.
+pub struct SftpStorage {
+    server: Arc<Server>,
+    /// Opened on first use and kept for the backend's lifetime; dropped and
+    /// opened again when the connection is lost
+    connection: Arc<Mutex<Option<Connection>>>,
+}
+
+impl SftpStorage {
+    /// Backend for a destination, with the key's passphrase, if one was
+    /// saved, read from the credential store.
+    pub fn for_destination(destination: &str, settings: &SftpSettings) -> Result<Self> {
+        let secret_key = crate::config::destination_key(crate::credentials::STORAGE_SECRET, destination);
+        let passphrase = crate::credentials::get(&secret_key)?.map(Zeroizing::new);
+
+        Ok(Self {
+            server: Arc::new(Server {
+                settings: settings.clone(),
+                passphrase,
+            }),
+            connection: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Runs `op` on a blocking thread with the open session, connecting
+    /// first if needed. When the connection drops, it is opened again and
+    /// `op` retried with backoff.
+    async fn run<T, F>(&self, what: &str, op: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: Fn(&Sftp) -> Result<T> + Send + 'static,
+    {
+        let server = self.server.clone();
+        let connection = self.connection.clone();
+        let what = what.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            for retry_count in 0..MAX_RETRIES {
+                let active = match connection.take() {
+                    Some(active) => active,
+                    None => server.connect()?,
+                };
+                match op(&active.sftp) {
+                    Ok(value) => {
+                        *connection = Some(active);
+                        return Ok(value);
+                    }
+                    Err(e) if is_disconnect(&e) && retry_count < MAX_RETRIES - 1 => {
+                        crate::logger::log_warn(&format!("Failed to {} ({:#}), retrying (attempt {}/{})", what, e, retry_count + 1, MAX_RETRIES));
+                        std::thread::sleep(std::time::Duration::from_secs(2u64.pow(retry_count)));
+                    }
+                    Err(e) => {
+                        if !is_disconnect(&e) {
+                            *connection = Some(active);
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+
+            anyhow::bail!("Max retries ({}) exceeded to {}", MAX_RETRIES, what)
+        })
+        .await
+        .context("SFTP task failed")?
+    }
+}
+
+impl Server {
+    fn port(&self) -> u16 {
+        self.settings.port.unwrap_or(DEFAULT_PORT)
+    }
+
+    fn connect(&self) -> Result<Connection> {
+        let settings = &self.settings;
+        let port = self.port();
+        let tcp = TcpStream::connect((settings.host.as_str(), port))
+            .with_context(|| format!("Failed to connect to {}:{}", settings.host, port))?;
+
+        let mut session = Session::new().context("Failed to start SSH session")?;
+        session.set_timeout(SESSION_TIMEOUT_MS);
+        session.set_tcp_stream(tcp);
+        session.handshake()
+            .with_context(|| format!("SSH handshake with {} failed", settings.host))?;
+        self.verify_host_key(&session)?;
+
+        session.userauth_pubkey_file(
+            &settings.username,
+            None,
+            Path::new(&settings.key_file),
+            self.passphrase.as_ref().map(|passphrase| passphrase.as_str()),
+        ).with_context(|| format!("{} did not accept the key {} for user '{}'", settings.host, settings.key_file, settings.username))?;
+
+        let sftp = session.sftp()
+            .with_context(|| format!("Failed to start SFTP on {}", settings.host))?;
+        Ok(Connection { sftp, _session: session })
+    }
+
+    /// Checks the server's host key against the known_hosts file, refusing
+    /// hosts that aren't listed as well as keys that changed.
+    fn verify_host_key(&self, session: &Session) -> Result<()> {
+        let host = &self.settings.host;
+        let known_hosts_path = match &self.settings.known_hosts {
+            Some(path) => PathBuf::from(path),
+            None => dirs::home_dir()
+                .context("Failed to find the home directory")?
+                .join(".ssh")
+                .join("known_hosts"),
+        };
+
+        let mut known_hosts = session.known_hosts().context("Failed to set up host key checking")?;
+        known_hosts.read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+            .with_context(|| format!("Failed to read {}", known_hosts_path.display()))?;
+        let (key, _) = session.host_key().context("The server did not send a host key")?;
+
+        match known_hosts.check_port(host, self.port(), key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => anyhow::bail!(
+                "The host key of {} does not match the one in {}. If the server's key was changed on purpose, update that file; otherwise the connection may be intercepted.",
+                host, known_hosts_path.display()
+            ),
+            CheckResult::NotFound => anyhow::bail!(
+                "{} is not listed in {}. Connect to it once with ssh to check and save its host key.",
+                host, known_hosts_path.display()
+            ),
+            CheckResult::Failure => anyhow::bail!("Failed to check the host key of {}", host),
+        }
+    }
+
+    /// The remote path of a backend path. Rejects `.`, `..` and backslashes
+    /// so nothing outside the backup directory can be touched.
+    fn remote(&self, path: &str) -> Result<PathBuf> {
+        let mut remote = self.settings.directory.trim_end_matches('/').to_string();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if name == "." || name == ".." || name.contains('\\') {
+                anyhow::bail!("Invalid path in backup directory: {}", path);
+            }
+            remote.push('/');
+            remote.push_str(name);
+        }
+        if remote.is_empty() {
+            remote.push('/');
+        }
+        Ok(PathBuf::from(remote))
+    }
+
+    /// Remote paths of the directories between the backup directory and
+    /// `path`, outermost first.
+    fn parents(&self, path: &str) -> Result<Vec<PathBuf>> {
+        let (dir, _) = crate::storage::split(path);
+        let mut current = String::new();
+        let mut parents = Vec::new();
+        for name in dir.split('/').filter(|name| !name.is_empty()) {
+            current = crate::storage::join(&current, name);
+            parents.push(self.remote(&current)?);
+        }
+        Ok(parents)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for SftpStorage {
+    fn describe(&self) -> String {
+        let settings = &self.server.settings;
+        format!("SFTP directory {}@{}:{}", settings.username, settings.host, settings.directory)
+    }
+
+    /// Creates the backup directory, but not the directories above it, which
+    /// are expected to exist on a server set up for backups.
+    async fn ensure_container(&mut self) -> Result<()> {
+        let root = self.server.remote("")?;
+        self.run("create backup directory", move |sftp| {
+            match sftp.stat(&root) {
+                Ok(stat) if stat.is_dir() => Ok(()),
+                Ok(_) => anyhow::bail!("{} on the server is not a directory", root.display()),
+                Err(e) if is_not_found(&e) => sftp.mkdir(&root, 0o755)
+                    .with_context(|| format!("Failed to create {} on the server", root.display())),
+                Err(e) => Err(e).with_context(|| format!("Failed to read {} on the server", root.display())),
+            }
+        }).await
+    }
+
+    async fn put(&mut self, local: &Path, path: &str, hooks: TransferHooks) -> Result<StoredObject> {
+        let parents = self.server.parents(path)?;
+        let target = self.server.remote(path)?;
+        let local = local.to_path_buf();
+        let path = path.to_string();
+
+        self.run("upload file", move |sftp| {
+            for dir in &parents {
+                create_dir(sftp, dir)?;
+            }
+            upload_atomically(sftp, &local, &target, &hooks)?;
+            let stat = sftp.stat(&target)
+                .with_context(|| format!("Failed to read {} on the server", target.display()))?;
+            Ok(to_stored(&path, &stat))
+        }).await
+    }
+
+    async fn get(&mut self, path: &str, local: &Path, hooks: TransferHooks) -> Result<u64> {
+        let source = self.server.remote(path)?;
+        let local = local.to_path_buf();
+        self.run("download file", move |sftp| download_atomically(sftp, &source, &local, &hooks)).await
+    }
+
+    async fn list(&mut self, dir: &str) -> Result<Vec<StoredObject>> {
+        let dir_path = self.server.remote(dir)?;
+        let dir = dir.to_string();
+
+        self.run("list folder", move |sftp| {
+            let entries = match sftp.readdir(&dir_path) {
+                Ok(entries) => entries,
+                Err(e) if is_not_found(&e) => return Ok(Vec::new()),
+                Err(e) => return Err(e).with_context(|| format!("Failed to list {} on the server", dir_path.display())),
+            };
+
+            let mut objects: Vec<StoredObject> = entries.iter()
+                .filter_map(|(entry_path, stat)| {
+                    let name = entry_path.file_name()?.to_str()?;
+                    // Uploads still in flight, or left behind by a crash
+                    if name.ends_with(".partial") {
+                        return None;
+                    }
+                    Some(to_stored(&crate::storage::join(&dir, name), stat))
+                })
+                .collect();
+            objects.sort_by(|a, b| b.modified_time.cmp(&a.modified_time));
+            Ok(objects)
+        }).await
+    }
+
+    async fn stat(&mut self, path: &str) -> Result<Option<StoredObject>> {
+        let remote = self.server.remote(path)?;
+        let path = path.to_string();
+        self.run("check file", move |sftp| {
+            match sftp.stat(&remote) {
+                Ok(stat) => Ok(Some(to_stored(&path, &stat))),
+                Err(e) if is_not_found(&e) => Ok(None),
+                Err(e) => Err(e).with_context(|| format!("Failed to read {} on the server", remote.display())),
+            }
+        }).await
+    }
+
+    async fn delete(&mut self, path: &str) -> Result<()> {
+        let remote = self.server.remote(path)?;
+        self.run("delete file", move |sftp| {
+            match sftp.unlink(&remote) {
+                Ok(()) => Ok(()),
+                Err(e) if is_not_found(&e) => Ok(()),
+                Err(e) => Err(e).with_context(|| format!("Failed to delete {} on the server", remote.display())),
+            }
+        }).await
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let parents = self.server.parents(to)?;
+        let from_path = self.server.remote(from)?;
+        let to_path = self.server.remote(to)?;
+        self.run("move file", move |sftp| {
+            for dir in &parents {
+                create_dir(sftp, dir)?;
+            }
+            replace(sftp, &from_path, &to_path)
+        }).await
+    }
+}
+
+/// Creates a directory, treating one that already exists as created.
+fn create_dir(sftp: &Sftp, dir: &Path) -> Result<()> {
+    match sftp.mkdir(dir, 0o755) {
+        Ok(()) => Ok(()),
+        Err(_) if sftp.stat(dir).is_ok_and(|stat| stat.is_dir()) => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to create {} on the server", dir.display())),
+    }
+}
+
+/// Writes `local` to a `.partial` sibling of `target` and renames it over
+/// `target`. On failure or cancellation the partial file is removed and
+/// `target` is untouched.
+fn upload_atomically(sftp: &Sftp, local: &Path, target: &Path, hooks: &TransferHooks) -> Result<()> {
+    let partial_path = crate::storage::partial_path(target);
+
+    let result = (|| -> Result<()> {
+        let mut input = fs::File::open(local)
+            .with_context(|| format!("Failed to open {}", local.display()))?;
+        let total = input.metadata().map(|m| m.len()).unwrap_or(0);
+        let mut output = sftp.create(&partial_path)
+            .with_context(|| format!("Failed to create {} on the server", partial_path.display()))?;
+
+        copy_chunks(&mut input, &mut output, total, hooks, Remote::Output)
+            .with_context(|| format!("Failed to upload {}", local.display()))?;
+        // Only some servers support fsync; the data has been written either way
+        let _ = output.fsync();
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = sftp.unlink(&partial_path);
+        return Err(e);
+    }
+    replace(sftp, &partial_path, target)
+}
+
+/// Downloads `source` to a `.partial` sibling of `local`, flushes it and
+/// renames it into place. Returns the number of bytes written.
+fn download_atomically(sftp: &Sftp, source: &Path, local: &Path, hooks: &TransferHooks) -> Result<u64> {
+    let mut input = match sftp.open(source) {
+        Ok(input) => input,
+        Err(e) if is_not_found(&e) => anyhow::bail!("{} not found on the server", source.display()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to open {} on the server", source.display())),
+    };
+    let total = input.stat().ok().and_then(|stat| stat.size).unwrap_or(0);
+    let partial_path = crate::storage::partial_path(local);
+
+    let result = (|| -> Result<u64> {
+        let mut output = fs::File::create(&partial_path)
+            .with_context(|| format!("Failed to create {}", partial_path.display()))?;
+        let received = copy_chunks(&mut input, &mut output, total, hooks, Remote::Input)
+            .with_context(|| format!("Failed to download {}", source.display()))?;
+        output.sync_all()
+            .with_context(|| format!("Failed to flush {}", partial_path.display()))?;
+        Ok(received)
+    })();
+
+    let received = match result {
+        Ok(received) => received,
+        Err(e) => {
+            let _ = fs::remove_file(&partial_path);
+            return Err(e);
+        }
+    };
+    fs::rename(&partial_path, local)
+        .with_context(|| format!("Failed to move download to {}", local.display()))?;
+    Ok(received)
+}
+
+/// Copies `input` to `output` in chunks, reporting progress and stopping
+/// once cancelled. Returns the number of bytes copied.
+fn copy_chunks(input: &mut impl Read, output: &mut impl Write, total: u64, hooks: &TransferHooks, remote: Remote) -> Result<u64> {
+    let tag = |error: std::io::Error, side: Remote| -> anyhow::Error {
+        if side == remote {
+            RemoteIoError(error).into()
+        } else {
+            error.into()
+        }
+    };
+
+    let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+    let mut copied = 0u64;
+    hooks.report(0, total);
+    loop {
+        if hooks.is_cancelled() {
+            anyhow::bail!("Transfer cancelled");
+        }
+        let read = input.read(&mut buffer).map_err(|e| tag(e, Remote::Input))?;
+        if read == 0 {
+            break;
+        }
+        output.write_all(&buffer[..read]).map_err(|e| tag(e, Remote::Output))?;
+        copied += read as u64;
+        hooks.report(copied, total.max(copied));
+    }
+    output.flush().map_err(|e| tag(e, Remote::Output))?;
+    Ok(copied)
+}
+
+/// Renames `from` over `to`. SFTP version 3, which OpenSSH speaks, refuses
+/// to rename onto an existing file; then the old `to` is moved aside and only
+/// deleted once `from` is in place, so a failure never loses both.
+fn replace(sftp: &Sftp, from: &Path, to: &Path) -> Result<()> {
+    let rename_error = match sftp.rename(from, to, None) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    // Anything but an existing target is a real failure, reported as is
+    let target_exists = matches!(rename_error.code(), ErrorCode::SFTP(FX_FAILURE | FX_FILE_ALREADY_EXISTS))
+        && sftp.stat(from).is_ok()
+        && sftp.stat(to).is_ok();
+    if !target_exists {
+        return Err(rename_error)
+            .with_context(|| format!("Failed to move {} to {} on the server", from.display(), to.display()));
+    }
+
+    // Ends in `.partial`, so listings skip it like an unfinished upload
+    let mut aside_name = to.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    aside_name.push(".old.partial");
+    let aside = to.with_file_name(aside_name);
+    // Left over from an earlier run that failed in between; `to` is newer
+    let _ = sftp.unlink(&aside);
+    sftp.rename(to, &aside, None)
+        .with_context(|| format!("Failed to move {} aside on the server", to.display()))?;
+
+    if let Err(e) = sftp.rename(from, to, None) {
+        let _ = sftp.rename(&aside, to, None);
+        return Err(e)
+            .with_context(|| format!("Failed to move {} to {} on the server", from.display(), to.display()));
+    }
+    let _ = sftp.unlink(&aside);
+    Ok(())
+}
+
+fn to_stored(path: &str, stat: &FileStat) -> StoredObject {
+    let (_, name) = crate::storage::split(path);
+    StoredObject {
+        id: path.to_string(),
+        path: path.to_string(),
+        name: name.to_string(),
+        is_dir: stat.is_dir(),
+        size: if stat.is_dir() { None } else { stat.size },
+        modified_time: stat.mtime
+            .and_then(|mtime| DateTime::from_timestamp(mtime as i64, 0))
+            .map(|time| time.to_rfc3339()),
+        etag: None,
+    }
+}
+
+fn is_not_found(error: &ssh2::Error) -> bool {
+    matches!(error.code(), ErrorCode::SFTP(FX_NO_SUCH_FILE | FX_NO_SUCH_PATH))
+}
+
+/// Whether an error means the session is gone, rather than that an
+/// operation failed on a working one. Only errors from the SSH channel
+/// count; a local file that can't be read or written is no reason to
+/// reconnect.
+fn is_disconnect(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<ssh2::Error>() {
+            return matches!(e.code(), ErrorCode::Session(_));
+        }
+        cause.is::<RemoteIoError>()
+    })
+}
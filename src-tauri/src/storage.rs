@@ -0,0 +1,362 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use crate::config::{Destination, StorageConfig};
+
+/// Attempts at a request before giving up, for backends without their own retry policy.
+pub const MAX_RETRIES: u32 = 3;
+
+// Size of the pieces an upload body is streamed in, which sets how often progress is reported
+const UPLOAD_PIECE_SIZE: usize = 256 * 1024;
+
+/// Called with `(bytes_done, bytes_total)` while an upload or download is in flight.
+pub type TransferProgressCallback = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
+/// Optional observers for a single upload or download.
+#[derive(Clone, Default)]
+pub struct TransferHooks {
+    pub on_progress: Option<TransferProgressCallback>,
+    /// Checked before every attempt and between body chunks.
+    pub cancel: Option<crate::sync_engine::CancelToken>,
+}
+
+impl TransferHooks {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|cancel| cancel.is_cancelled())
+    }
+
+    pub fn report(&self, done: u64, total: u64) {
+        if let Some(callback) = &self.on_progress {
+            callback(done, total);
+        }
+    }
+}
+
+/// A file or directory in a backend, addressed by its path below the
+/// destination's backup folder.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredObject {
+    /// The backend's own identifier: a Drive file ID, otherwise the path
+    pub id: String,
+    /// `/`-separated path below the backup folder
+    pub path: String,
+    pub name: String,
+    pub is_dir: bool,
+    pub size: Option<u64>,
+    /// RFC 3339
+    pub modified_time: Option<String>,
+    /// Content tag that changes whenever the content does, if the backend has one
+    pub etag: Option<String>,
+}
+
+/// One stored version of a file's content.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub id: String,
+    #[serde(alias = "modifiedTime")]
+    pub modified_time: Option<String>,
+    /// Size in bytes as reported by the backend (a decimal string)
+    pub size: Option<String>,
+    #[serde(alias = "keepForever", default)]
+    pub keep_forever: bool,
+    #[serde(alias = "md5Checksum")]
+    pub md5_checksum: Option<String>,
+}
+
+/// Somewhere backups are stored. Paths are `/`-separated and relative to the
+/// destination's backup folder; `""` is the folder itself.
+#[async_trait]
+pub trait StorageBackend: Send {
+    /// Where the backups go, for logs, e.g. "Google Drive folder 'backups'".
+    fn describe(&self) -> String;
+
+    /// Creates the backup folder if it doesn't exist yet. Uploads need it;
+    /// everything else treats a missing folder as empty or not found.
+    async fn ensure_container(&mut self) -> Result<()>;
+
+    /// Uploads `local` to `path`, replacing any existing content and creating
+    /// missing parent directories.
+    async fn put(&mut self, local: &Path, path: &str, hooks: TransferHooks) -> Result<StoredObject>;
+
+    /// Downloads `path` to `local`, returning the number of bytes written. A
+    /// failed download never leaves a truncated `local` behind.
+    async fn get(&mut self, path: &str, local: &Path, hooks: TransferHooks) -> Result<u64>;
+
+    /// Files and directories directly inside `dir`, newest first. A directory
+    /// that doesn't exist lists as empty.
+    async fn list(&mut self, dir: &str) -> Result<Vec<StoredObject>>;
+
+    async fn stat(&mut self, path: &str) -> Result<Option<StoredObject>>;
+
+    /// Permanently deletes a file. A file that no longer exists counts as deleted.
+    async fn delete(&mut self, path: &str) -> Result<()>;
+
+    /// Moves a file, creating the target's parent directories.
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()>;
+
+    /// Moves a file to the backend's trash, or deletes it where there is none.
+    async fn trash(&mut self, path: &str) -> Result<()> {
+        self.delete(path).await
+    }
+
+    /// Stored revisions of a file, oldest first. Only some backends keep them.
+    async fn list_revisions(&mut self, _path: &str) -> Result<Vec<Revision>> {
+        anyhow::bail!("{} does not keep revisions", self.describe())
+    }
+
+    /// Downloads one revision of a file, like `get`.
+    async fn get_revision(&mut self, _path: &str, _revision_id: &str, _local: &Path, _hooks: TransferHooks) -> Result<u64> {
+        anyhow::bail!("{} does not keep revisions", self.describe())
+    }
+
+    /// Pins a revision so the backend doesn't purge it, or unpins it.
+    async fn keep_revision(&mut self, _path: &str, _revision_id: &str, _keep_forever: bool) -> Result<()> {
+        anyhow::bail!("{} does not keep revisions", self.describe())
+    }
+
+    async fn delete_revision(&mut self, _path: &str, _revision_id: &str) -> Result<()> {
+        anyhow::bail!("{} does not keep revisions", self.describe())
+    }
+}
+
+/// The backend a destination's config selects.
+pub fn open(destination: &Destination) -> Result<Box<dyn StorageBackend>> {
+    match destination.storage.clone().unwrap_or_default() {
+        StorageConfig::Drive => {
+            let folder = destination.drive_folder.clone()
+                .unwrap_or_else(|| crate::sync_engine::DEFAULT_DRIVE_FOLDER.to_string());
+            Ok(Box::new(crate::drive_sync::DriveSync::for_destination(&destination.name)
+                .with_backup_folder(&folder)))
+        }
+        StorageConfig::Filesystem { path } => {
+            Ok(Box::new(crate::local_storage::LocalStorage::new(path)))
+        }
+        StorageConfig::S3(settings) => {
+            Ok(Box::new(crate::s3_storage::S3Storage::for_destination(&destination.name, &settings)?))
+        }
+        StorageConfig::WebDav(settings) => {
+            Ok(Box::new(crate::webdav_storage::WebDavStorage::for_destination(&destination.name, &settings)?))
+        }
+        StorageConfig::Sftp(settings) => {
+            Ok(Box::new(crate::sftp_storage::SftpStorage::for_destination(&destination.name, &settings)?))
+        }
+    }
+}
+
+/// The backend of a destination looked up by name.
+pub fn open_destination(name: &str) -> Result<Box<dyn StorageBackend>> {
+    open(&crate::config::load_config()?.destination(name)?)
+}
+
+/// `dir/name`, or just `name` at the top of the backup folder.
+pub fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Splits a path into its parent directory and file name.
+pub fn split(path: &str) -> (&str, &str) {
+    match path.rsplit_once('/') {
+        Some((dir, name)) => (dir, name),
+        None => ("", path),
+    }
+}
+
+/// A relative local path as a backend path, with `/` separators on every platform.
+pub fn remote_path(relative: &Path) -> String {
+    relative.components()
+        .filter_map(|component| match component {
+            std::path::Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Sibling of `path` that a transfer writes to before renaming it into place.
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    name.push(".partial");
+    path.with_file_name(name)
+}
+
+/// Wraps one upload chunk in a streaming body that reports how much of the
+/// whole file has been handed to the connection, and aborts the request once
+/// cancelled. `offset` is where the chunk starts within the file.
+pub fn progress_body(data: Vec<u8>, hooks: TransferHooks, offset: u64, total: u64) -> reqwest::Body {
+    let pieces: Vec<Vec<u8>> = data.chunks(UPLOAD_PIECE_SIZE)
+        .map(|piece| piece.to_vec())
+        .collect();
+
+    let mut sent = offset;
+    let stream = futures_util::stream::iter(pieces.into_iter().map(move |piece| {
+        if hooks.is_cancelled() {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Upload cancelled"));
+        }
+        sent += piece.len() as u64;
+        hooks.report(sent, total);
+        Ok(piece)
+    }));
+
+    reqwest::Body::wrap_stream(stream)
+}
+
+/// Streams a file as a request body, reporting progress and aborting once
+/// cancelled like `progress_body`, without holding the file in memory.
+/// Opens the file right away, so a request builder can call it on each attempt.
+pub fn file_body(path: &Path, hooks: TransferHooks) -> Result<(reqwest::Body, u64)> {
+    use tokio::io::AsyncReadExt;
+
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let total = file.metadata()
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .len();
+    let file = tokio::fs::File::from_std(file);
+
+    let stream = futures_util::stream::unfold((file, 0u64), move |(mut file, sent)| {
+        let hooks = hooks.clone();
+        async move {
+            if hooks.is_cancelled() {
+                return Some((Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Upload cancelled")), (file, sent)));
+            }
+            let mut piece = vec![0u8; UPLOAD_PIECE_SIZE];
+            match file.read(&mut piece).await {
+                Ok(0) => None,
+                Ok(read) => {
+                    piece.truncate(read);
+                    let sent = sent + read as u64;
+                    hooks.report(sent, total.max(sent));
+                    Some((Ok(piece), (file, sent)))
+                }
+                Err(e) => Some((Err(e), (file, sent))),
+            }
+        }
+    });
+
+    Ok((reqwest::Body::wrap_stream(stream), total))
+}
+
+/// Sends the request `build` makes, building it again for each retry so it
+/// gets fresh headers and a fresh body. Network errors, server errors and
+/// 429s are retried with backoff; any other response is returned for the
+/// caller to check.
+pub async fn send_with_retry(what: &str, build: impl Fn() -> Result<reqwest::RequestBuilder>) -> Result<reqwest::Response> {
+    for retry_count in 0..MAX_RETRIES {
+        match build()?.send().await {
+            Ok(response) if retry_count < MAX_RETRIES - 1
+                && (response.status().is_server_error() || response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS) => {
+                crate::logger::log_warn(&format!("Failed to {} ({}), retrying (attempt {}/{})", what, response.status(), retry_count + 1, MAX_RETRIES));
+            }
+            Ok(response) => return Ok(response),
+            Err(e) if retry_count < MAX_RETRIES - 1 => {
+                crate::logger::log_warn(&format!("Failed to {} ({}), retrying (attempt {}/{})", what, e, retry_count + 1, MAX_RETRIES));
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to {}", what)),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(2u64.pow(retry_count))).await;
+    }
+
+    anyhow::bail!("Max retries ({}) exceeded to {}", MAX_RETRIES, what)
+}
+
+/// Downloads `path` from an HTTP backend to `local`: sends the request
+/// `build` makes, streams the body with `save_download` and starts over when
+/// the connection drops mid-body. `error_from` turns a failed response into
+/// the backend's own error.
+pub async fn download_with_retry<F>(
+    path: &str,
+    backend: &str,
+    local: &Path,
+    hooks: &TransferHooks,
+    build: impl Fn() -> Result<reqwest::RequestBuilder>,
+    error_from: impl Fn(reqwest::Response) -> F,
+) -> Result<u64>
+where
+    F: std::future::Future<Output = anyhow::Error>,
+{
+    for retry_count in 0..MAX_RETRIES {
+        if hooks.is_cancelled() {
+            anyhow::bail!("Download cancelled");
+        }
+        let response = send_with_retry("download file", &build).await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            anyhow::bail!("{} not found in {}", path, backend);
+        }
+        if !response.status().is_success() {
+            return Err(error_from(response).await);
+        }
+
+        match save_download(response, local, hooks).await? {
+            Download::Complete(received) => return Ok(received),
+            Download::Interrupted(e) if retry_count < MAX_RETRIES - 1 => {
+                crate::logger::log_warn(&format!("Download interrupted ({}), retrying (attempt {}/{})", e, retry_count + 1, MAX_RETRIES));
+            }
+            Download::Interrupted(e) => {
+                return Err(anyhow::Error::new(e).context("Download interrupted"));
+            }
+        }
+    }
+
+    anyhow::bail!("Max retries ({}) exceeded for download", MAX_RETRIES)
+}
+
+/// How streaming a download to disk ended.
+pub enum Download {
+    Complete(u64),
+    /// The connection dropped mid-body; nothing was left on disk, so the
+    /// request can simply be retried.
+    Interrupted(reqwest::Error),
+}
+
+/// Streams a successful response body to `<dest_path>.partial` and renames it
+/// into place once complete, returning the number of bytes written.
+pub async fn save_download(response: reqwest::Response, dest_path: &Path, hooks: &TransferHooks) -> Result<Download> {
+    use futures_util::StreamExt;
+    use std::io::Write;
+
+    let partial_path = partial_path(dest_path);
+    let total = response.content_length().unwrap_or(0);
+    let mut file = std::fs::File::create(&partial_path)
+        .with_context(|| format!("Failed to create {}", partial_path.display()))?;
+    let mut received = 0u64;
+    let mut stream = response.bytes_stream();
+
+    while let Some(piece) = stream.next().await {
+        if hooks.is_cancelled() {
+            drop(file);
+            let _ = std::fs::remove_file(&partial_path);
+            anyhow::bail!("Download cancelled");
+        }
+        match piece {
+            Ok(bytes) => {
+                if let Err(e) = file.write_all(&bytes) {
+                    drop(file);
+                    let _ = std::fs::remove_file(&partial_path);
+                    return Err(e).with_context(|| format!("Failed to write {}", partial_path.display()));
+                }
+                received += bytes.len() as u64;
+                hooks.report(received, total.max(received));
+            }
+            Err(e) => {
+                drop(file);
+                let _ = std::fs::remove_file(&partial_path);
+                return Ok(Download::Interrupted(e));
+            }
+        }
+    }
+
+    file.sync_all()
+        .with_context(|| format!("Failed to flush {}", partial_path.display()))?;
+    drop(file);
+    std::fs::rename(&partial_path, dest_path)
+        .with_context(|| format!("Failed to move download to {}", dest_path.display()))?;
+    Ok(Download::Complete(received))
+}
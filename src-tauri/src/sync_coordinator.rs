@@ -0,0 +1,362 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use tauri::Emitter;
+use tokio::sync::watch;
+use crate::sync_engine::{CancelToken, SyncEngine, SyncError, SyncPhase, SyncPlan, SyncProgress, SyncReport};
+
+const LOCK_FILE_NAME: &str = "sync.lock";
+
+// Minimum gap between `sync-progress` events within the same phase
+const PROGRESS_EMIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Result of a coordinated sync. The error is shared because every caller that
+/// joined the same run receives the same outcome.
+pub type SyncOutcome = Result<SyncSummary, Arc<SyncError>>;
+
+/// What one run did for each destination. A destination that fails doesn't
+/// stop the others; the run only fails as a whole when all of them did.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncSummary {
+    pub reports: Vec<SyncReport>,
+    pub failures: Vec<DestinationFailure>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DestinationFailure {
+    pub destination: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncTrigger {
+    Manual,
+    Scheduled,
+    Tray,
+}
+
+impl SyncTrigger {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SyncTrigger::Manual => "manual",
+            SyncTrigger::Scheduled => "scheduled",
+            SyncTrigger::Tray => "tray",
+        }
+    }
+}
+
+/// Receiver for the run currently in progress in this process, if any.
+/// Requests that arrive while it is set wait on it instead of starting a run.
+static IN_FLIGHT: Mutex<Option<watch::Receiver<Option<SyncOutcome>>>> = Mutex::new(None);
+
+/// Latest progress of the running sync, so a window opened mid-sync can catch up.
+static PROGRESS: Mutex<Option<SyncProgress>> = Mutex::new(None);
+
+/// Cancellation token of the running sync, if any.
+static CANCEL: Mutex<Option<CancelToken>> = Mutex::new(None);
+
+/// Runs a sync, or joins the one already in progress and returns its outcome.
+///
+/// Only the run that actually starts emits `sync-started`, `sync-completed` and
+/// `sync-failed`, so joined requests don't show up twice in the UI.
+pub async fn request_sync(app_handle: &tauri::AppHandle, trigger: SyncTrigger) -> SyncOutcome {
+    let (mut receiver, started) = {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        match in_flight.as_ref() {
+            Some(receiver) => (receiver.clone(), None),
+            None => {
+                // The cancel token is in place before the run becomes visible,
+                // so a cancel_sync that sees the run can always stop it
+                let cancel = CancelToken::new();
+                *CANCEL.lock().unwrap() = Some(cancel.clone());
+                let (sender, receiver) = watch::channel(None);
+                *in_flight = Some(receiver.clone());
+                (receiver, Some((sender, cancel)))
+            }
+        }
+    };
+
+    match started {
+        Some((sender, cancel)) => {
+            let app_handle = app_handle.clone();
+            // Run detached so the sync finishes even if the requesting caller goes away
+            tauri::async_runtime::spawn(async move {
+                let run = RunGuard { app_handle: app_handle.clone(), sender: Some(sender) };
+                let _ = app_handle.emit("sync-started", trigger);
+                let started_at = now_secs();
+
+                let outcome = run_exclusive(&app_handle, trigger, started_at, cancel).await.map_err(Arc::new);
+
+                match &outcome {
+                    Ok(summary) => {
+                        let _ = app_handle.emit("sync-completed", summary.clone());
+                    }
+                    Err(e) if matches!(e.as_ref(), SyncError::Cancelled) => {
+                        crate::logger::log_info("Sync cancelled");
+                        let _ = app_handle.emit("sync-cancelled", ());
+                    }
+                    Err(e) => {
+                        crate::logger::log_error(&format!("Sync failed: {}", e));
+                        let _ = app_handle.emit("sync-failed", e.to_string());
+                    }
+                }
+                run.finish(outcome);
+            });
+        }
+        None => {
+            crate::logger::log_info(&format!("Sync already in progress, joining it ({:?} request)", trigger));
+        }
+    }
+
+    let outcome = match receiver.wait_for(|outcome| outcome.is_some()).await {
+        Ok(outcome) => outcome.clone().unwrap(),
+        Err(_) => Err(Arc::new(SyncError::Interrupted)),
+    };
+    outcome
+}
+
+/// Owns the running sync's slot in `IN_FLIGHT`, `CANCEL` and `PROGRESS` and
+/// clears it however the run ends. A run that panics never reaches `finish`,
+/// so the guard publishes `Interrupted` to everyone waiting on it instead.
+struct RunGuard {
+    app_handle: tauri::AppHandle,
+    sender: Option<watch::Sender<Option<SyncOutcome>>>,
+}
+
+impl RunGuard {
+    fn finish(mut self, outcome: SyncOutcome) {
+        if let Some(sender) = self.sender.take() {
+            // Clear before publishing so later requests start a fresh run
+            clear_run_state();
+            let _ = sender.send(Some(outcome));
+        }
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        // After finish() the slots may already belong to the next run
+        if let Some(sender) = self.sender.take() {
+            clear_run_state();
+            crate::logger::log_error("Sync ended without an outcome");
+            let _ = self.app_handle.emit("sync-failed", SyncError::Interrupted.to_string());
+            let _ = sender.send(Some(Err(Arc::new(SyncError::Interrupted))));
+        }
+    }
+}
+
+/// Forgets the run in progress. Also runs while unwinding from a panic, so it
+/// takes the locks even if they were poisoned.
+fn clear_run_state() {
+    CANCEL.lock().unwrap_or_else(PoisonError::into_inner).take();
+    PROGRESS.lock().unwrap_or_else(PoisonError::into_inner).take();
+    IN_FLIGHT.lock().unwrap_or_else(PoisonError::into_inner).take();
+}
+
+/// Runs `task`, such as a restore, while holding the sync lock, so no sync
+/// in this or another instance can overlap it. Fails with `Locked` if one is
+/// already running.
+pub async fn while_locked<T>(task: impl std::future::Future<Output = T>) -> Result<T, SyncError> {
+    if IN_FLIGHT.lock().unwrap().is_some() {
+        return Err(SyncError::Locked);
+    }
+    let _lock = SyncLock::acquire()?;
+    Ok(task.await)
+}
+
+/// Whether a sync is running in this process or in another app instance.
+pub fn is_syncing() -> bool {
+    if IN_FLIGHT.lock().unwrap().is_some() {
+        return true;
+    }
+    SyncLock::is_held_elsewhere()
+}
+
+/// Asks the running sync to stop at the next file or upload chunk. Returns
+/// `false` when there was nothing to cancel.
+pub fn cancel_sync() -> bool {
+    match CANCEL.lock().unwrap().as_ref() {
+        Some(cancel) => {
+            crate::logger::log_info("Sync cancellation requested");
+            cancel.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Latest progress of the sync in progress, `None` when idle.
+pub fn current_progress() -> Option<SyncProgress> {
+    PROGRESS.lock().unwrap().clone()
+}
+
+/// Syncs every destination in turn while holding the sync lock, recording a
+/// history entry per destination.
+async fn run_exclusive(
+    app_handle: &tauri::AppHandle,
+    trigger: SyncTrigger,
+    started_at: u64,
+    cancel: CancelToken,
+) -> Result<SyncSummary, SyncError> {
+    let prepared = SyncLock::acquire()
+        .and_then(|lock| Ok((lock, SyncPlan::all_from_config()?)));
+    let (_lock, plans) = match prepared {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            record_history(trigger, None, started_at, Err(&e));
+            return Err(e);
+        }
+    };
+
+    let mut summary = SyncSummary::default();
+    let mut first_error = None;
+    for plan in plans {
+        let destination = plan.destination.clone();
+        let destination_started_at = now_secs();
+        let result = run_destination(app_handle, plan, cancel.clone()).await;
+        record_history(trigger, Some(&destination), destination_started_at, result.as_ref());
+
+        match result {
+            Ok(report) => summary.reports.push(report),
+            Err(SyncError::Cancelled) => return Err(SyncError::Cancelled),
+            Err(e) => {
+                crate::logger::log_error(&format!("Sync of destination '{}' failed: {}", destination, e));
+                summary.failures.push(DestinationFailure { destination, error: e.to_string() });
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    match first_error {
+        Some(e) if summary.reports.is_empty() => Err(e),
+        _ => Ok(summary),
+    }
+}
+
+async fn run_destination(app_handle: &tauri::AppHandle, plan: SyncPlan, cancel: CancelToken) -> Result<SyncReport, SyncError> {
+    let app_handle = app_handle.clone();
+    let last_emit: Mutex<Option<(SyncPhase, Instant)>> = Mutex::new(None);
+    let engine = SyncEngine::new(plan).with_progress(move |progress| {
+        *PROGRESS.lock().unwrap() = Some(progress.clone());
+
+        // Throttle per-file and per-chunk updates, but never drop a phase change
+        let mut last_emit = last_emit.lock().unwrap();
+        let due = match *last_emit {
+            Some((phase, at)) => phase != progress.phase || at.elapsed() >= PROGRESS_EMIT_INTERVAL,
+            None => true,
+        };
+        if due {
+            *last_emit = Some((progress.phase, Instant::now()));
+            let _ = app_handle.emit("sync-progress", progress.clone());
+        }
+    }).with_cancel(cancel);
+
+    engine.run().await
+}
+
+fn record_history(
+    trigger: SyncTrigger,
+    destination: Option<&str>,
+    started_at: u64,
+    result: Result<&SyncReport, &SyncError>,
+) {
+    let destination = destination.map(str::to_string);
+    let record = match result {
+        Ok(report) => crate::file_tracker::SyncRunRecord {
+            destination,
+            started_at: report.started_at,
+            finished_at: report.finished_at,
+            trigger: trigger.as_str().to_string(),
+            status: "success".to_string(),
+            files_staged: report.files_staged as u64,
+            uploaded: report.uploaded(),
+            message: None,
+        },
+        Err(e) => crate::file_tracker::SyncRunRecord {
+            destination,
+            started_at,
+            finished_at: now_secs(),
+            trigger: trigger.as_str().to_string(),
+            status: match e {
+                SyncError::Cancelled => "cancelled",
+                _ => "failed",
+            }.to_string(),
+            files_staged: 0,
+            uploaded: false,
+            message: Some(e.to_string()),
+        },
+    };
+
+    if let Err(e) = crate::file_tracker::record_sync_run(&record) {
+        crate::logger::log_error(&format!("Failed to record sync history: {}", e));
+    }
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Advisory lock on `sync.lock` in the data directory. The OS releases it
+/// when the holding process exits, so a crash can't leave it stuck.
+struct SyncLock {
+    file: File,
+}
+
+impl SyncLock {
+    fn path() -> Result<PathBuf, SyncError> {
+        Ok(crate::config::get_data_dir()
+            .map_err(SyncError::Config)?
+            .join(LOCK_FILE_NAME))
+    }
+
+    fn acquire() -> Result<Self, SyncError> {
+        let path = Self::path()?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(SyncError::LockFile)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Err(SyncError::Locked),
+            Err(TryLockError::Error(e)) => return Err(SyncError::LockFile(e)),
+        }
+
+        // Record the holder for anyone inspecting the data directory
+        let _ = file.set_len(0);
+        let _ = write!(file, "{}", std::process::id());
+
+        Ok(Self { file })
+    }
+
+    fn is_held_elsewhere() -> bool {
+        let file = match Self::path().ok().and_then(|path| File::open(path).ok()) {
+            Some(file) => file,
+            None => return false,
+        };
+
+        match file.try_lock_shared() {
+            Ok(()) => {
+                let _ = file.unlock();
+                false
+            }
+            Err(TryLockError::WouldBlock) => true,
+            Err(TryLockError::Error(_)) => false,
+        }
+    }
+}
+
+impl Drop for SyncLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
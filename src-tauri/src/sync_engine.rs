@@ -0,0 +1,1174 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::Serialize;
+use thiserror::Error;
+use crate::config::{DeletionPolicy, Destination, RetentionPolicy, SyncMode};
+use crate::crypto::EncryptionKey;
+use crate::manifest::Manifest;
+use crate::storage::StorageBackend;
+use crate::version_manager::ArchiveCandidate;
+
+pub const DEFAULT_DRIVE_FOLDER: &str = "sync-bot-backups";
+
+// Staging layout: files are copied into PARTIAL_DIR and only swapped into
+// TRACKED_DIR once every copy succeeded, so a cancelled or failed run never
+// leaves a half-updated tree behind.
+const TRACKED_DIR: &str = "tracked";
+const PARTIAL_DIR: &str = "tracked.partial";
+const PREVIOUS_DIR: &str = "tracked.old";
+
+// Scratch directory for encrypted copies of mirrored files while they upload
+const ENCRYPTED_DIR: &str = "encrypted";
+
+// Folder inside the backup folder that receives tombstoned files in mirror mode
+const TOMBSTONE_DIR: &str = "deleted";
+
+// Folder inside the backup folder that receives timestamped archives
+pub const VERSIONS_DIR: &str = "versions";
+
+// app_metadata key holding the manifest digest of the last archive uploaded to
+// VERSIONS_DIR, suffixed per destination by `config::destination_key`
+const LAST_VERSION_DIGEST_KEY: &str = "last_version_digest";
+
+// app_metadata key holding the ETag the backend reported for the uploaded
+// ZIP, suffixed per destination like LAST_VERSION_DIGEST_KEY
+const UPLOADED_ZIP_ETAG_KEY: &str = "uploaded_zip_etag";
+
+// app_metadata key holding the manifest digest `backup.zip` was last built
+// from, suffixed per destination
+const ZIP_CONTENT_DIGEST_KEY: &str = "zip_content_digest";
+
+// app_metadata key holding the hash of the plain ZIP and the fingerprint of
+// the key `backup.zip.enc` was made from, suffixed per destination
+const ENCRYPTED_ZIP_SOURCE_KEY: &str = "encrypted_zip_source";
+
+// Prefix of the app_metadata keys holding the same for the encrypted copy of
+// a mirrored file, followed by the copy's path
+const ENCRYPTED_FILE_SOURCE_PREFIX: &str = "encrypted_source:";
+
+// app_metadata key holding, as a JSON list, the remote paths of plaintext
+// copies uploaded before encryption was turned on, suffixed per destination
+const PLAINTEXT_LEFTOVERS_KEY: &str = "plaintext_leftovers";
+
+#[derive(Debug, Error)]
+pub enum SyncError {
+    #[error("Failed to load config: {0}")]
+    Config(anyhow::Error),
+    #[error("No files or folders tracked. Please add files/folders first.")]
+    NothingTracked,
+    #[error("Failed to read tracked files: {0}")]
+    Tracker(anyhow::Error),
+    #[error("Failed to create staging directory {}: {source}", path.display())]
+    StagingDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to copy {} to {}: {source}", from.display(), to.display())]
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to build archive manifest: {0}")]
+    Manifest(anyhow::Error),
+    #[error("Failed to encrypt backup: {0}")]
+    Encryption(anyhow::Error),
+    #[error("Failed to build ZIP: {0}")]
+    Zip(anyhow::Error),
+    #[error("Failed to check if ZIP changed: {0}")]
+    ChangeDetection(anyhow::Error),
+    #[error("Failed to open backup storage: {0}")]
+    Storage(anyhow::Error),
+    #[error("Failed to upload ZIP file: {0}")]
+    Upload(anyhow::Error),
+    #[error("Another sync or a restore is already running")]
+    Locked,
+    #[error("Failed to open sync lock file: {0}")]
+    LockFile(std::io::Error),
+    #[error("Sync ended unexpectedly")]
+    Interrupted,
+    #[error("Sync was cancelled")]
+    Cancelled,
+}
+
+/// Cooperative cancellation flag shared between a running sync and whoever
+/// may want to stop it. The engine checks it between files and upload chunks.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn check(&self) -> Result<(), SyncError> {
+        if self.is_cancelled() {
+            return Err(SyncError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// Everything a sync of one destination needs to know up front, resolved
+/// from config and the tracked-path table.
+#[derive(Debug, Clone)]
+pub struct SyncPlan {
+    pub destination: String,
+    pub mode: SyncMode,
+    /// What happens to the remote copy of a file deleted locally (mirror mode)
+    pub deletion_policy: DeletionPolicy,
+    pub staging_dir: PathBuf,
+    pub archives_dir: PathBuf,
+    /// Where the backups go; `storage::open` turns it into a backend
+    pub remote: Destination,
+    pub retention: RetentionPolicy,
+    /// Upload each timestamped archive into `versions/` in the backup folder
+    pub upload_versions: bool,
+    /// Key that archives and mirrored files are encrypted with before upload
+    pub encryption_key: Option<EncryptionKey>,
+    pub tracked_paths: Vec<PathBuf>,
+    pub files: Vec<PathBuf>,
+}
+
+impl SyncPlan {
+    /// One plan per destination that has tracked paths, the default
+    /// destination first.
+    pub fn all_from_config() -> Result<Vec<Self>, SyncError> {
+        let config = crate::config::load_config()
+            .map_err(SyncError::Config)?;
+        let encryption_key = crate::crypto::load_configured_key()
+            .map_err(SyncError::Encryption)?;
+
+        let mut plans = Vec::new();
+        for destination in config.destinations() {
+            let plan = Self::for_destination(&config, &destination, encryption_key.clone())?;
+            if !plan.tracked_paths.is_empty() {
+                plans.push(plan);
+            }
+        }
+
+        if plans.is_empty() {
+            crate::logger::log_warn("No files or folders are being tracked");
+            return Err(SyncError::NothingTracked);
+        }
+        Ok(plans)
+    }
+
+    fn for_destination(
+        config: &crate::config::Config,
+        destination: &Destination,
+        encryption_key: Option<EncryptionKey>,
+    ) -> Result<Self, SyncError> {
+        let staging_dir = crate::config::get_destination_staging_dir(&destination.name)
+            .map_err(SyncError::Config)?;
+        let archives_dir = crate::config::get_destination_archives_dir(&destination.name)
+            .map_err(SyncError::Config)?;
+
+        let tracked_paths = crate::file_tracker::get_tracked_paths_for(&destination.name)
+            .map_err(SyncError::Tracker)?
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        let files = crate::file_tracker::get_files_to_sync(&destination.name)
+            .map_err(SyncError::Tracker)?;
+
+        Ok(Self {
+            destination: destination.name.clone(),
+            mode: config.sync_mode.unwrap_or_default(),
+            deletion_policy: config.deletion_policy.unwrap_or_default(),
+            staging_dir,
+            archives_dir,
+            remote: destination.clone(),
+            retention: config.retention.clone().unwrap_or_default(),
+            upload_versions: config.upload_versions.unwrap_or(false),
+            encryption_key,
+            tracked_paths,
+            files,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncReport {
+    pub destination: String,
+    pub mode: SyncMode,
+    pub files_staged: usize,
+    pub archive_path: Option<PathBuf>,
+    /// Rolling `backup.zip`, only built in archive mode.
+    pub zip_path: Option<PathBuf>,
+    /// Backend ID of the uploaded ZIP, `None` when the upload was skipped
+    /// because nothing changed or when mirroring.
+    pub uploaded_file_id: Option<String>,
+    /// Backend ID of the archive uploaded to `versions/`, if any.
+    pub version_file_id: Option<String>,
+    pub files_uploaded: usize,
+    pub files_unchanged: usize,
+    /// Files removed locally whose remote copy was trashed or tombstoned (mirror mode).
+    pub files_deleted: usize,
+    /// Per-file failures that didn't stop the rest of the sync.
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    pub started_at: u64,
+    pub finished_at: u64,
+}
+
+impl SyncReport {
+    pub fn uploaded(&self) -> bool {
+        self.files_uploaded > 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncPhase {
+    Staging,
+    Archiving,
+    Zipping,
+    CheckingChanges,
+    Uploading,
+    Deleting,
+    Finished,
+}
+
+/// Snapshot of a running sync. File and byte counters refer to the current
+/// phase; the upload counters are only filled in while uploading.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncProgress {
+    /// Destination being synced, filled in by the engine
+    pub destination: Option<String>,
+    pub phase: SyncPhase,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub current_path: Option<String>,
+    pub upload_bytes_sent: u64,
+    pub upload_bytes_total: u64,
+}
+
+impl SyncProgress {
+    fn phase(phase: SyncPhase) -> Self {
+        Self {
+            destination: None,
+            phase,
+            files_done: 0,
+            files_total: 0,
+            bytes_done: 0,
+            bytes_total: 0,
+            current_path: None,
+            upload_bytes_sent: 0,
+            upload_bytes_total: 0,
+        }
+    }
+
+    fn files(phase: SyncPhase, files_total: usize, bytes_total: u64) -> Self {
+        Self {
+            files_total,
+            bytes_total,
+            ..Self::phase(phase)
+        }
+    }
+}
+
+pub type ProgressCallback = Arc<dyn Fn(&SyncProgress) + Send + Sync>;
+
+pub struct SyncEngine {
+    plan: SyncPlan,
+    on_progress: Option<ProgressCallback>,
+    cancel: CancelToken,
+}
+
+impl SyncEngine {
+    pub fn new(plan: SyncPlan) -> Self {
+        Self {
+            plan,
+            on_progress: None,
+            cancel: CancelToken::new(),
+        }
+    }
+
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn with_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&SyncProgress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(callback));
+        self
+    }
+
+    pub async fn run(&self) -> Result<SyncReport, SyncError> {
+        let started_at = now_secs();
+        let plan = &self.plan;
+
+        crate::logger::log_info(&format!("Syncing destination '{}'", plan.destination));
+        crate::logger::log_info(&format!("Staging directory: {:?}", plan.staging_dir));
+
+        if plan.tracked_paths.is_empty() {
+            crate::logger::log_warn("No files or folders are being tracked");
+            return Err(SyncError::NothingTracked);
+        }
+
+        crate::logger::log_info(&format!("Found {} tracked path(s)", plan.tracked_paths.len()));
+        crate::logger::log_info(&format!("Total files to process: {}", plan.files.len()));
+
+        let mut storage = crate::storage::open(&plan.remote)
+            .map_err(SyncError::Storage)?;
+        crate::logger::log_info(&format!("Backing up to {}", storage.describe()));
+
+        let (files_staged, manifest) = self.stage_files()?;
+
+        // Create archive before sync (for version history)
+        let tracked_dir = plan.staging_dir.join(TRACKED_DIR);
+        let (files_total, bytes_total) = crate::version_manager::directory_totals(&tracked_dir)
+            .unwrap_or_default();
+        let mut progress = SyncProgress::files(SyncPhase::Archiving, files_total, bytes_total);
+        self.report(progress.clone());
+        let mut warnings = Vec::new();
+        let archive_result = crate::version_manager::create_archive(
+            &tracked_dir,
+            TRACKED_DIR,
+            &plan.archives_dir,
+            &manifest.with_prefix(TRACKED_DIR),
+            &plan.retention,
+            plan.encryption_key.as_ref(),
+            &mut |path, size| self.advance_cancellable(&mut progress, path, size),
+        );
+        let archive_path = match archive_result {
+            Ok(path) => Some(path),
+            Err(_) if self.cancel.is_cancelled() => return Err(SyncError::Cancelled),
+            Err(e) => {
+                let msg = format!("Warning: Failed to create archive: {}", e);
+                crate::logger::log_warn(&msg);
+                warnings.push(msg);
+                None
+            }
+        };
+
+        let mut report = SyncReport {
+            destination: plan.destination.clone(),
+            mode: plan.mode,
+            files_staged,
+            archive_path,
+            zip_path: None,
+            uploaded_file_id: None,
+            version_file_id: None,
+            files_uploaded: 0,
+            files_unchanged: 0,
+            files_deleted: 0,
+            errors: Vec::new(),
+            warnings,
+            started_at,
+            finished_at: 0,
+        };
+
+        match plan.mode {
+            SyncMode::Archive => self.sync_archive(storage.as_mut(), &mut report, &manifest).await?,
+            SyncMode::Mirror => self.sync_mirror(storage.as_mut(), &mut report).await?,
+        }
+
+        if plan.upload_versions {
+            if let Some(archive_path) = report.archive_path.clone() {
+                self.upload_version(storage.as_mut(), &archive_path, &manifest, &mut report).await?;
+            }
+        }
+
+        if plan.encryption_key.is_some() {
+            self.delete_plaintext_leftovers(storage.as_mut(), &mut report).await?;
+        }
+
+        match crate::file_tracker::prune_file_metadata(&plan.tracked_paths) {
+            Ok(0) => {}
+            Ok(pruned) => crate::logger::log_info(&format!("Pruned {} stale file metadata row(s)", pruned)),
+            Err(e) => crate::logger::log_warn(&format!("Failed to prune file metadata: {}", e)),
+        }
+
+        // Save last sync time; also done when the upload was skipped to show we checked
+        report.finished_at = now_secs();
+        let _ = crate::file_tracker::set_metadata("last_sync_time", &report.finished_at.to_string());
+        self.report(SyncProgress::phase(SyncPhase::Finished));
+
+        Ok(report)
+    }
+
+    /// Archive mode: rebuilds `backup.zip` and uploads it when it changed.
+    async fn sync_archive(&self, storage: &mut dyn StorageBackend, report: &mut SyncReport, manifest: &Manifest) -> Result<(), SyncError> {
+        let zip_path = self.build_zip(manifest)?;
+        report.zip_path = Some(zip_path.clone());
+
+        // Check if ZIP has changed (for smart sync)
+        self.report(SyncProgress::phase(SyncPhase::CheckingChanges));
+        let zip_changed = crate::file_tracker::has_file_changed(&zip_path)
+            .map_err(SyncError::ChangeDetection)?;
+        let remote_changed = !zip_changed && self.remote_zip_changed(storage, &zip_path).await;
+        self.cancel.check()?;
+
+        if zip_changed || remote_changed {
+            self.report(SyncProgress::phase(SyncPhase::Uploading));
+            report.uploaded_file_id = Some(self.upload(storage, &zip_path).await?);
+            report.files_uploaded = 1;
+        } else {
+            crate::logger::log_info("ZIP file has not changed, skipping upload");
+            report.files_unchanged = 1;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the uploaded ZIP was replaced or removed on the server since it
+    /// was uploaded, judged by the ETag recorded then. Backends without ETags
+    /// never report a change, and neither does a failed check.
+    async fn remote_zip_changed(&self, storage: &mut dyn StorageBackend, zip_path: &Path) -> bool {
+        let etag_key = crate::config::destination_key(UPLOADED_ZIP_ETAG_KEY, &self.plan.destination);
+        let Some(etag) = crate::file_tracker::get_metadata(&etag_key).unwrap_or(None) else {
+            return false;
+        };
+        let Some(name) = self.upload_name(zip_path) else {
+            return false;
+        };
+
+        match storage.stat(&name).await {
+            Ok(Some(object)) if object.etag.as_deref() == Some(etag.as_str()) => false,
+            Ok(Some(_)) => {
+                crate::logger::log_warn(&format!("{} was changed on {} since it was uploaded, uploading it again", name, storage.describe()));
+                true
+            }
+            Ok(None) => {
+                crate::logger::log_warn(&format!("{} is missing from {}, uploading it again", name, storage.describe()));
+                true
+            }
+            Err(e) => {
+                crate::logger::log_warn(&format!("Failed to check {} on {}: {}", name, storage.describe(), e));
+                false
+            }
+        }
+    }
+
+    /// The name the ZIP is stored under: its own, or its encrypted copy's.
+    fn upload_name(&self, zip_path: &Path) -> Option<String> {
+        let upload_path = match self.plan.encryption_key {
+            Some(_) => crate::crypto::encrypted_path(zip_path),
+            None => zip_path.to_path_buf(),
+        };
+        upload_path.file_name()
+            .and_then(|name| name.to_str())
+            .map(str::to_string)
+    }
+
+    /// Uploads this run's timestamped archive into `versions/` and prunes old
+    /// versions with the same retention policy as the local archives. Skipped
+    /// when the archive holds the same files as the last uploaded version.
+    async fn upload_version(
+        &self,
+        storage: &mut dyn StorageBackend,
+        archive_path: &Path,
+        manifest: &Manifest,
+        report: &mut SyncReport,
+    ) -> Result<(), SyncError> {
+        let digest = manifest.content_digest();
+        let digest_key = crate::config::destination_key(LAST_VERSION_DIGEST_KEY, &self.plan.destination);
+        let last_digest = crate::file_tracker::get_metadata(&digest_key).unwrap_or(None);
+        if last_digest.as_deref() == Some(digest.as_str()) {
+            crate::logger::log_info("Archive matches the last uploaded version, skipping version upload");
+            return Ok(());
+        }
+
+        storage.ensure_container()
+            .await
+            .map_err(SyncError::Storage)?;
+        self.cancel.check()?;
+
+        let Some(archive_name) = archive_path.file_name().and_then(|name| name.to_str()) else {
+            return Ok(());
+        };
+        let size = std::fs::metadata(archive_path).map(|m| m.len()).unwrap_or(0);
+        let mut progress = SyncProgress::files(SyncPhase::Uploading, 1, size);
+        progress.current_path = Some(archive_path.to_string_lossy().to_string());
+        self.report(progress.clone());
+
+        let version_path = crate::storage::join(VERSIONS_DIR, archive_name);
+        match storage.put(archive_path, &version_path, self.upload_hooks(&progress)).await {
+            Ok(object) => {
+                let _ = crate::file_tracker::set_metadata(&digest_key, &digest);
+                crate::logger::log_info(&format!("Uploaded {} to {}/", archive_path.display(), VERSIONS_DIR));
+                report.version_file_id = Some(object.id);
+            }
+            Err(_) if self.cancel.is_cancelled() => return Err(SyncError::Cancelled),
+            Err(e) => {
+                let msg = format!("Failed to upload archive version {}: {}", archive_path.display(), e);
+                crate::logger::log_error(&msg);
+                report.errors.push(msg);
+                return Ok(());
+            }
+        }
+        self.advance(&mut progress, archive_path, size);
+
+        self.prune_versions(storage, report).await
+    }
+
+    /// Deletes the archives in `versions/` that the retention policy no longer keeps.
+    async fn prune_versions(
+        &self,
+        storage: &mut dyn StorageBackend,
+        report: &mut SyncReport,
+    ) -> Result<(), SyncError> {
+        let files = match storage.list(VERSIONS_DIR).await {
+            Ok(files) => files,
+            Err(e) => {
+                let msg = format!("Failed to list {}/ for pruning: {}", VERSIONS_DIR, e);
+                crate::logger::log_warn(&msg);
+                report.warnings.push(msg);
+                return Ok(());
+            }
+        };
+
+        let candidates: Vec<ArchiveCandidate> = files.iter()
+            .filter(|file| !file.is_dir)
+            .filter_map(|file| ArchiveCandidate::from_name(&file.name, file.size.unwrap_or(0)))
+            .collect();
+        let decisions = crate::version_manager::select_for_pruning(
+            &candidates,
+            &self.plan.retention,
+            chrono::Local::now().naive_local(),
+        );
+
+        for decision in decisions {
+            self.cancel.check()?;
+            let Some(file) = files.iter().find(|file| file.name == decision.name) else { continue };
+            match storage.delete(&file.path).await {
+                Ok(()) => crate::logger::log_info(&format!("Pruned {}/{} from {} ({})", VERSIONS_DIR, decision.name, storage.describe(), decision.reason)),
+                Err(e) => {
+                    let msg = format!("Failed to prune {}/{}: {}", VERSIONS_DIR, decision.name, e);
+                    crate::logger::log_error(&msg);
+                    report.errors.push(msg);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the plaintext copies left from before encryption was turned on,
+    /// once this sync has uploaded their encrypted replacements without errors.
+    /// Archives in `versions/` and `deleted/` are history and stay, so the
+    /// user is told about them instead.
+    async fn delete_plaintext_leftovers(&self, storage: &mut dyn StorageBackend, report: &mut SyncReport) -> Result<(), SyncError> {
+        let key = crate::config::destination_key(PLAINTEXT_LEFTOVERS_KEY, &self.plan.destination);
+        let Some(value) = crate::file_tracker::get_metadata(&key).map_err(SyncError::Tracker)? else {
+            return Ok(());
+        };
+        if !report.errors.is_empty() {
+            return Ok(());
+        }
+        let paths: Vec<String> = serde_json::from_str(&value).unwrap_or_default();
+
+        let mut remaining = Vec::new();
+        for path in paths {
+            self.cancel.check()?;
+            match storage.delete(&path).await {
+                Ok(()) => crate::logger::log_info(&format!("Deleted unencrypted copy {} from {}", path, storage.describe())),
+                Err(e) => {
+                    let msg = format!("Failed to delete unencrypted copy {} from {}: {}", path, storage.describe(), e);
+                    crate::logger::log_warn(&msg);
+                    report.warnings.push(msg);
+                    remaining.push(path);
+                }
+            }
+        }
+
+        let saved = if remaining.is_empty() {
+            let msg = format!(
+                "Encryption is on for {}; files in its {}/ and {}/ folders from before then are still unencrypted",
+                storage.describe(), VERSIONS_DIR, TOMBSTONE_DIR
+            );
+            crate::logger::log_warn(&msg);
+            report.warnings.push(msg);
+            crate::file_tracker::remove_metadata(&key)
+        } else {
+            serde_json::to_string(&remaining)
+                .map_err(anyhow::Error::from)
+                .and_then(|value| crate::file_tracker::set_metadata(&key, &value))
+        };
+        saved.map_err(SyncError::Tracker)
+    }
+
+    /// Mirror mode: recreates the tracked tree inside the backup folder and
+    /// uploads only the source files that changed since they were last synced.
+    async fn sync_mirror(&self, storage: &mut dyn StorageBackend, report: &mut SyncReport) -> Result<(), SyncError> {
+        let plan = &self.plan;
+
+        self.report(SyncProgress::files(SyncPhase::CheckingChanges, plan.files.len(), 0));
+        let mut changed = Vec::new();
+        for file_path in &plan.files {
+            self.cancel.check()?;
+            match crate::file_tracker::has_file_changed(file_path) {
+                Ok(true) => changed.push(file_path),
+                Ok(false) => report.files_unchanged += 1,
+                Err(e) => report.errors.push(format!("Failed to check {}: {}", file_path.display(), e)),
+            }
+        }
+
+        let vanished = self.vanished_mirror_files(report)?;
+
+        crate::logger::log_info(&format!("{} changed file(s) to mirror, {} unchanged, {} deleted locally", changed.len(), report.files_unchanged, vanished.len()));
+        if changed.is_empty() && vanished.is_empty() {
+            return Ok(());
+        }
+
+        storage.ensure_container()
+            .await
+            .map_err(SyncError::Storage)?;
+
+        let bytes_total = changed.iter()
+            .filter_map(|file| std::fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        let mut progress = SyncProgress::files(SyncPhase::Uploading, changed.len(), bytes_total);
+
+        for file_path in changed {
+            self.cancel.check()?;
+
+            let relative_path = tracked_relative_path(&plan.tracked_paths, file_path);
+            let size = std::fs::metadata(file_path).map(|m| m.len()).unwrap_or(0);
+
+            progress.current_path = Some(file_path.to_string_lossy().to_string());
+            self.report(progress.clone());
+
+            let upload_path = match &plan.encryption_key {
+                Some(key) => {
+                    let encrypted = crate::crypto::encrypted_path(&plan.staging_dir.join(ENCRYPTED_DIR).join(&relative_path));
+                    if let Some(parent) = encrypted.parent() {
+                        std::fs::create_dir_all(parent)
+                            .map_err(|source| SyncError::StagingDir { path: parent.to_path_buf(), source })?;
+                    }
+                    let source_key = format!("{}{}", ENCRYPTED_FILE_SOURCE_PREFIX, encrypted.to_string_lossy());
+                    if let Err(e) = encrypt_for_upload(file_path, &encrypted, key, &source_key) {
+                        let msg = format!("Failed to encrypt {}: {}", file_path.display(), e);
+                        crate::logger::log_error(&msg);
+                        report.errors.push(msg);
+                        self.advance(&mut progress, file_path, size);
+                        continue;
+                    }
+                    encrypted
+                }
+                None => file_path.clone(),
+            };
+            let remote_path = crate::storage::remote_path(&match &plan.encryption_key {
+                Some(_) => crate::crypto::encrypted_path(&relative_path),
+                None => relative_path.clone(),
+            });
+
+            let upload_result = storage.put(&upload_path, &remote_path, self.upload_hooks(&progress)).await;
+
+            match upload_result {
+                Ok(object) => {
+                    // Kept until now so a failed upload resumes with the same bytes
+                    if upload_path != *file_path {
+                        let _ = std::fs::remove_file(&upload_path);
+                        let _ = crate::file_tracker::remove_metadata(&format!("{}{}", ENCRYPTED_FILE_SOURCE_PREFIX, upload_path.to_string_lossy()));
+                    }
+                    if let Err(e) = crate::file_tracker::mark_file_synced(file_path) {
+                        crate::logger::log_error(&format!("Failed to mark {} as synced: {}", file_path.display(), e));
+                    }
+                    let mirror_file = crate::file_tracker::MirrorFile {
+                        path: file_path.to_string_lossy().to_string(),
+                        destination: plan.destination.clone(),
+                        parent_id: crate::storage::split(&remote_path).0.to_string(),
+                        remote_path,
+                        remote_id: object.id,
+                    };
+                    if let Err(e) = crate::file_tracker::record_mirror_file(&mirror_file) {
+                        crate::logger::log_error(&format!("Failed to record mirrored file {}: {}", file_path.display(), e));
+                    }
+                    crate::logger::log_info(&format!("Mirrored {} to {}", file_path.display(), mirror_file.remote_path));
+                    report.files_uploaded += 1;
+                }
+                Err(_) if self.cancel.is_cancelled() => return Err(SyncError::Cancelled),
+                Err(e) => {
+                    let msg = format!("Failed to upload {}: {}", file_path.display(), e);
+                    crate::logger::log_error(&msg);
+                    report.errors.push(msg);
+                }
+            }
+
+            self.advance(&mut progress, file_path, size);
+        }
+
+        if !vanished.is_empty() {
+            self.propagate_deletions(storage, vanished, report).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Mirrored files that are no longer on disk. Rows for files that still
+    /// exist but are simply not tracked anymore are forgotten without
+    /// touching their remote copy. Files under a tracked path that is missing
+    /// itself are left alone: an unmounted drive or a renamed folder is not a
+    /// deletion.
+    fn vanished_mirror_files(&self, report: &mut SyncReport) -> Result<Vec<crate::file_tracker::MirrorFile>, SyncError> {
+        let current: HashSet<String> = self.plan.files.iter()
+            .map(|file| file.to_string_lossy().to_string())
+            .collect();
+        let missing_roots: Vec<&PathBuf> = self.plan.tracked_paths.iter()
+            .filter(|root| !root.exists())
+            .collect();
+        for root in &missing_roots {
+            let msg = format!("Tracked path {} is missing; not propagating deletions under it", root.display());
+            crate::logger::log_warn(&msg);
+            report.warnings.push(msg);
+        }
+
+        let mut vanished = Vec::new();
+        for mirror_file in crate::file_tracker::get_mirror_files(&self.plan.destination).map_err(SyncError::Tracker)? {
+            if current.contains(&mirror_file.path) {
+                continue;
+            }
+            let path = Path::new(&mirror_file.path);
+            if missing_roots.iter().any(|root| path.starts_with(root)) {
+                continue;
+            }
+            if path.exists() {
+                let _ = crate::file_tracker::remove_mirror_file(&mirror_file.path);
+            } else {
+                vanished.push(mirror_file);
+            }
+        }
+
+        Ok(vanished)
+    }
+
+    /// Applies the deletion policy to the remote copies of locally deleted files.
+    async fn propagate_deletions(
+        &self,
+        storage: &mut dyn StorageBackend,
+        vanished: Vec<crate::file_tracker::MirrorFile>,
+        report: &mut SyncReport,
+    ) -> Result<(), SyncError> {
+        let policy = self.plan.deletion_policy;
+        let mut progress = SyncProgress::files(SyncPhase::Deleting, vanished.len(), 0);
+        // Tombstones from one sync share a timestamped folder under deleted/
+        let tombstone_dir = crate::storage::join(
+            TOMBSTONE_DIR,
+            &chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string(),
+        );
+
+        for mirror_file in vanished {
+            self.cancel.check()?;
+            let local_path = PathBuf::from(&mirror_file.path);
+            // Rows recorded before the storage layer may use `\` on Windows
+            let remote_path = mirror_file.remote_path.replace('\\', "/");
+
+            let result = match policy {
+                DeletionPolicy::Keep => Ok(()),
+                DeletionPolicy::Trash => storage.trash(&remote_path).await,
+                DeletionPolicy::Tombstone => {
+                    storage.rename(&remote_path, &crate::storage::join(&tombstone_dir, &remote_path)).await
+                }
+            };
+
+            match result {
+                Ok(()) => {
+                    let outcome = match policy {
+                        DeletionPolicy::Keep => "kept",
+                        DeletionPolicy::Trash => "trashed",
+                        DeletionPolicy::Tombstone => "moved to deleted/",
+                    };
+                    crate::logger::log_info(&format!("Local file {} was deleted; remote copy {}", local_path.display(), outcome));
+                    let _ = crate::file_tracker::remove_mirror_file(&mirror_file.path);
+                    let _ = crate::file_tracker::remove_file_metadata(&local_path);
+                    if policy != DeletionPolicy::Keep {
+                        report.files_deleted += 1;
+                    }
+                }
+                Err(e) => {
+                    let msg = format!("Failed to apply deletion of {} to {}: {}", local_path.display(), storage.describe(), e);
+                    crate::logger::log_error(&msg);
+                    report.errors.push(msg);
+                }
+            }
+
+            self.advance(&mut progress, &local_path, 0);
+        }
+
+        Ok(())
+    }
+
+    /// Copies every tracked file that lives outside the staging directory into
+    /// `staging/tracked`, returning how many files were copied.
+    ///
+    /// The copy goes to a scratch directory first and replaces `tracked` only
+    /// once complete; on cancellation or error the scratch copy is removed.
+    fn stage_files(&self) -> Result<(usize, Manifest), SyncError> {
+        let staging_dir = &self.plan.staging_dir;
+        let partial_dir = staging_dir.join(PARTIAL_DIR);
+
+        // Leftover from a run that was killed mid-copy
+        remove_staging_tree(&partial_dir)?;
+
+        match self.copy_tracked_files(&partial_dir) {
+            Ok(staged) => {
+                replace_tracked_dir(staging_dir)?;
+                Ok(staged)
+            }
+            Err(e) => {
+                crate::logger::log_warn("Staging did not complete, discarding partial copy");
+                let _ = remove_staging_tree(&partial_dir);
+                Err(e)
+            }
+        }
+    }
+
+    fn copy_tracked_files(&self, target_root: &Path) -> Result<(usize, Manifest), SyncError> {
+        let plan = &self.plan;
+        let bytes_total = plan.files.iter()
+            .filter_map(|file| std::fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        let mut progress = SyncProgress::files(SyncPhase::Staging, plan.files.len(), bytes_total);
+        let mut staged = 0;
+        let mut manifest = Manifest::new();
+        for tracked_path in &plan.tracked_paths {
+            manifest.add_root(&tracked_relative_path(&plan.tracked_paths, tracked_path), tracked_path);
+        }
+
+        std::fs::create_dir_all(target_root)
+            .map_err(|source| SyncError::StagingDir { path: target_root.to_path_buf(), source })?;
+
+        for file_path in &plan.files {
+            self.cancel.check()?;
+
+            let size = std::fs::metadata(file_path).map(|m| m.len()).unwrap_or(0);
+            if file_path.starts_with(&plan.staging_dir) {
+                self.advance(&mut progress, file_path, size);
+                continue;
+            }
+
+            progress.current_path = Some(file_path.to_string_lossy().to_string());
+            self.report(progress.clone());
+
+            let relative_path = tracked_relative_path(&plan.tracked_paths, file_path);
+            let target = target_root.join(&relative_path);
+
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|source| SyncError::StagingDir { path: parent.to_path_buf(), source })?;
+            }
+
+            if let Err(source) = std::fs::copy(file_path, &target) {
+                let err = SyncError::Copy { from: file_path.clone(), to: target, source };
+                crate::logger::log_error(&err.to_string());
+                return Err(err);
+            }
+
+            // fs::copy keeps permissions, so read-only sources (like SSH keys) would
+            // otherwise block removing this tree when it gets replaced next sync
+            if let Ok(metadata) = std::fs::metadata(&target) {
+                let mut permissions = metadata.permissions();
+                if permissions.readonly() {
+                    #[allow(clippy::permissions_set_readonly_false)]
+                    permissions.set_readonly(false);
+                    let _ = std::fs::set_permissions(&target, permissions);
+                }
+            }
+
+            manifest.add_file(&relative_path, file_path, &target)
+                .map_err(SyncError::Manifest)?;
+
+            crate::logger::log_info(&format!("Copied {} to staging", file_path.display()));
+            staged += 1;
+            self.advance(&mut progress, file_path, size);
+        }
+
+        Ok((staged, manifest))
+    }
+
+    /// Rebuilds `staging/backup.zip` from `staging/tracked` unless the existing
+    /// ZIP was built from files with the same contents. Keeping the same ZIP
+    /// keeps its encrypted copy, so an interrupted upload can resume.
+    fn build_zip(&self, manifest: &Manifest) -> Result<PathBuf, SyncError> {
+        let staging_dir = &self.plan.staging_dir;
+        crate::logger::log_info("Creating ZIP file of staging directory...");
+        let zip_path = staging_dir.join("backup.zip");
+
+        let digest = manifest.content_digest();
+        let digest_key = crate::config::destination_key(ZIP_CONTENT_DIGEST_KEY, &self.plan.destination);
+        let built_from = crate::file_tracker::get_metadata(&digest_key).unwrap_or(None);
+        if zip_path.exists() && built_from.as_deref() == Some(digest.as_str()) {
+            crate::logger::log_info("Staged files unchanged, using existing ZIP");
+            return Ok(zip_path);
+        }
+
+        crate::logger::log_info("Staging directory has changed, creating new ZIP...");
+
+        // Build next to the real ZIP and rename into place, so an interrupted
+        // build never replaces the last good one
+        let partial_path = staging_dir.join("backup.zip.partial");
+        if let Err(e) = self.write_zip(&partial_path, manifest) {
+            let _ = std::fs::remove_file(&partial_path);
+            return Err(e);
+        }
+        std::fs::rename(&partial_path, &zip_path)
+            .map_err(|e| SyncError::Zip(anyhow::anyhow!("Failed to move ZIP into place: {}", e)))?;
+        if let Err(e) = crate::file_tracker::set_metadata(&digest_key, &digest) {
+            crate::logger::log_warn(&format!("Failed to record the ZIP's content digest: {}", e));
+        }
+
+        crate::logger::log_info(&format!("ZIP file created: {:?}", zip_path));
+        Ok(zip_path)
+    }
+
+    fn write_zip(&self, zip_path: &Path, manifest: &Manifest) -> Result<(), SyncError> {
+        let file = std::fs::File::create(zip_path)
+            .map_err(|e| SyncError::Zip(anyhow::anyhow!("Failed to create ZIP file: {}", e)))?;
+        let mut zip = zip::ZipWriter::new(file);
+
+        // Add files from the "tracked" subdirectory, but without the "tracked" folder wrapper
+        let tracked_dir = self.plan.staging_dir.join(TRACKED_DIR);
+        let (files_total, bytes_total) = crate::version_manager::directory_totals(&tracked_dir)
+            .unwrap_or_default();
+        let mut progress = SyncProgress::files(SyncPhase::Zipping, files_total, bytes_total);
+        self.report(progress.clone());
+        if tracked_dir.exists() {
+            crate::version_manager::add_directory_to_zip(
+                &mut zip,
+                &tracked_dir,
+                &tracked_dir,
+                "",
+                &mut |path, size| self.advance_cancellable(&mut progress, path, size),
+            )
+            .map_err(|e| if self.cancel.is_cancelled() { SyncError::Cancelled } else { SyncError::Zip(e) })?;
+        }
+
+        manifest.write_to_zip(&mut zip)
+            .map_err(SyncError::Zip)?;
+
+        zip.finish()
+            .map_err(|e| SyncError::Zip(anyhow::anyhow!("Failed to finalize ZIP: {}", e)))?;
+        Ok(())
+    }
+
+    async fn upload(&self, storage: &mut dyn StorageBackend, zip_path: &Path) -> Result<String, SyncError> {
+        storage.ensure_container()
+            .await
+            .map_err(SyncError::Storage)?;
+
+        // The change check runs on the plain ZIP; encrypting it again yields
+        // different bytes every time, so the encrypted copy is only remade
+        // when the ZIP or the key changed
+        let upload_path = match &self.plan.encryption_key {
+            Some(key) => {
+                let encrypted = crate::crypto::encrypted_path(zip_path);
+                let source_key = crate::config::destination_key(ENCRYPTED_ZIP_SOURCE_KEY, &self.plan.destination);
+                encrypt_for_upload(zip_path, &encrypted, key, &source_key)
+                    .map_err(SyncError::Encryption)?;
+                encrypted
+            }
+            None => zip_path.to_path_buf(),
+        };
+
+        crate::logger::log_info(&format!("Uploading ZIP file to {}...", storage.describe()));
+
+        let Some(file_name) = self.upload_name(zip_path) else {
+            return Err(SyncError::Upload(anyhow::anyhow!("Invalid file name")));
+        };
+        let hooks = self.upload_hooks(&SyncProgress::phase(SyncPhase::Uploading));
+        match storage.put(&upload_path, &file_name, hooks).await {
+            Ok(object) => {
+                if let Err(e) = crate::file_tracker::mark_file_synced(zip_path) {
+                    crate::logger::log_error(&format!("Failed to mark ZIP as synced: {}", e));
+                }
+                let etag_key = crate::config::destination_key(UPLOADED_ZIP_ETAG_KEY, &self.plan.destination);
+                let recorded = match &object.etag {
+                    Some(etag) => crate::file_tracker::set_metadata(&etag_key, etag),
+                    None => crate::file_tracker::remove_metadata(&etag_key),
+                };
+                if let Err(e) = recorded {
+                    crate::logger::log_error(&format!("Failed to record the uploaded ZIP's ETag: {}", e));
+                }
+                crate::logger::log_info("ZIP file uploaded successfully!");
+                Ok(object.id)
+            }
+            Err(_) if self.cancel.is_cancelled() => {
+                crate::logger::log_warn("Upload cancelled");
+                Err(SyncError::Cancelled)
+            }
+            Err(e) => {
+                crate::logger::log_error(&format!("Failed to upload ZIP: {}", e));
+                Err(SyncError::Upload(e))
+            }
+        }
+    }
+
+    /// Hooks for an upload that report its byte counts on top of `base` and
+    /// stop it when the sync is cancelled.
+    fn upload_hooks(&self, base: &SyncProgress) -> crate::storage::TransferHooks {
+        let on_progress = self.on_progress.clone().map(|callback| {
+            let base = SyncProgress {
+                destination: Some(self.plan.destination.clone()),
+                ..base.clone()
+            };
+            let upload_callback: crate::storage::TransferProgressCallback = Arc::new(move |sent, total| {
+                callback(&SyncProgress {
+                    upload_bytes_sent: sent,
+                    upload_bytes_total: total,
+                    ..base.clone()
+                });
+            });
+            upload_callback
+        });
+
+        crate::storage::TransferHooks {
+            on_progress,
+            cancel: Some(self.cancel.clone()),
+        }
+    }
+
+    /// Counts one processed file towards `progress` and reports it.
+    fn advance(&self, progress: &mut SyncProgress, path: &Path, size: u64) {
+        progress.files_done += 1;
+        progress.bytes_done += size;
+        progress.current_path = Some(path.to_string_lossy().to_string());
+        self.report(progress.clone());
+    }
+
+    /// Like `advance`, but fails once the sync has been cancelled so archive
+    /// writers stop between entries.
+    fn advance_cancellable(&self, progress: &mut SyncProgress, path: &Path, size: u64) -> anyhow::Result<()> {
+        self.advance(progress, path, size);
+        if self.cancel.is_cancelled() {
+            anyhow::bail!("Sync was cancelled");
+        }
+        Ok(())
+    }
+
+    fn report(&self, mut progress: SyncProgress) {
+        if let Some(callback) = &self.on_progress {
+            progress.destination = Some(self.plan.destination.clone());
+            callback(&progress);
+        }
+    }
+}
+
+/// Maps a tracked source file to its location relative to `staging/tracked`.
+///
+/// Single tracked files land directly in `tracked/`, files inside a tracked
+/// directory keep their structure under `tracked/<dir name>/`.
+pub fn tracked_relative_path(tracked_paths: &[PathBuf], file_path: &Path) -> PathBuf {
+    let file_name = PathBuf::from(file_path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown"));
+
+    let base = match tracked_paths.iter().find(|tracked| file_path.starts_with(tracked)) {
+        Some(base) => base,
+        None => return file_name,
+    };
+
+    match file_path.strip_prefix(base) {
+        // Base is the file itself (single file tracked)
+        Ok(rel) if rel.as_os_str().is_empty() => file_name,
+        // Base is a directory, include its name: base_name/relative_path
+        Ok(rel) => match base.file_name() {
+            Some(base_name) => Path::new(base_name).join(rel),
+            None => rel.to_path_buf(),
+        },
+        Err(_) => file_name,
+    }
+}
+
+/// Notes the plaintext copies on a destination, its rolling ZIP and mirrored
+/// files, so the first sync after encryption is turned on deletes them.
+/// Must run before `forget_uploads`, which drops the mirrored files' records.
+pub fn remember_plaintext_uploads(destination: &str) -> anyhow::Result<()> {
+    let key = crate::config::destination_key(PLAINTEXT_LEFTOVERS_KEY, destination);
+    let mut paths: Vec<String> = match crate::file_tracker::get_metadata(&key)? {
+        Some(value) => serde_json::from_str(&value)?,
+        None => Vec::new(),
+    };
+    // The rolling ZIP's name without encryption, see `SyncEngine::upload_name`
+    paths.push("backup.zip".to_string());
+    paths.extend(crate::file_tracker::get_mirror_files(destination)?
+        .into_iter()
+        .map(|mirror_file| mirror_file.remote_path.replace('\\', "/")));
+    paths.sort();
+    paths.dedup();
+    crate::file_tracker::set_metadata(&key, &serde_json::to_string(&paths)?)
+}
+
+/// Forgets what has been uploaded to a destination: the sync state of its
+/// files and rolling ZIP, the ZIP's ETag, its mirrored files and the last version
+/// uploaded. Used when its storage or the encryption key changes, so the next
+/// sync uploads everything.
+pub fn forget_uploads(destination: &str) -> anyhow::Result<()> {
+    for tracked_path in crate::file_tracker::get_tracked_paths_for(destination)? {
+        crate::file_tracker::remove_file_metadata_under(Path::new(&tracked_path))?;
+    }
+    crate::file_tracker::remove_file_metadata_under(&crate::config::get_destination_staging_dir(destination)?)?;
+    crate::file_tracker::remove_mirror_files(destination)?;
+    crate::file_tracker::remove_metadata(&crate::config::destination_key(LAST_VERSION_DIGEST_KEY, destination))?;
+    crate::file_tracker::remove_metadata(&crate::config::destination_key(UPLOADED_ZIP_ETAG_KEY, destination))
+}
+
+/// Encrypts `source` into `encrypted` unless `encrypted` already holds the
+/// same content under the same key, as recorded in app_metadata under
+/// `source_key`. Encrypting again picks a fresh nonce and so different bytes,
+/// which would keep an interrupted upload from resuming.
+fn encrypt_for_upload(source: &Path, encrypted: &Path, key: &EncryptionKey, source_key: &str) -> anyhow::Result<()> {
+    let fingerprint = format!("{}:{}", crate::file_tracker::calculate_file_hash(source)?, key.fingerprint());
+    let encrypted_from = crate::file_tracker::get_metadata(source_key).unwrap_or(None);
+    if encrypted.exists() && encrypted_from.as_deref() == Some(fingerprint.as_str()) {
+        crate::logger::log_info(&format!("Using existing encrypted copy of {}", source.display()));
+        return Ok(());
+    }
+
+    crate::crypto::encrypt_file(source, encrypted, key)?;
+    if let Err(e) = crate::file_tracker::set_metadata(source_key, &fingerprint) {
+        crate::logger::log_warn(&format!("Failed to record what {} was encrypted from: {}", encrypted.display(), e));
+    }
+    crate::logger::log_info(&format!("Encrypted {} for upload", source.display()));
+    Ok(())
+}
+
+/// Swaps the freshly staged copy in for `staging/tracked`.
+fn replace_tracked_dir(staging_dir: &Path) -> Result<(), SyncError> {
+    let tracked_dir = staging_dir.join(TRACKED_DIR);
+    let partial_dir = staging_dir.join(PARTIAL_DIR);
+    let previous_dir = staging_dir.join(PREVIOUS_DIR);
+
+    remove_staging_tree(&previous_dir)?;
+    if tracked_dir.exists() {
+        std::fs::rename(&tracked_dir, &previous_dir)
+            .map_err(|source| SyncError::StagingDir { path: tracked_dir.clone(), source })?;
+    }
+    std::fs::rename(&partial_dir, &tracked_dir)
+        .map_err(|source| SyncError::StagingDir { path: tracked_dir.clone(), source })?;
+    remove_staging_tree(&previous_dir)
+}
+
+fn remove_staging_tree(path: &Path) -> Result<(), SyncError> {
+    match std::fs::remove_dir_all(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(source) => Err(SyncError::StagingDir { path: path.to_path_buf(), source }),
+    }
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
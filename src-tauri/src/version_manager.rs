@@ -2,31 +2,79 @@ use std::path::{Path, PathBuf};
 use std::fs;
 use zip::write::{FileOptions, ZipWriter};
 use zip::CompressionMethod;
-use chrono::Local;
+use std::collections::HashSet;
+use chrono::{Local, NaiveDateTime};
 use anyhow::{Result, Context};
+use crate::config::RetentionPolicy;
+use crate::crypto::EncryptionKey;
+use crate::manifest::Manifest;
 
-pub fn create_archive(source_dir: &Path, archives_dir: &Path) -> Result<PathBuf> {
-    // Create timestamp
-    let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
-    let archive_name = format!("sync-{}.zip", timestamp);
+// Archives are named `sync-<local time>.zip`; only files matching this are ever pruned
+const ARCHIVE_NAME_FORMAT: &str = "sync-%Y-%m-%d_%H-%M-%S";
+
+/// Called with the source path and size of each file as it is written into an
+/// archive. Returning an error aborts the archive.
+pub type EntryCallback<'a> = &'a mut dyn FnMut(&Path, u64) -> Result<()>;
+
+/// Archives `source_dir` as `sync-<timestamp>.zip`, its files placed under
+/// `prefix` and `manifest` written at the archive root. The manifest's entry
+/// paths must match. With a key, only the encrypted `sync-<timestamp>.zip.enc`
+/// is kept.
+pub fn create_archive(
+    source_dir: &Path,
+    prefix: &str,
+    archives_dir: &Path,
+    manifest: &Manifest,
+    retention: &RetentionPolicy,
+    key: Option<&EncryptionKey>,
+    on_entry: EntryCallback,
+) -> Result<PathBuf> {
+    let archive_name = format!("{}.zip", Local::now().format(ARCHIVE_NAME_FORMAT));
     let archive_path = archives_dir.join(&archive_name);
     
+    // Write under a temporary name so an aborted archive never looks like a real one
+    let partial_path = archives_dir.join(format!("{}.partial", archive_name));
+    if let Err(e) = write_archive(source_dir, prefix, &partial_path, manifest, on_entry) {
+        let _ = fs::remove_file(&partial_path);
+        return Err(e);
+    }
+    let archive_path = match key {
+        Some(key) => {
+            let encrypted_path = crate::crypto::encrypted_path(&archive_path);
+            let result = crate::crypto::encrypt_file(&partial_path, &encrypted_path, key);
+            let _ = fs::remove_file(&partial_path);
+            result.context("Failed to encrypt archive")?;
+            encrypted_path
+        }
+        None => {
+            fs::rename(&partial_path, &archive_path)
+                .context("Failed to move archive into place")?;
+            archive_path
+        }
+    };
+    
+    cleanup_old_archives(archives_dir, retention)?;
+    
+    Ok(archive_path)
+}
+
+fn write_archive(source_dir: &Path, prefix: &str, archive_path: &Path, manifest: &Manifest, on_entry: EntryCallback) -> Result<()> {
     // Create zip file
-    let file = fs::File::create(&archive_path)
+    let file = fs::File::create(archive_path)
         .context("Failed to create archive file")?;
     let mut zip = ZipWriter::new(file);
     
     // Add files to zip
-    add_directory_to_zip(&mut zip, source_dir, source_dir, "")
-        .context("Failed to add files to archive")?;
+    if source_dir.exists() {
+        add_directory_to_zip(&mut zip, source_dir, source_dir, prefix, on_entry)
+            .context("Failed to add files to archive")?;
+    }
+    manifest.write_to_zip(&mut zip)?;
     
     zip.finish()
         .context("Failed to finalize archive")?;
     
-    // Clean up old archives (keep only last 4)
-    cleanup_old_archives(archives_dir)?;
-    
-    Ok(archive_path)
+    Ok(())
 }
 
 pub fn add_directory_to_zip(
@@ -34,6 +82,7 @@ pub fn add_directory_to_zip(
     base_path: &Path,
     current_path: &Path,
     zip_path: &str,
+    on_entry: EntryCallback,
 ) -> Result<()> {
     let entries = fs::read_dir(current_path)
         .context("Failed to read directory")?;
@@ -61,44 +110,158 @@ pub fn add_directory_to_zip(
             
             std::io::copy(&mut file, zip)
                 .context("Failed to write file to archive")?;
+            on_entry(&path, metadata.len())?;
         } else if metadata.is_dir() {
             zip.add_directory(&zip_entry_path, FileOptions::default())?;
-            add_directory_to_zip(zip, base_path, &path, &zip_entry_path)?;
+            // Entry paths are relative to base_path, so the prefix stays the same
+            add_directory_to_zip(zip, base_path, &path, zip_path, &mut *on_entry)?;
         }
     }
     
     Ok(())
 }
 
-fn cleanup_old_archives(archives_dir: &Path) -> Result<()> {
-    let mut archives: Vec<_> = fs::read_dir(archives_dir)
+/// Counts the files below `dir` and their total size, used to size progress
+/// reporting before archiving.
+pub fn directory_totals(dir: &Path) -> Result<(usize, u64)> {
+    let mut files = 0;
+    let mut bytes = 0;
+
+    if !dir.exists() {
+        return Ok((files, bytes));
+    }
+
+    for entry in fs::read_dir(dir).context("Failed to read directory")? {
+        let entry = entry.context("Failed to read directory entry")?;
+        let metadata = entry.metadata()
+            .context("Failed to get entry metadata")?;
+
+        if metadata.is_file() {
+            files += 1;
+            bytes += metadata.len();
+        } else if metadata.is_dir() {
+            let (sub_files, sub_bytes) = directory_totals(&entry.path())?;
+            files += sub_files;
+            bytes += sub_bytes;
+        }
+    }
+
+    Ok((files, bytes))
+}
+
+/// An archive considered for pruning, local or remote.
+#[derive(Debug, Clone)]
+pub struct ArchiveCandidate {
+    pub name: String,
+    pub created: NaiveDateTime,
+    pub size: u64,
+}
+
+impl ArchiveCandidate {
+    /// Parses one of our `sync-<timestamp>.zip` or `sync-<timestamp>.zip.enc`
+    /// names; anything else is not ours to prune and yields `None`.
+    pub fn from_name(name: &str, size: u64) -> Option<Self> {
+        let name_without_enc = name.strip_suffix(crate::crypto::ENCRYPTED_SUFFIX).unwrap_or(name);
+        let stem = name_without_enc.strip_suffix(".zip")?;
+        let created = NaiveDateTime::parse_from_str(stem, ARCHIVE_NAME_FORMAT).ok()?;
+        Some(Self { name: name.to_string(), created, size })
+    }
+}
+
+/// An archive the retention policy dropped, and why.
+#[derive(Debug, Clone)]
+pub struct PruneDecision {
+    pub name: String,
+    pub reason: String,
+}
+
+/// Applies `policy` to `candidates` and returns the ones to remove. Shared by
+/// local and remote pruning so both sides keep the same points in time.
+pub fn select_for_pruning(
+    candidates: &[ArchiveCandidate],
+    policy: &RetentionPolicy,
+    now: NaiveDateTime,
+) -> Vec<PruneDecision> {
+    let mut sorted: Vec<&ArchiveCandidate> = candidates.iter().collect();
+    sorted.sort_by_key(|archive| std::cmp::Reverse(archive.created));
+
+    let rules: [(Option<u32>, &str); 4] = [
+        (policy.keep_hourly, "%Y-%m-%d %H"),
+        (policy.keep_daily, "%Y-%m-%d"),
+        (policy.keep_weekly, "%G-W%V"),
+        (policy.keep_monthly, "%Y-%m"),
+    ];
+    let has_keep_rules = policy.keep_last.is_some() || rules.iter().any(|(count, _)| count.is_some());
+
+    let mut kept = vec![!has_keep_rules; sorted.len()];
+    let keep_last = policy.keep_last.unwrap_or(0) as usize;
+    for flag in kept.iter_mut().take(keep_last) {
+        *flag = true;
+    }
+    for (count, bucket_format) in rules {
+        let Some(count) = count else { continue };
+        let mut buckets = HashSet::new();
+        // Newest first, so each bucket is represented by its newest archive
+        for (index, archive) in sorted.iter().enumerate() {
+            if buckets.len() >= count as usize {
+                break;
+            }
+            if buckets.insert(archive.created.format(bucket_format).to_string()) {
+                kept[index] = true;
+            }
+        }
+    }
+
+    let mut pruned = Vec::new();
+    let mut total_size = 0u64;
+    for (index, archive) in sorted.iter().enumerate() {
+        // Never leave nothing behind
+        if index == 0 {
+            total_size += archive.size;
+            continue;
+        }
+
+        let reason = if !kept[index] {
+            Some("not selected by any keep rule".to_string())
+        } else if let Some(days) = policy.max_age_days.filter(|days| (now - archive.created).num_days() >= *days as i64) {
+            Some(format!("older than {} day(s)", days))
+        } else if let Some(max_mb) = policy.max_total_size_mb.filter(|max_mb| total_size + archive.size > max_mb * 1024 * 1024) {
+            Some(format!("total size limit of {} MB reached", max_mb))
+        } else {
+            total_size += archive.size;
+            None
+        };
+
+        if let Some(reason) = reason {
+            pruned.push(PruneDecision { name: archive.name.clone(), reason });
+        }
+    }
+
+    pruned
+}
+
+/// Removes local archives the retention policy no longer keeps and returns
+/// their paths. Files not named like our archives are left alone.
+fn cleanup_old_archives(archives_dir: &Path, policy: &RetentionPolicy) -> Result<Vec<PathBuf>> {
+    let candidates: Vec<ArchiveCandidate> = fs::read_dir(archives_dir)
         .context("Failed to read archives directory")?
+        .filter_map(|entry| entry.ok())
         .filter_map(|entry| {
-            entry.ok().and_then(|e| {
-                let path = e.path();
-                if path.extension() == Some(std::ffi::OsStr::new("zip")) {
-                    e.metadata().ok().and_then(|m| {
-                        m.modified().ok().map(|modified| (path, modified))
-                    })
-                } else {
-                    None
-                }
-            })
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            ArchiveCandidate::from_name(&entry.file_name().to_string_lossy(), metadata.len())
         })
         .collect();
-    
-    // Sort by modification time (newest first)
-    archives.sort_by(|a, b| b.1.cmp(&a.1));
-    
-    // Keep only last 4, delete the rest
-    if archives.len() > 4 {
-        for (path, _) in archives.iter().skip(4) {
-            fs::remove_file(path)
-                .context("Failed to remove old archive")?;
-        }
+
+    let mut removed = Vec::new();
+    for decision in select_for_pruning(&candidates, policy, Local::now().naive_local()) {
+        let path = archives_dir.join(&decision.name);
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove old archive {}", decision.name))?;
+        crate::logger::log_info(&format!("Pruned archive {} ({})", decision.name, decision.reason));
+        removed.push(path);
     }
     
-    Ok(())
+    Ok(removed)
 }
 
 #[allow(dead_code)]
@@ -120,3 +283,92 @@ pub fn get_archive_count(archives_dir: &Path) -> Result<usize> {
     
     Ok(count)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn at(timestamp: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn archives(timestamps: &[&str]) -> Vec<ArchiveCandidate> {
+        timestamps.iter()
+            .map(|timestamp| {
+                let name = format!("{}.zip", at(timestamp).format(ARCHIVE_NAME_FORMAT));
+                ArchiveCandidate::from_name(&name, MB).unwrap()
+            })
+            .collect()
+    }
+
+    fn no_rules() -> RetentionPolicy {
+        RetentionPolicy { keep_last: None, ..RetentionPolicy::default() }
+    }
+
+    fn pruned(candidates: &[ArchiveCandidate], policy: &RetentionPolicy, now: &str) -> Vec<String> {
+        let mut names: Vec<String> = select_for_pruning(candidates, policy, at(now))
+            .into_iter()
+            .map(|decision| decision.name)
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn names(timestamps: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = archives(timestamps).into_iter().map(|archive| archive.name).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn parses_only_our_archive_names() {
+        let archive = ArchiveCandidate::from_name("sync-2024-03-01_10-30-00.zip.enc", 5).unwrap();
+        assert_eq!(archive.created, at("2024-03-01 10:30"));
+        assert!(ArchiveCandidate::from_name("sync-2024-03-01_10-30-00.zip", 5).is_some());
+        assert!(ArchiveCandidate::from_name("sync-2024-03-01_10-30-00.zip.partial", 5).is_none());
+        assert!(ArchiveCandidate::from_name("holiday-photos.zip", 5).is_none());
+    }
+
+    #[test]
+    fn keeps_the_most_recent() {
+        let candidates = archives(&["2024-03-01 10:00", "2024-03-01 11:00", "2024-03-01 12:00", "2024-03-01 13:00"]);
+        let policy = RetentionPolicy { keep_last: Some(2), ..no_rules() };
+        assert_eq!(pruned(&candidates, &policy, "2024-03-01 14:00"), names(&["2024-03-01 10:00", "2024-03-01 11:00"]));
+    }
+
+    #[test]
+    fn keeps_the_newest_archive_of_each_day() {
+        let candidates = archives(&["2024-03-01 10:00", "2024-03-01 12:00", "2024-03-02 09:00", "2024-03-02 18:00", "2024-03-03 08:00"]);
+        let policy = RetentionPolicy { keep_daily: Some(2), ..no_rules() };
+        assert_eq!(
+            pruned(&candidates, &policy, "2024-03-03 09:00"),
+            names(&["2024-03-01 10:00", "2024-03-01 12:00", "2024-03-02 09:00"])
+        );
+    }
+
+    #[test]
+    fn keeps_everything_without_keep_rules() {
+        let candidates = archives(&["2024-03-01 10:00", "2024-03-02 10:00", "2024-03-03 10:00"]);
+        assert!(pruned(&candidates, &no_rules(), "2024-03-04 10:00").is_empty());
+    }
+
+    #[test]
+    fn age_and_size_limits_override_keep_rules_but_spare_the_newest() {
+        let candidates = archives(&["2024-01-01 10:00", "2024-02-20 10:00", "2024-02-25 10:00", "2024-03-01 10:00"]);
+
+        let by_age = RetentionPolicy { keep_last: Some(10), max_age_days: Some(30), ..no_rules() };
+        assert_eq!(pruned(&candidates, &by_age, "2024-03-02 10:00"), names(&["2024-01-01 10:00"]));
+        assert_eq!(
+            pruned(&candidates, &by_age, "2025-01-01 10:00"),
+            names(&["2024-01-01 10:00", "2024-02-20 10:00", "2024-02-25 10:00"])
+        );
+
+        let by_size = RetentionPolicy { keep_last: Some(10), max_total_size_mb: Some(2), ..no_rules() };
+        assert_eq!(
+            pruned(&candidates, &by_size, "2024-03-02 10:00"),
+            names(&["2024-01-01 10:00", "2024-02-20 10:00"])
+        );
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashSet;
+use std::path::Path;
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use zeroize::Zeroizing;
+use crate::config::WebDavSettings;
+use crate::storage::{StorageBackend, StoredObject, TransferHooks};
+
+// Properties PROPFIND asks for; everything `StoredObject` needs
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:">
+  <d:prop>
+    <d:resourcetype/>
+    <d:getcontentlength/>
+    <d:getlastmodified/>
+    <d:getetag/>
+  </d:prop>
+</d:propfind>"#;
+
+/// Backups in a folder on a WebDAV server such as Nextcloud or ownCloud,
+/// signed in to with an app password. Files are uploaded under a `.partial`
+/// name and moved into place, so a reader never sees half a backup.
+pub struct WebDavStorage {
+    client: Client,
+    /// URL of the backup folder, ending in `/`
+    base: url::Url,
+    username: String,
+    password: Zeroizing<String>,
+    /// Directories known to exist, so uploads don't recreate their parents
+    known_dirs: HashSet<String>,
+}
+
+impl WebDavStorage {
+    /// Backend for a destination, with the app password read from the
+    /// credential store.
+    pub fn for_destination(destination: &str, settings: &WebDavSettings) -> Result<Self> {
+        let secret_key = crate::config::destination_key(crate::credentials::STORAGE_SECRET, destination);
+        let password = crate::credentials::get(&secret_key)?
+            .with_context(|| format!("No WebDAV app password saved for destination '{}'", destination))?;
+
+        let mut base = url::Url::parse(&settings.url)
+            .context("The WebDAV URL is not valid")?;
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+
+        Ok(Self {
+            client: Client::new(),
+            base,
+            username: settings.username.clone(),
+            password: Zeroizing::new(password),
+            known_dirs: HashSet::new(),
+        })
+    }
+
+    /// URL of a backend path; directories get a trailing `/`, as servers
+    /// redirect or reject collection requests without one.
+    fn url(&self, path: &str, is_dir: bool) -> Result<url::Url> {
+        let mut url = self.base.clone();
+        {
+            let mut segments = url.path_segments_mut()
+                .map_err(|_| anyhow::anyhow!("The WebDAV URL cannot have paths below it"))?;
+            segments.pop_if_empty();
+            segments.extend(path.split('/').filter(|name| !name.is_empty()));
+            if is_dir {
+                segments.push("");
+            }
+        }
+        Ok(url)
+    }
+
+    fn request(&self, method: Method, url: url::Url) -> RequestBuilder {
+        self.client.request(method, url)
+            .basic_auth(&self.username, Some(self.password.as_str()))
+    }
+
+    /// Creates a directory, treating one that already exists as created.
+    async fn make_collection(&self, dir: &str) -> Result<()> {
+        let url = self.url(dir, true)?;
+        let response = crate::storage::send_with_retry("create folder", || {
+            Ok(self.request(dav_method(b"MKCOL"), url.clone()))
+        }).await?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            // MKCOL on an existing collection
+            StatusCode::METHOD_NOT_ALLOWED => Ok(()),
+            StatusCode::CONFLICT => anyhow::bail!("The folder above {} does not exist on the server", url),
+            _ => Err(error_from(response, "create folder").await),
+        }
+    }
+
+    /// Creates `dir` and the directories above it, below the backup folder.
+    async fn create_dirs(&mut self, dir: &str) -> Result<()> {
+        let mut current = String::new();
+        for name in dir.split('/').filter(|name| !name.is_empty()) {
+            current = crate::storage::join(&current, name);
+            if self.known_dirs.contains(&current) {
+                continue;
+            }
+            self.make_collection(&current).await?;
+            self.known_dirs.insert(current.clone());
+        }
+        Ok(())
+    }
+
+    /// Moves `from` over `to`. The server swaps it in as one operation, which
+    /// is what makes uploads atomic.
+    async fn move_file(&self, from: &str, to: &str) -> Result<()> {
+        let from_url = self.url(from, false)?;
+        let to_url = self.url(to, false)?;
+        let response = crate::storage::send_with_retry("move file", || {
+            Ok(self.request(dav_method(b"MOVE"), from_url.clone())
+                .header("Destination", to_url.as_str())
+                .header("Overwrite", "T"))
+        }).await?;
+        if !response.status().is_success() {
+            return Err(error_from(response, &format!("move {} to {}", from, to)).await);
+        }
+        Ok(())
+    }
+
+    /// The multistatus body of a PROPFIND, or `None` if `path` doesn't exist.
+    async fn propfind(&self, path: &str, is_dir: bool, depth: &str) -> Result<Option<String>> {
+        let url = self.url(path, is_dir)?;
+        let response = crate::storage::send_with_retry("list folder", || {
+            Ok(self.request(dav_method(b"PROPFIND"), url.clone())
+                .header("Depth", depth)
+                .header("Content-Type", "application/xml; charset=utf-8")
+                .body(PROPFIND_BODY))
+        }).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if response.status() != StatusCode::MULTI_STATUS {
+            return Err(error_from(response, "list folder").await);
+        }
+        response.text()
+            .await
+            .map(Some)
+            .context("Failed to read PROPFIND response")
+    }
+
+    /// The objects in a multistatus body as `(href path, object)` pairs, with
+    /// each `StoredObject::path` set to its name below `dir`.
+    fn parse_multistatus(&self, xml: &str, dir: &str) -> Result<Vec<(String, StoredObject)>> {
+        let document = roxmltree::Document::parse(xml)
+            .context("Unexpected XML in PROPFIND response")?;
+
+        let mut objects = Vec::new();
+        for response in document.descendants().filter(|node| node.tag_name().name() == "response") {
+            let Some(href) = child_text(response, "href") else {
+                continue;
+            };
+            let Ok(url) = self.base.join(href) else {
+                continue;
+            };
+            let href_path = urlencoding::decode(url.path())
+                .map(|path| path.trim_end_matches('/').to_string())
+                .unwrap_or_default();
+            let name = href_path.rsplit('/').next().unwrap_or_default().to_string();
+            let path = crate::storage::join(dir, &name);
+
+            let is_dir = prop(response, "resourcetype")
+                .is_some_and(|node| node.children().any(|child| child.tag_name().name() == "collection"));
+            let object = StoredObject {
+                id: path.clone(),
+                path,
+                name,
+                is_dir,
+                size: prop(response, "getcontentlength")
+                    .and_then(|node| node.text())
+                    .and_then(|length| length.trim().parse().ok()),
+                modified_time: prop(response, "getlastmodified")
+                    .and_then(|node| node.text())
+                    .and_then(|time| DateTime::parse_from_rfc2822(time.trim()).ok())
+                    .map(|time| time.with_timezone(&Utc).to_rfc3339()),
+                etag: prop(response, "getetag")
+                    .and_then(|node| node.text())
+                    .map(|etag| etag.trim().trim_start_matches("W/").trim_matches('"').to_string()),
+            };
+            objects.push((href_path, object));
+        }
+        Ok(objects)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for WebDavStorage {
+    fn describe(&self) -> String {
+        format!("WebDAV folder {}", self.base)
+    }
+
+    /// Creates the backup folder, but not the folders above it: a missing
+    /// parent usually means a mistyped URL rather than a folder to create.
+    async fn ensure_container(&mut self) -> Result<()> {
+        if self.known_dirs.contains("") {
+            return Ok(());
+        }
+        self.make_collection("").await?;
+        self.known_dirs.insert(String::new());
+        Ok(())
+    }
+
+    async fn put(&mut self, local: &Path, path: &str, hooks: TransferHooks) -> Result<StoredObject> {
+        let (dir, name) = crate::storage::split(path);
+        self.create_dirs(dir).await?;
+
+        let partial = crate::storage::join(dir, &format!("{}.partial", name));
+        let partial_url = self.url(&partial, false)?;
+        let response = crate::storage::send_with_retry("upload file", || {
+            check_cancelled(&hooks)?;
+            let (body, total) = crate::storage::file_body(local, hooks.clone())?;
+            Ok(self.request(Method::PUT, partial_url.clone())
+                .header("Content-Length", total)
+                .body(body))
+        }).await;
+        let finished = match response {
+            Ok(response) if response.status().is_success() => self.move_file(&partial, path).await,
+            Ok(response) => Err(error_from(response, "upload file").await),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = finished {
+            let _ = self.delete(&partial).await;
+            return Err(e);
+        }
+
+        // The server's ETag for the file, which the PUT response may not carry
+        // and the move may change
+        self.stat(path)
+            .await?
+            .with_context(|| format!("{} is missing from {} after upload", path, self.describe()))
+    }
+
+    async fn get(&mut self, path: &str, local: &Path, hooks: TransferHooks) -> Result<u64> {
+        let url = self.url(path, false)?;
+        crate::storage::download_with_retry(
+            path,
+            &self.describe(),
+            local,
+            &hooks,
+            || Ok(self.request(Method::GET, url.clone())),
+            |response| error_from(response, "download file"),
+        ).await
+    }
+
+    async fn list(&mut self, dir: &str) -> Result<Vec<StoredObject>> {
+        let Some(xml) = self.propfind(dir, true, "1").await? else {
+            return Ok(Vec::new());
+        };
+        // The folder itself comes back as one of the responses
+        let dir_path = urlencoding::decode(self.url(dir, true)?.path())
+            .map(|path| path.trim_end_matches('/').to_string())
+            .unwrap_or_default();
+
+        let mut objects: Vec<StoredObject> = self.parse_multistatus(&xml, dir)?
+            .into_iter()
+            .filter(|(href_path, _)| *href_path != dir_path)
+            .map(|(_, object)| object)
+            // Uploads still in flight, or left behind by a crash
+            .filter(|object| !object.name.is_empty() && !object.name.ends_with(".partial"))
+            .collect();
+        objects.sort_by(|a, b| b.modified_time.cmp(&a.modified_time));
+        Ok(objects)
+    }
+
+    async fn stat(&mut self, path: &str) -> Result<Option<StoredObject>> {
+        let Some(xml) = self.propfind(path, false, "0").await? else {
+            return Ok(None);
+        };
+        let (dir, _) = crate::storage::split(path);
+        Ok(self.parse_multistatus(&xml, dir)?
+            .into_iter()
+            .next()
+            .map(|(_, object)| object))
+    }
+
+    async fn delete(&mut self, path: &str) -> Result<()> {
+        let url = self.url(path, false)?;
+        let response = crate::storage::send_with_retry("delete file", || {
+            Ok(self.request(Method::DELETE, url.clone()))
+        }).await?;
+        if response.status().is_success() || response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        Err(error_from(response, "delete file").await)
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let (dir, _) = crate::storage::split(to);
+        self.create_dirs(dir).await?;
+        self.move_file(from, to).await
+    }
+}
+
+/// A WebDAV method reqwest has no constant for.
+fn dav_method(name: &'static [u8]) -> Method {
+    Method::from_bytes(name).expect("WebDAV method names are valid tokens")
+}
+
+fn check_cancelled(hooks: &TransferHooks) -> Result<()> {
+    if hooks.is_cancelled() {
+        anyhow::bail!("Transfer cancelled");
+    }
+    Ok(())
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.tag_name().name() == name)
+        .and_then(|child| child.text())
+}
+
+/// A property of a multistatus response, from its `propstat` with a 200
+/// status; properties the server doesn't have come back in a 404 one.
+fn prop<'a, 'input>(response: roxmltree::Node<'a, 'input>, name: &str) -> Option<roxmltree::Node<'a, 'input>> {
+    response.children()
+        .filter(|child| child.tag_name().name() == "propstat")
+        .filter(|propstat| child_text(*propstat, "status").is_some_and(|status| status.contains(" 200 ")))
+        .flat_map(|propstat| propstat.children().filter(|child| child.tag_name().name() == "prop"))
+        .flat_map(|prop| prop.children())
+        .find(|child| child.tag_name().name() == name)
+}
+
+/// An error for a failed response, with the message from the server's XML
+/// error body when there is one.
+async fn error_from(response: Response, what: &str) -> anyhow::Error {
+    let status = response.status();
+    if status == StatusCode::UNAUTHORIZED {
+        return anyhow::anyhow!("Failed to {}: the server rejected the user name or app password", what);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let message = roxmltree::Document::parse(&body)
+        .ok()
+        .and_then(|document| document.descendants()
+            .find(|node| node.tag_name().name() == "message")
+            .and_then(|node| node.text())
+            .map(str::to_string));
+    match message {
+        Some(message) => anyhow::anyhow!("Failed to {}: {} - {}", what, status, message),
+        None => anyhow::anyhow!("Failed to {}: {} - {}", what, status, body),
+    }
+}
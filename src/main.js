@@ -31,12 +31,37 @@ const lastSyncEl = document.getElementById('last-sync');
 const nextSyncEl = document.getElementById('next-sync');
 const stagingDirEl = document.getElementById('staging-dir');
 const driveFolderEl = document.getElementById('drive-folder');
+const sharedDriveEl = document.getElementById('shared-drive');
 const syncIntervalEl = document.getElementById('sync-interval');
+const syncModeEl = document.getElementById('sync-mode');
+const deletionPolicyEl = document.getElementById('deletion-policy');
+const uploadVersionsEl = document.getElementById('upload-versions');
+const encryptionModeEl = document.getElementById('encryption-mode');
+const encryptionPassphraseRow = document.getElementById('encryption-passphrase-row');
+const encryptionPassphraseEl = document.getElementById('encryption-passphrase');
+const setEncryptionPassphraseBtn = document.getElementById('set-encryption-passphrase');
+const encryptionKeyFileRow = document.getElementById('encryption-key-file-row');
+const encryptionKeyFileEl = document.getElementById('encryption-key-file');
+const selectEncryptionKeyFileBtn = document.getElementById('select-encryption-key-file');
+const generateEncryptionKeyFileBtn = document.getElementById('generate-encryption-key-file');
+// Retention inputs by the RetentionPolicy field they edit
+const retentionEls = {
+    keep_last: document.getElementById('keep-last'),
+    keep_hourly: document.getElementById('keep-hourly'),
+    keep_daily: document.getElementById('keep-daily'),
+    keep_weekly: document.getElementById('keep-weekly'),
+    keep_monthly: document.getElementById('keep-monthly'),
+    max_age_days: document.getElementById('max-age-days'),
+    max_total_size_mb: document.getElementById('max-total-size'),
+};
 const autoSyncEl = document.getElementById('auto-sync');
 const clientIdEl = document.getElementById('client-id');
 const clientSecretEl = document.getElementById('client-secret');
+// The secret is never sent back to the UI, only whether one is stored
+let clientSecretSaved = false;
 const fileListEl = document.getElementById('file-list');
 const syncNowBtn = document.getElementById('sync-now');
+const cancelSyncBtn = document.getElementById('cancel-sync');
 const authenticateBtn = document.getElementById('authenticate');
 const oauthCallbackSection = document.getElementById('oauth-callback-section');
 const authCodeEl = document.getElementById('auth-code');
@@ -45,6 +70,49 @@ const selectStagingDirBtn = document.getElementById('select-staging-dir');
 const addFileBtn = document.getElementById('add-file');
 const addFolderBtn = document.getElementById('add-folder');
 const logOutputEl = document.getElementById('log-output');
+const restoreBackupEl = document.getElementById('restore-backup');
+const refreshBackupsBtn = document.getElementById('refresh-backups');
+const restoreRevisionEl = document.getElementById('restore-revision');
+const keepRevisionBtn = document.getElementById('keep-revision');
+const deleteRevisionBtn = document.getElementById('delete-revision');
+const restoreTargetEl = document.getElementById('restore-target');
+const selectRestoreTargetBtn = document.getElementById('select-restore-target');
+const restoreConflictEl = document.getElementById('restore-conflict');
+const restorePassphraseEl = document.getElementById('restore-passphrase');
+const restoreNowBtn = document.getElementById('restore-now');
+const destinationEl = document.getElementById('destination');
+const removeDestinationBtn = document.getElementById('remove-destination');
+const resetDriveFolderBtn = document.getElementById('reset-drive-folder');
+const destinationNameEl = document.getElementById('destination-name');
+const destinationFolderEl = document.getElementById('destination-folder');
+const destinationClientIdEl = document.getElementById('destination-client-id');
+const destinationSharedDriveEl = document.getElementById('destination-shared-drive');
+const saveDestinationBtn = document.getElementById('save-destination');
+const storageTypeEl = document.getElementById('storage-type');
+const storagePathEl = document.getElementById('storage-path');
+const saveStorageBtn = document.getElementById('save-storage');
+const s3EndpointEl = document.getElementById('s3-endpoint');
+const s3BucketEl = document.getElementById('s3-bucket');
+const s3RegionEl = document.getElementById('s3-region');
+const s3PrefixEl = document.getElementById('s3-prefix');
+const s3AccessKeyIdEl = document.getElementById('s3-access-key-id');
+const s3PathStyleEl = document.getElementById('s3-path-style');
+const storageSecretEl = document.getElementById('storage-secret');
+const webdavUrlEl = document.getElementById('webdav-url');
+const webdavUsernameEl = document.getElementById('webdav-username');
+const sftpHostEl = document.getElementById('sftp-host');
+const sftpPortEl = document.getElementById('sftp-port');
+const sftpUsernameEl = document.getElementById('sftp-username');
+const sftpDirectoryEl = document.getElementById('sftp-directory');
+const sftpKeyFileEl = document.getElementById('sftp-key-file');
+const sftpKnownHostsEl = document.getElementById('sftp-known-hosts');
+// Credential store holding the active destination's storage secret, if one is saved
+let storageSecretStore = null;
+// What the storage secret is for each storage type that has one
+const STORAGE_SECRET_NAMES = { s3: 'Secret access key', webdav: 'App password', sftp: 'Key passphrase (if any)' };
+
+// Destinations as returned by get_destinations, default first
+let destinations = [];
 
 logDebug('Main.js loading - global APIs initialized');
 
@@ -66,6 +134,13 @@ async function init() {
     try {
         log('Initializing application data...', 'info');
         
+        // Load destinations before anything that depends on the active one
+        try {
+            await loadDestinations();
+        } catch (e) {
+            logError('Failed to load destinations', e);
+        }
+
         // Check auth status
         await updateAuthUI();
         
@@ -77,12 +152,25 @@ async function init() {
             if (config) {
                 if (config.staging_dir) stagingDirEl.value = config.staging_dir;
                 if (config.drive_folder) driveFolderEl.value = config.drive_folder;
+                if (config.shared_drive) sharedDriveEl.value = config.shared_drive;
                 if (config.sync_interval) syncIntervalEl.value = config.sync_interval;
+                if (config.sync_mode) syncModeEl.value = config.sync_mode;
+                if (config.deletion_policy) deletionPolicyEl.value = config.deletion_policy;
+                if (config.upload_versions !== undefined) uploadVersionsEl.checked = config.upload_versions;
+                if (config.encryption) encryptionModeEl.value = config.encryption;
+                if (config.encryption_key_file) encryptionKeyFileEl.value = config.encryption_key_file;
+                updateEncryptionRows();
+                if (config.retention) {
+                    for (const [field, el] of Object.entries(retentionEls)) {
+                        el.value = config.retention[field] ?? '';
+                    }
+                }
                 if (config.auto_sync !== undefined) autoSyncEl.checked = config.auto_sync;
                 if (config.client_id) clientIdEl.value = config.client_id;
-                if (config.client_secret) clientSecretEl.value = config.client_secret;
                 log('Configuration loaded', 'success');
             }
+
+            await loadCredentialStatus();
         } catch (e) {
             logError('Failed to load configuration', e);
             log(`Warning: Could not load saved settings.`, 'warning');
@@ -104,6 +192,17 @@ async function init() {
             logDebug('Initial status check failed (normal if first run)');
         }
 
+        // 5. Catch up with a sync that started before this window opened
+        try {
+            const progress = await invoke('get_sync_progress');
+            if (progress) {
+                setSyncRunning(true);
+                showSyncProgress(progress);
+            }
+        } catch (e) {
+            logDebug('Sync progress check failed', e);
+        }
+
         log('Application ready', 'success');
         logDebug('=== Application Initialization Completed ===');
     } catch (error) {
@@ -174,7 +273,7 @@ function setupEventListeners() {
             logDebug('File selection dialog returned', { selected });
             if (selected && selected.length > 0) {
                 for (const path of selected) {
-                    await invoke('add_tracked_path', { path });
+                    await invoke('add_tracked_path', { path, destination: destinationEl.value });
                 }
                 await loadTrackedFiles();
                 log(`Added ${selected.length} file(s)`, 'success');
@@ -197,7 +296,7 @@ function setupEventListeners() {
                 logDebug('Folder selection dialog returned', { selected });
                 if (selected && selected.length > 0) {
                     for (const path of selected) {
-                        await invoke('add_tracked_path', { path });
+                        await invoke('add_tracked_path', { path, destination: destinationEl.value });
                     }
                     await loadTrackedFiles();
                     log(`Added ${selected.length} folder(s)`, 'success');
@@ -213,17 +312,33 @@ function setupEventListeners() {
         await performSync();
     });
 
+    cancelSyncBtn.addEventListener('click', async () => {
+        cancelSyncBtn.disabled = true;
+        const cancelled = await invoke('cancel_sync');
+        if (cancelled) {
+            log('Cancelling sync...', 'info');
+        }
+    });
+
+    let authInProgress = false;
     authenticateBtn.addEventListener('click', async () => {
+        // While waiting for the browser the button cancels the sign-in
+        if (authInProgress) {
+            await invoke('cancel_oauth');
+            return;
+        }
+        const previousLabel = authenticateBtn.innerHTML;
         try {
-            if (!clientIdEl.value || !clientSecretEl.value) {
+            const hasClientId = clientIdEl.value || activeDestination()?.client_id;
+            if (!hasClientId || !(clientSecretEl.value || clientSecretSaved)) {
                 log('Please enter your Google Client ID and Secret first', 'warning');
                 return;
             }
-            authenticateBtn.disabled = true;
+            authInProgress = true;
             log('Starting Google Drive authentication...', 'info');
             
             // 1. Get the auth URL
-            const url = await invoke('get_auth_url');
+            const url = await invoke('get_auth_url', { destination: destinationEl.value });
             
             // 2. Open browser for OAuth
             await invoke('open_url', { url });
@@ -231,7 +346,9 @@ function setupEventListeners() {
             
             // 3. Automatically listen for the code (no more copy-paste!)
             log('Waiting for authentication from browser...', 'info');
+            authenticateBtn.innerHTML = 'Cancel Sign-in';
             const code = await invoke('listen_for_oauth_code');
+            authenticateBtn.disabled = true;
             
             // 4. Handle the received code
             log('Code received! Finalizing authentication...', 'info');
@@ -239,12 +356,17 @@ function setupEventListeners() {
             log('Google Drive authenticated successfully!', 'success');
             
             await updateAuthUI();
+            await loadDestinations();
             oauthCallbackSection.style.display = 'none';
         } catch (error) {
             log(`Authentication error: ${error}`, 'error');
             // Fallback: show the manual entry section if automatic fails
-            oauthCallbackSection.style.display = 'block';
+            if (!String(error).includes('cancelled')) {
+                oauthCallbackSection.style.display = 'block';
+            }
+            authenticateBtn.innerHTML = previousLabel;
         } finally {
+            authInProgress = false;
             authenticateBtn.disabled = false;
         }
     });
@@ -276,61 +398,439 @@ function setupEventListeners() {
     });
 
     clientSecretEl.addEventListener('change', async () => {
-        await invoke('set_google_client_secret', { secret: clientSecretEl.value });
+        if (!clientSecretEl.value) {
+            return;
+        }
+        await invoke('set_google_client_secret', { secret: clientSecretEl.value, destination: destinationEl.value });
+        clientSecretSaved = true;
+    });
+
+    destinationEl.addEventListener('change', onDestinationChanged);
+
+    saveDestinationBtn.addEventListener('click', async () => {
+        const name = destinationNameEl.value.trim();
+        if (!name) {
+            log('Please enter a destination name', 'warning');
+            return;
+        }
+        try {
+            await invoke('save_destination', {
+                name,
+                driveFolder: destinationFolderEl.value.trim() || null,
+                clientId: destinationClientIdEl.value.trim() || null,
+                sharedDrive: destinationSharedDriveEl.value.trim() || null
+            });
+            await loadDestinations();
+            destinationEl.value = name;
+            await onDestinationChanged();
+            await loadTrackedFiles();
+            log(`Destination ${name} saved`, 'success');
+        } catch (error) {
+            log(`Error saving destination: ${error}`, 'error');
+        }
+    });
+
+    resetDriveFolderBtn.addEventListener('click', async () => {
+        try {
+            await invoke('reset_drive_folder', { destination: destinationEl.value });
+            log('The Drive folder will be looked up by its path on the next sync', 'info');
+        } catch (error) {
+            log(`Error resetting Drive folder: ${error}`, 'error');
+        }
+    });
+
+    removeDestinationBtn.addEventListener('click', async () => {
+        const name = destinationEl.value;
+        if (!confirm(`Remove destination ${name} and sign out of its account? Backups already on Drive are kept.`)) {
+            return;
+        }
+        try {
+            await invoke('remove_destination', { name });
+            await loadDestinations();
+            await onDestinationChanged();
+            await loadTrackedFiles();
+            log(`Destination ${name} removed`, 'info');
+        } catch (error) {
+            log(`Error removing destination: ${error}`, 'error');
+        }
+    });
+
+    storageTypeEl.addEventListener('change', updateStorageFields);
+
+    saveStorageBtn.addEventListener('click', async () => {
+        const name = destinationEl.value;
+        try {
+            if (storageSecretEl.value) {
+                await invoke('set_storage_secret', { secret: storageSecretEl.value, destination: name });
+                storageSecretEl.value = '';
+            }
+            await invoke('set_destination_storage', { destination: name, storage: storageConfig() });
+            await loadDestinations();
+            await onDestinationChanged();
+            log(`Storage of destination ${name} saved; the next sync uploads everything to it`, 'success');
+        } catch (error) {
+            log(`Error saving storage: ${error}`, 'error');
+        }
     });
 
     driveFolderEl.addEventListener('change', async () => {
         await invoke('set_drive_folder', { folder: driveFolderEl.value });
     });
 
+    sharedDriveEl.addEventListener('change', async () => {
+        await invoke('set_shared_drive', { drive: sharedDriveEl.value.trim() });
+    });
+
     syncIntervalEl.addEventListener('change', async () => {
         await invoke('set_sync_interval', { interval: parseInt(syncIntervalEl.value) });
     });
 
+    syncModeEl.addEventListener('change', async () => {
+        await invoke('set_sync_mode', { mode: syncModeEl.value });
+    });
+
+    deletionPolicyEl.addEventListener('change', async () => {
+        await invoke('set_deletion_policy', { policy: deletionPolicyEl.value });
+    });
+
+    encryptionModeEl.addEventListener('change', async () => {
+        updateEncryptionRows();
+        await saveEncryption();
+    });
+
+    setEncryptionPassphraseBtn.addEventListener('click', async () => {
+        try {
+            await invoke('set_encryption_passphrase', { passphrase: encryptionPassphraseEl.value });
+            encryptionPassphraseEl.value = '';
+            log('Encryption passphrase set. Keep it safe: backups cannot be restored without it.', 'success');
+        } catch (error) {
+            log(`Error setting passphrase: ${error}`, 'error');
+        }
+    });
+
+    selectEncryptionKeyFileBtn.addEventListener('click', async () => {
+        const selected = await open({ multiple: false, directory: false });
+        if (selected) {
+            encryptionKeyFileEl.value = selected;
+            await saveEncryption();
+        }
+    });
+
+    generateEncryptionKeyFileBtn.addEventListener('click', async () => {
+        const dir = await open({ directory: true, multiple: false });
+        if (!dir) {
+            return;
+        }
+        const path = `${dir}/sync-bot.key`;
+        try {
+            await invoke('generate_encryption_key_file', { path });
+            encryptionKeyFileEl.value = path;
+            await saveEncryption();
+            log(`Generated key file ${path}. Keep a copy outside this machine.`, 'success');
+        } catch (error) {
+            log(`Error generating key file: ${error}`, 'error');
+        }
+    });
+
+    uploadVersionsEl.addEventListener('change', async () => {
+        await invoke('set_upload_versions', { enabled: uploadVersionsEl.checked });
+    });
+
+    for (const el of Object.values(retentionEls)) {
+        el.addEventListener('change', saveRetentionPolicy);
+    }
+
     autoSyncEl.addEventListener('change', async () => {
         await invoke('set_auto_sync', { enabled: autoSyncEl.checked });
     });
 
-    // Listen for scheduled sync events from backend
-    listen('scheduled-sync', async () => {
-        logDebug('Scheduled sync event received');
-        await performSync();
+    refreshBackupsBtn.addEventListener('click', async () => {
+        await loadBackups();
+        await loadRevisions();
     });
 
-    // Listen for tray sync events
-    listen('tray-sync-requested', async () => {
-        logDebug('Tray sync event received');
-        await performSync();
+    // Revisions belong to backup.zip, so picking one means restoring the current backup
+    restoreRevisionEl.addEventListener('change', () => {
+        if (restoreRevisionEl.value) {
+            restoreBackupEl.value = '';
+        }
+        updateRevisionButtons();
     });
-}
 
-async function performSync() {
-    try {
-        syncNowBtn.disabled = true;
+    restoreBackupEl.addEventListener('change', () => {
+        if (restoreBackupEl.value) {
+            restoreRevisionEl.value = '';
+        }
+        updateRevisionButtons();
+    });
+
+    keepRevisionBtn.addEventListener('click', async () => {
+        const option = restoreRevisionEl.selectedOptions[0];
+        const keepForever = option.dataset.keepForever !== 'true';
+        try {
+            await invoke('keep_backup_revision', {
+                destination: destinationEl.value,
+                revisionId: option.value,
+                keepForever
+            });
+            log(keepForever ? 'Revision will be kept forever' : 'Revision may expire again', 'success');
+            await loadRevisions();
+        } catch (error) {
+            log(`Error updating revision: ${error}`, 'error');
+        }
+    });
+
+    deleteRevisionBtn.addEventListener('click', async () => {
+        if (!confirm('Permanently delete this revision of backup.zip from Google Drive?')) {
+            return;
+        }
+        try {
+            await invoke('delete_backup_revision', {
+                destination: destinationEl.value,
+                revisionId: restoreRevisionEl.value
+            });
+            log('Revision deleted', 'success');
+            await loadRevisions();
+        } catch (error) {
+            log(`Error deleting revision: ${error}`, 'error');
+        }
+    });
+
+    selectRestoreTargetBtn.addEventListener('click', async () => {
+        const selected = await open({
+            directory: true,
+            multiple: false,
+            defaultPath: restoreTargetEl.value || undefined
+        });
+        // Cancelling the dialog goes back to restoring to the original locations
+        restoreTargetEl.value = selected || '';
+    });
+
+    restoreNowBtn.addEventListener('click', performRestore);
+
+    // Every sync (button, tray or scheduler) runs in the backend and reports
+    // through these events, so the UI shows each run exactly once
+    listen('sync-started', (event) => {
+        logDebug('Backend sync started', event.payload);
+        setSyncRunning(true);
         syncStatusEl.textContent = 'Syncing...';
         syncStatusEl.className = 'status-value syncing';
-        log('Starting sync...', 'info');
+        log(`Starting ${event.payload} sync...`, 'info');
+    });
 
-        const result = await invoke('sync_now');
-        log(`Sync completed: ${result.files_synced} files synced`, 'success');
-        syncStatusEl.textContent = 'Sync Complete';
-        syncStatusEl.className = 'status-value success';
+    listen('sync-progress', (event) => {
+        showSyncProgress(event.payload);
+    });
+
+    listen('sync-completed', async (event) => {
+        const summary = event.payload;
+        for (const report of summary.reports) {
+            const prefix = destinationPrefix(report.destination);
+            if (report.files_uploaded > 0) {
+                log(`${prefix}Sync completed: ${report.files_uploaded} uploaded, ${report.files_unchanged} unchanged`, 'success');
+            } else {
+                log(`${prefix}Sync completed: no changes to upload`, 'success');
+            }
+            if (report.files_deleted > 0) {
+                log(`${prefix}Removed ${report.files_deleted} deleted file(s) from Drive`, 'success');
+            }
+            for (const err of report.errors) {
+                log(`${prefix}${err}`, 'error');
+            }
+            for (const warning of report.warnings) {
+                log(`${prefix}${warning}`, 'error');
+            }
+        }
+        for (const failure of summary.failures) {
+            log(`${destinationPrefix(failure.destination)}Sync error: ${failure.error}`, 'error');
+        }
+        if (summary.failures.length > 0) {
+            syncStatusEl.textContent = 'Sync Partly Failed';
+            syncStatusEl.className = 'status-value error';
+        } else {
+            syncStatusEl.textContent = 'Sync Complete';
+            syncStatusEl.className = 'status-value success';
+        }
+        setSyncRunning(false);
         await updateStatus();
-    } catch (error) {
-        log(`Sync error: ${error}`, 'error');
+    });
+
+    listen('sync-cancelled', () => {
+        log('Sync cancelled', 'warning');
+        syncStatusEl.textContent = 'Sync Cancelled';
+        syncStatusEl.className = 'status-value';
+        setSyncRunning(false);
+    });
+
+    listen('sync-failed', (event) => {
+        log(`Sync error: ${event.payload}`, 'error');
         syncStatusEl.textContent = 'Sync Failed';
         syncStatusEl.className = 'status-value error';
+        setSyncRunning(false);
+    });
+}
+
+const SYNC_PHASE_LABELS = {
+    staging: 'Copying files',
+    archiving: 'Archiving',
+    zipping: 'Zipping',
+    checking_changes: 'Checking for changes',
+    uploading: 'Uploading',
+    deleting: 'Removing deleted files',
+    finished: 'Finishing',
+};
+
+function formatBytes(bytes) {
+    if (bytes < 1024) return `${bytes} B`;
+    if (bytes < 1024 * 1024) return `${(bytes / 1024).toFixed(1)} KB`;
+    if (bytes < 1024 * 1024 * 1024) return `${(bytes / (1024 * 1024)).toFixed(1)} MB`;
+    return `${(bytes / (1024 * 1024 * 1024)).toFixed(2)} GB`;
+}
+
+// Log and status prefix naming the destination, left out for the default one
+function destinationPrefix(destination) {
+    return destination && destination !== 'default' ? `[${destination}] ` : '';
+}
+
+function showSyncProgress(progress) {
+    let text = destinationPrefix(progress.destination) + (SYNC_PHASE_LABELS[progress.phase] || progress.phase);
+    if (progress.phase === 'uploading' && progress.upload_bytes_total > 0) {
+        text += ` ${formatBytes(progress.upload_bytes_sent)} / ${formatBytes(progress.upload_bytes_total)}`;
+    } else if (progress.files_total > 0) {
+        text += ` ${progress.files_done}/${progress.files_total}`;
+    }
+    syncStatusEl.textContent = text;
+    syncStatusEl.className = 'status-value syncing';
+    syncStatusEl.title = progress.current_path || '';
+}
+
+function setSyncRunning(running) {
+    syncNowBtn.disabled = running;
+    cancelSyncBtn.disabled = !running;
+}
+
+async function performSync() {
+    try {
+        // Progress and results are displayed by the sync-* event listeners
+        await invoke('sync_now');
+    } catch (error) {
+        logDebug('sync_now returned an error', error);
+    }
+}
+
+function updateEncryptionRows() {
+    encryptionPassphraseRow.style.display = encryptionModeEl.value === 'passphrase' ? 'block' : 'none';
+    encryptionKeyFileRow.style.display = encryptionModeEl.value === 'keyfile' ? 'block' : 'none';
+}
+
+async function saveEncryption() {
+    try {
+        await invoke('set_encryption', {
+            mode: encryptionModeEl.value,
+            keyFile: encryptionKeyFileEl.value || null
+        });
+    } catch (error) {
+        log(`Error saving encryption settings: ${error}`, 'error');
+    }
+}
+
+async function saveRetentionPolicy() {
+    const policy = {};
+    for (const [field, el] of Object.entries(retentionEls)) {
+        policy[field] = el.value === '' ? null : parseInt(el.value);
+    }
+    try {
+        await invoke('set_retention_policy', { policy });
+    } catch (error) {
+        log(`Error saving retention policy: ${error}`, 'error');
+    }
+}
+
+async function loadBackups() {
+    try {
+        const backups = await invoke('list_backups', { destination: destinationEl.value });
+        restoreBackupEl.innerHTML = '<option value="">Current backup (backup.zip)</option>';
+        for (const backup of backups) {
+            const option = document.createElement('option');
+            option.value = backup.id;
+            const modified = backup.modified_time ? new Date(backup.modified_time).toLocaleString() : '';
+            const size = backup.size ? ` - ${formatBytes(Number(backup.size))}` : '';
+            option.textContent = `${backup.name} (${modified}${size})`;
+            restoreBackupEl.appendChild(option);
+        }
+        log(`Found ${backups.length} backup(s) on Google Drive`, 'info');
+    } catch (error) {
+        log(`Error listing backups: ${error}`, 'error');
+    }
+}
+
+async function loadRevisions() {
+    try {
+        const revisions = await invoke('list_backup_revisions', { destination: destinationEl.value });
+        restoreRevisionEl.innerHTML = '<option value="">Latest</option>';
+        for (const revision of revisions) {
+            const option = document.createElement('option');
+            option.value = revision.id;
+            option.dataset.keepForever = revision.keep_forever;
+            const modified = revision.modified_time ? new Date(revision.modified_time).toLocaleString() : revision.id;
+            const size = revision.size ? ` - ${formatBytes(Number(revision.size))}` : '';
+            option.textContent = `${modified}${size}${revision.keep_forever ? ' (kept forever)' : ''}`;
+            restoreRevisionEl.appendChild(option);
+        }
+    } catch (error) {
+        log(`Error listing revisions: ${error}`, 'error');
+    }
+    updateRevisionButtons();
+}
+
+function updateRevisionButtons() {
+    const option = restoreRevisionEl.selectedOptions[0];
+    const selected = Boolean(option && option.value);
+    keepRevisionBtn.disabled = !selected;
+    deleteRevisionBtn.disabled = !selected;
+    keepRevisionBtn.textContent = selected && option.dataset.keepForever === 'true' ? 'Unpin' : 'Keep Forever';
+}
+
+async function performRestore() {
+    const target = restoreTargetEl.value || 'original locations';
+    if (!confirm(`Restore the selected backup to ${target}?`)) {
+        return;
+    }
+
+    restoreNowBtn.disabled = true;
+    log(`Restoring backup to ${target}...`, 'info');
+    try {
+        const report = await invoke('restore', {
+            destination: destinationEl.value,
+            backupId: restoreBackupEl.value || null,
+            revisionId: restoreRevisionEl.value || null,
+            targetDir: restoreTargetEl.value || null,
+            conflict: restoreConflictEl.value,
+            passphrase: restorePassphraseEl.value || null
+        });
+        log(`Restore completed: ${report.files_restored} restored (${report.files_renamed} renamed), ${report.files_skipped} skipped`, 'success');
+        for (const err of report.errors) {
+            log(err, 'error');
+        }
+    } catch (error) {
+        log(`${error}`, 'error');
     } finally {
-        syncNowBtn.disabled = false;
+        restoreNowBtn.disabled = false;
     }
 }
 
 async function updateAuthUI() {
     try {
-        const isAuthenticated = await invoke('check_auth_status');
+        const isAuthenticated = await invoke('check_auth_status', { destination: destinationEl.value });
         logDebug('Auth status check', { isAuthenticated });
         
-        if (isAuthenticated) {
+        if (!isAuthenticated) {
+            authenticateBtn.innerHTML = 'Authenticate';
+            authenticateBtn.classList.remove('btn-success');
+            authenticateBtn.classList.add('btn-secondary');
+            authenticateBtn.title = '';
+            document.getElementById('auth-reset')?.remove();
+        } else {
             authenticateBtn.innerHTML = 'Google Drive Connected';
             authenticateBtn.classList.remove('btn-secondary');
             authenticateBtn.classList.add('btn-success');
@@ -355,6 +855,11 @@ async function updateAuthUI() {
                 };
                 authenticateBtn.parentNode.appendChild(resetLink);
             }
+
+            const expiresAt = await invoke('get_session_expiry', { destination: destinationEl.value });
+            authenticateBtn.title = expiresAt
+                ? `Session valid until ${new Date(expiresAt).toLocaleString()}, renewed automatically`
+                : '';
         }
     } catch (error) {
         logError('Error checking auth status', error);
@@ -375,16 +880,35 @@ async function loadTrackedFiles() {
             const item = document.createElement('div');
             item.className = 'file-item';
             item.innerHTML = `
-                <span class="file-path">${file}</span>
-                <button class="file-remove" data-path="${file}">Remove</button>
+                <span class="file-path">${file.path}</span>
+                <button class="file-remove" data-path="${file.path}">Remove</button>
             `;
             fileListEl.appendChild(item);
 
+            // Only worth offering once there is somewhere else to send the path
+            if (destinations.length > 1) {
+                const select = document.createElement('select');
+                select.className = 'file-destination';
+                for (const destination of destinations) {
+                    select.add(new Option(destination.name, destination.name, false, destination.name === file.destination));
+                }
+                select.addEventListener('change', async () => {
+                    try {
+                        await invoke('set_tracked_path_destination', { path: file.path, destination: select.value });
+                        log(`${file.path} now syncs to ${select.value}`, 'info');
+                    } catch (error) {
+                        log(`Error moving path: ${error}`, 'error');
+                        select.value = file.destination;
+                    }
+                });
+                item.insertBefore(select, item.querySelector('.file-remove'));
+            }
+
             item.querySelector('.file-remove').addEventListener('click', async () => {
                 try {
-                    await invoke('remove_tracked_path', { path: file });
+                    await invoke('remove_tracked_path', { path: file.path });
                     await loadTrackedFiles();
-                    log(`Removed: ${file}`, 'info');
+                    log(`Removed: ${file.path}`, 'info');
                 } catch (error) {
                     log(`Error removing file: ${error}`, 'error');
                 }
@@ -395,6 +919,116 @@ async function loadTrackedFiles() {
     }
 }
 
+function activeDestination() {
+    return destinations.find((destination) => destination.name === destinationEl.value);
+}
+
+async function loadDestinations() {
+    destinations = await invoke('get_destinations');
+    const selected = destinationEl.value;
+    destinationEl.innerHTML = '';
+    for (const destination of destinations) {
+        const needsSignIn = destination.storage.type === 'drive' && !destination.authenticated;
+        const label = needsSignIn ? `${destination.name} (not signed in)` : destination.name;
+        destinationEl.add(new Option(label, destination.name));
+    }
+    destinationEl.value = destinations.some((destination) => destination.name === selected) ? selected : 'default';
+    removeDestinationBtn.disabled = destinationEl.value === 'default';
+}
+
+async function loadCredentialStatus() {
+    const credentials = await invoke('get_credential_status', { destination: destinationEl.value });
+    clientSecretSaved = credentials.has_client_secret;
+    clientSecretEl.placeholder = clientSecretSaved ? `Saved in ${credentials.store}` : 'Enter Client Secret';
+    storageSecretStore = credentials.has_storage_secret ? credentials.store : null;
+    updateStorageFields();
+}
+
+async function onDestinationChanged() {
+    removeDestinationBtn.disabled = destinationEl.value === 'default';
+    const destination = activeDestination();
+    destinationNameEl.value = destination && destination.name !== 'default' ? destination.name : '';
+    destinationFolderEl.value = destination && destination.name !== 'default' ? destination.drive_folder || '' : '';
+    destinationClientIdEl.value = destination?.client_id || '';
+    destinationSharedDriveEl.value = destination && destination.name !== 'default' ? destination.shared_drive || '' : '';
+    const storage = destination?.storage || { type: 'drive' };
+    storageTypeEl.value = storage.type;
+    storagePathEl.value = storage.type === 'filesystem' ? storage.path : '';
+    const s3 = storage.type === 's3' ? storage : {};
+    s3EndpointEl.value = s3.endpoint || '';
+    s3BucketEl.value = s3.bucket || '';
+    s3RegionEl.value = s3.region || '';
+    s3PrefixEl.value = s3.prefix || '';
+    s3AccessKeyIdEl.value = s3.access_key_id || '';
+    s3PathStyleEl.checked = s3.path_style ?? true;
+    const webdav = storage.type === 'webdav' ? storage : {};
+    webdavUrlEl.value = webdav.url || '';
+    webdavUsernameEl.value = webdav.username || '';
+    const sftp = storage.type === 'sftp' ? storage : {};
+    sftpHostEl.value = sftp.host || '';
+    sftpPortEl.value = sftp.port || '';
+    sftpUsernameEl.value = sftp.username || '';
+    sftpDirectoryEl.value = sftp.directory || '';
+    sftpKeyFileEl.value = sftp.key_file || '';
+    sftpKnownHostsEl.value = sftp.known_hosts || '';
+    storageSecretEl.value = '';
+    updateStorageFields();
+    // Backups and revisions listed so far belong to the previous destination
+    restoreBackupEl.innerHTML = '<option value="">Current backup (backup.zip)</option>';
+    restoreRevisionEl.innerHTML = '<option value="">Latest</option>';
+    updateRevisionButtons();
+    await updateAuthUI();
+    try {
+        await loadCredentialStatus();
+    } catch (error) {
+        logError('Failed to load credential status', error);
+    }
+}
+
+// Storage settings the selected type needs, as `set_destination_storage` expects them
+function storageConfig() {
+    switch (storageTypeEl.value) {
+        case 'filesystem':
+            return { type: 'filesystem', path: storagePathEl.value.trim() };
+        case 's3':
+            return {
+                type: 's3',
+                endpoint: s3EndpointEl.value.trim(),
+                bucket: s3BucketEl.value.trim(),
+                region: s3RegionEl.value.trim() || null,
+                prefix: s3PrefixEl.value.trim() || null,
+                path_style: s3PathStyleEl.checked,
+                access_key_id: s3AccessKeyIdEl.value.trim()
+            };
+        case 'webdav':
+            return {
+                type: 'webdav',
+                url: webdavUrlEl.value.trim(),
+                username: webdavUsernameEl.value.trim()
+            };
+        case 'sftp':
+            return {
+                type: 'sftp',
+                host: sftpHostEl.value.trim(),
+                port: sftpPortEl.value ? parseInt(sftpPortEl.value, 10) : null,
+                username: sftpUsernameEl.value.trim(),
+                key_file: sftpKeyFileEl.value.trim(),
+                known_hosts: sftpKnownHostsEl.value.trim() || null,
+                directory: sftpDirectoryEl.value.trim()
+            };
+        default:
+            return { type: 'drive' };
+    }
+}
+
+function updateStorageFields() {
+    for (const field of document.querySelectorAll('.storage-field')) {
+        field.style.display = field.dataset.storage.split(' ').includes(storageTypeEl.value) ? '' : 'none';
+    }
+    const secretName = STORAGE_SECRET_NAMES[storageTypeEl.value] || 'Secret';
+    storageSecretEl.placeholder = storageSecretStore ? `${secretName} saved in ${storageSecretStore}` : secretName;
+}
+
 async function updateStatus() {
     try {
         const status = await invoke('get_sync_status');